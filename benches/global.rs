@@ -0,0 +1,92 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Measures the overhead of allocating through [`Global`].
+//!
+//! Run with `cargo bench` to measure the fallback API, and with
+//! `cargo +nightly bench --features allocator_api` to measure the real one.
+
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+use allocator_fallback::{Allocator, Global};
+use std::alloc::Layout;
+use std::ptr;
+use std::time::Instant;
+
+const ITERATIONS: u32 = 1_000_000;
+
+/// Prevents the optimizer from making assumptions about `x`.
+/// [`std::hint::black_box`] is newer than this crate’s minimum supported
+/// Rust version.
+fn black_box<T: Copy>(x: T) -> T {
+    // SAFETY: `&x` is valid for reads.
+    unsafe { ptr::read_volatile(&x) }
+}
+
+fn bench<F: FnMut()>(name: &str, mut f: F) {
+    for _ in 0..ITERATIONS / 10 {
+        f();
+    }
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    let ns = start.elapsed().as_nanos() as f64 / f64::from(ITERATIONS);
+    println!("{:<24} {:>8.1} ns/iter", name, ns);
+}
+
+fn main() {
+    let small = Layout::new::<[u8; 64]>();
+    let large = Layout::new::<[u8; 4096]>();
+
+    bench("allocate/deallocate", || {
+        let ptr = Global.allocate(black_box(small)).unwrap();
+        unsafe { Global.deallocate(black_box(ptr).cast(), small) };
+    });
+
+    bench("allocate bytes", || {
+        for size in 1..=16 {
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            let ptr = Global.allocate(black_box(layout)).unwrap();
+            unsafe { Global.deallocate(black_box(ptr).cast(), layout) };
+        }
+    });
+
+    bench("allocate_zeroed", || {
+        let ptr = Global.allocate_zeroed(black_box(large)).unwrap();
+        unsafe { Global.deallocate(black_box(ptr).cast(), large) };
+    });
+
+    bench("grow", || {
+        let ptr = Global.allocate(black_box(small)).unwrap();
+        unsafe {
+            let ptr =
+                Global.grow(ptr.cast(), small, black_box(large)).unwrap();
+            Global.deallocate(black_box(ptr).cast(), large);
+        }
+    });
+
+    bench("shrink", || {
+        let ptr = Global.allocate(black_box(large)).unwrap();
+        unsafe {
+            let ptr =
+                Global.shrink(ptr.cast(), large, black_box(small)).unwrap();
+            Global.deallocate(black_box(ptr).cast(), small);
+        }
+    });
+}
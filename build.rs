@@ -38,10 +38,15 @@ fn compile<P: AsRef<Path>>(path: P) -> io::Result<bool> {
 }
 
 fn main() -> io::Result<()> {
+    println!("cargo:rustc-check-cfg=cfg(has_allocator_api)");
+    println!("cargo:rustc-check-cfg=cfg(has_core_error)");
     env::set_current_dir(PathBuf::from_iter(&["misc", "feature-test"]))?;
     if compile("allocator_api.rs")? {
         println!("cargo:rustc-cfg=has_allocator_api");
     }
+    if compile("core_error.rs")? {
+        println!("cargo:rustc-cfg=has_core_error");
+    }
     println!("cargo:rerun-if-changed=build.rs");
     Ok(())
 }
@@ -0,0 +1,279 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, OwningAllocator};
+use alloc::alloc::Layout;
+use core::fmt::{self, Debug, Formatter};
+use core::mem::size_of;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The number of histogram buckets. Bucket `k` counts requests with sizes in
+/// `(2^(k-1), 2^k]` (bucket 0 also counts zero-sized requests).
+pub(crate) const BUCKETS: usize = size_of::<usize>() * 8;
+
+/// Returns the histogram bucket for a request of `size` bytes.
+pub(crate) fn bucket(size: usize) -> usize {
+    BUCKETS - size.saturating_sub(1).leading_zeros() as usize
+}
+
+/// An allocator that sends small requests to `S` and large requests to `L`,
+/// adjusting the size threshold between them as the distribution of request
+/// sizes changes.
+///
+/// Request sizes are counted in a histogram with power-of-two buckets. Every
+/// [`interval`](Self::set_interval) allocations, the threshold is set to the
+/// upper bound of the bucket containing the
+/// [`percentile`](Self::set_percentile)th percentile of recent requests, and
+/// the histogram’s counts are halved so that older requests gradually stop
+/// influencing it. Requests no larger than the threshold are sent to `S`.
+///
+/// Because the threshold changes, the size of a block doesn’t say which
+/// allocator it came from, so deallocations are routed using
+/// [`S::owns`](OwningAllocator::owns), like [`Chain`](crate::Chain).
+///
+/// The histogram is updated with relaxed atomic operations, so when
+/// allocating from multiple threads, the threshold is computed from an
+/// approximate snapshot. This affects only performance, not correctness.
+pub struct Adaptive<S, L> {
+    small: S,
+    large: L,
+    threshold: AtomicUsize,
+    percentile: usize,
+    interval: usize,
+    /// The number of allocations since the allocator was created.
+    requests: AtomicUsize,
+    counts: [AtomicUsize; BUCKETS],
+}
+
+impl<S, L> Adaptive<S, L> {
+    /// Creates a new adaptive allocator, initially sending requests of up to
+    /// `threshold` bytes to `small`, and larger requests to `large`.
+    ///
+    /// By default, the threshold is recomputed every 1024 allocations, at
+    /// the 90th percentile.
+    pub fn new(small: S, large: L, threshold: usize) -> Self {
+        // Used only to initialize the array, as atomics aren’t `Copy`.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            small,
+            large,
+            threshold: AtomicUsize::new(threshold),
+            percentile: 90,
+            interval: 1024,
+            requests: AtomicUsize::new(0),
+            counts: [ZERO; BUCKETS],
+        }
+    }
+
+    /// Sets the percentile of request sizes the threshold is placed at.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `percentile` is 0 or greater than 100.
+    pub fn set_percentile(&mut self, percentile: u8) {
+        assert!(
+            (1..=100).contains(&percentile),
+            "percentile must be between 1 and 100",
+        );
+        self.percentile = percentile.into();
+    }
+
+    /// Sets the number of allocations between recomputations of the
+    /// threshold.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `interval` is 0.
+    pub fn set_interval(&mut self, interval: usize) {
+        assert!(interval > 0, "interval must be non-zero");
+        self.interval = interval;
+    }
+
+    /// Returns the current threshold: requests of up to this many bytes are
+    /// sent to `S`.
+    pub fn threshold(&self) -> usize {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    /// Returns the allocator for small requests.
+    pub fn small(&self) -> &S {
+        &self.small
+    }
+
+    /// Returns the allocator for large requests.
+    pub fn large(&self) -> &L {
+        &self.large
+    }
+
+    /// Records a request, recomputing the threshold if an interval has
+    /// elapsed.
+    fn record(&self, layout: Layout) {
+        self.counts[bucket(layout.size())].fetch_add(1, Ordering::Relaxed);
+        let n = self.requests.fetch_add(1, Ordering::Relaxed);
+        if n % self.interval == self.interval - 1 {
+            self.recompute();
+        }
+    }
+
+    fn recompute(&self) {
+        let mut counts = [0; BUCKETS];
+        for (count, atomic) in counts.iter_mut().zip(&self.counts) {
+            *count = atomic.load(Ordering::Relaxed);
+        }
+        let total: usize = counts.iter().fold(0, |a, &b| a.saturating_add(b));
+        let target = (total as u128 * self.percentile as u128 + 99) / 100;
+        let mut seen = 0_u128;
+        let k = counts
+            .iter()
+            .position(|&count| {
+                seen += count as u128;
+                seen >= target
+            })
+            .unwrap_or(BUCKETS - 1);
+        self.threshold.store(1 << k, Ordering::Relaxed);
+        // Subtract rather than store, so that concurrent increments are kept.
+        for (&count, atomic) in counts.iter().zip(&self.counts) {
+            atomic.fetch_sub(count / 2, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<S: Debug, L: Debug> Debug for Adaptive<S, L> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Adaptive")
+            .field("small", &self.small)
+            .field("large", &self.large)
+            .field("threshold", &self.threshold())
+            .finish()
+    }
+}
+
+// SAFETY: Every block is allocated by exactly one of `S` and `L`, and
+// `OwningAllocator` guarantees that each block is deallocated by the
+// allocator that allocated it.
+unsafe impl<S, L> Allocator for Adaptive<S, L>
+where
+    S: OwningAllocator,
+    L: Allocator,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(layout);
+        if layout.size() <= self.threshold() {
+            self.small.allocate(layout)
+        } else {
+            self.large.allocate(layout)
+        }
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(layout);
+        if layout.size() <= self.threshold() {
+            self.small.allocate_zeroed(layout)
+        } else {
+            self.large.allocate_zeroed(layout)
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller. `OwningAllocator::owns` tells us which
+        // allocator allocated the block.
+        unsafe {
+            if self.small.owns(ptr, layout) {
+                self.small.deallocate(ptr, layout);
+            } else {
+                self.large.deallocate(ptr, layout);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Bump, Global, TrackingAllocator};
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn buckets() {
+        assert_eq!(bucket(0), 0);
+        assert_eq!(bucket(1), 0);
+        assert_eq!(bucket(2), 1);
+        assert_eq!(bucket(16), 4);
+        assert_eq!(bucket(17), 5);
+        assert_eq!(bucket(isize::MAX as usize), BUCKETS - 1);
+    }
+
+    /// Makes 19 small requests for every large one.
+    fn bimodal<A: Allocator>(alloc: &A, rounds: usize, small: usize) {
+        let small = Layout::from_size_align(small, 1).unwrap();
+        let large = Layout::from_size_align(4096, 1).unwrap();
+        for i in 0..rounds * 20 {
+            let layout = if i % 20 == 0 {
+                large
+            } else {
+                small
+            };
+            let ptr = alloc.allocate(layout).unwrap();
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+        }
+    }
+
+    #[test]
+    fn separates_modes() {
+        let mut buf = alloc::vec![MaybeUninit::uninit(); 1 << 16];
+        let bump = Bump::new(&mut buf);
+        let large = TrackingAllocator::new(Global);
+        let mut alloc = Adaptive::new(&bump, &large, 8192);
+        alloc.set_interval(100);
+
+        // Initially, everything goes to the bump allocator.
+        bimodal(&alloc, 4, 16);
+        assert_eq!(large.stats().allocations, 0);
+        assert_eq!(alloc.threshold(), 8192);
+
+        bimodal(&alloc, 1, 16);
+        assert_eq!(alloc.threshold(), 16);
+        let used = bump.used();
+        bimodal(&alloc, 5, 16);
+        assert_eq!(large.stats().allocations, 5);
+        assert_eq!(large.stats().current, 0);
+        // Each small block is freed right away, so the bump allocator
+        // reclaims it.
+        assert_eq!(bump.used(), used);
+    }
+
+    #[test]
+    fn follows_shift() {
+        let mut buf = alloc::vec![MaybeUninit::uninit(); 1 << 17];
+        let bump = Bump::new(&mut buf);
+        let mut alloc = Adaptive::new(&bump, Global, 8192);
+        alloc.set_interval(100);
+        bimodal(&alloc, 5, 16);
+        assert_eq!(alloc.threshold(), 16);
+
+        // As small requests get larger, the threshold follows, since old
+        // requests are gradually forgotten.
+        bimodal(&alloc, 10, 256);
+        assert_eq!(alloc.threshold(), 256);
+    }
+}
@@ -0,0 +1,64 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Returns `ptr`, after checking that it’s aligned for `layout`.
+///
+/// Allocators in this crate pass the blocks they return through this
+/// function, so that a misaligned block is caught where it’s created rather
+/// than causing undefined behavior later. The check is a `debug_assert!`, so
+/// it is compiled out in release builds.
+#[inline]
+#[track_caller]
+pub(crate) fn debug_aligned(
+    ptr: NonNull<[u8]>,
+    layout: Layout,
+) -> NonNull<[u8]> {
+    debug_assert!(
+        ptr.as_ptr() as *mut u8 as usize % layout.align() == 0,
+        "allocator returned a block that isn’t aligned to {} bytes",
+        layout.align(),
+    );
+    ptr
+}
+
+#[cfg(all(test, debug_assertions))]
+mod tests {
+    use super::*;
+    use core::ptr;
+
+    fn block(addr: usize) -> NonNull<[u8]> {
+        let ptr = ptr::slice_from_raw_parts_mut(addr as *mut u8, 8);
+        NonNull::new(ptr).unwrap()
+    }
+
+    #[test]
+    fn aligned() {
+        let layout = Layout::from_size_align(8, 16).unwrap();
+        assert_eq!(debug_aligned(block(64), layout), block(64));
+    }
+
+    #[test]
+    #[should_panic(expected = "isn’t aligned to 16 bytes")]
+    fn misaligned() {
+        let layout = Layout::from_size_align(8, 16).unwrap();
+        debug_aligned(block(72), layout);
+    }
+}
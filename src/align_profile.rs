@@ -0,0 +1,155 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use crate::adaptive::{BUCKETS, bucket};
+use alloc::alloc::Layout;
+use core::fmt::{self, Debug, Formatter};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// An allocator that forwards to `A`, recording the largest alignment
+/// requested for each size class.
+///
+/// This shows whether a workload ever needs more alignment than is natural
+/// for its sizes, which helps decide whether an allocator specialized for
+/// it has to support over-aligned requests. Size classes are powers of two:
+/// a request of `size` bytes falls in the class of the smallest power of two
+/// at least as large. Growing or shrinking a block records its new layout.
+pub struct AlignmentProfiler<A> {
+    inner: A,
+    max_align: [AtomicUsize; BUCKETS],
+}
+
+impl<A> AlignmentProfiler<A> {
+    /// Creates a new [`AlignmentProfiler`] that forwards to `inner`.
+    pub fn new(inner: A) -> Self {
+        // Used only to initialize the array, as atomics aren’t `Copy`.
+        #[allow(clippy::declare_interior_mutable_const)]
+        const ZERO: AtomicUsize = AtomicUsize::new(0);
+        Self {
+            inner,
+            max_align: [ZERO; BUCKETS],
+        }
+    }
+
+    /// Returns the largest alignment requested for sizes in the same class
+    /// as `size`, or 0 if there haven’t been any such requests.
+    pub fn max_align_for_size(&self, size: usize) -> usize {
+        self.max_align
+            .get(bucket(size))
+            .map_or(0, |max| max.load(Ordering::Relaxed))
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn record(&self, layout: Layout) {
+        self.max_align[bucket(layout.size())]
+            .fetch_max(layout.align(), Ordering::Relaxed);
+    }
+}
+
+impl<A: Debug> Debug for AlignmentProfiler<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AlignmentProfiler")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for AlignmentProfiler<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(layout);
+        self.inner.allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(layout);
+        self.inner.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(new_layout);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(new_layout);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(new_layout);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+
+    let alloc = AlignmentProfiler::new(Global);
+    let layouts = [
+        Layout::new::<u8>(),
+        Layout::new::<[u32; 3]>(),
+        Layout::from_size_align(16, 64).unwrap(),
+        Layout::from_size_align(100, 8).unwrap(),
+        Layout::from_size_align(128, 4).unwrap(),
+    ];
+    for &layout in &layouts {
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+    }
+    assert_eq!(alloc.max_align_for_size(1), 1);
+    // 12 and 16 bytes are both in the class of 16.
+    assert_eq!(alloc.max_align_for_size(12), 64);
+    assert_eq!(alloc.max_align_for_size(16), 64);
+    assert_eq!(alloc.max_align_for_size(65), 8);
+    assert_eq!(alloc.max_align_for_size(4), 0);
+    assert_eq!(alloc.max_align_for_size(usize::MAX), 0);
+}
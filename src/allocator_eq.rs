@@ -0,0 +1,60 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{Allocator, Global};
+
+/// An [`Allocator`] that can determine whether another instance of the same
+/// type is interchangeable with it.
+///
+/// This allows data structures to check that two values were allocated by
+/// the same allocator before merging them.
+///
+/// Stateful allocators in this crate implement this trait (where they can)
+/// rather than [`PartialEq`]: comparing their state field by field wouldn’t
+/// say whether they are interchangeable, as two arenas over different
+/// buffers can look identical. Stateless allocators like [`Global`] also
+/// implement [`Eq`], [`Ord`], and [`Hash`](core::hash::Hash).
+///
+/// # Safety
+///
+/// If [`Self::same_allocator`] returns true, memory allocated by either
+/// allocator must be able to be deallocated, grown, or shrunk by the other.
+/// Returning false for allocators that are actually interchangeable is
+/// allowed.
+pub unsafe trait AllocatorEq: Allocator {
+    /// Returns whether `self` and `other` are interchangeable.
+    fn same_allocator(&self, other: &Self) -> bool;
+}
+
+// SAFETY: This impl simply forwards to `A`.
+unsafe impl<A> AllocatorEq for &A
+where
+    A: AllocatorEq + ?Sized,
+{
+    fn same_allocator(&self, other: &Self) -> bool {
+        A::same_allocator(*self, *other)
+    }
+}
+
+// SAFETY: `Global` is stateless; all instances forward to the global
+// allocator.
+unsafe impl AllocatorEq for Global {
+    fn same_allocator(&self, _other: &Self) -> bool {
+        true
+    }
+}
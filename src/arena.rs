@@ -0,0 +1,248 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{Allocator, Global};
+use alloc::alloc::{Layout, handle_alloc_error};
+use core::cell::Cell;
+use core::fmt::{self, Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem;
+use core::ptr::{self, NonNull};
+
+/// The number of elements in the first chunk of a [`TypedArena`].
+const INITIAL_CAPACITY: usize = 8;
+
+/// Stored at the start of each chunk.
+struct ChunkHeader {
+    prev: Option<NonNull<ChunkHeader>>,
+    capacity: usize,
+}
+
+/// An arena that allocates values of type `T` from a backing allocator `A`,
+/// and drops them all when the arena itself is dropped.
+///
+/// Values are stored in chunks allocated from `A`, each twice as large as
+/// the last. If `T` doesn’t need to be dropped, dropping the arena simply
+/// deallocates the chunks.
+pub struct TypedArena<T, A: Allocator = Global> {
+    alloc: A,
+    /// The most recently allocated chunk.
+    head: Cell<Option<NonNull<ChunkHeader>>>,
+    /// The number of values in the most recently allocated chunk. All other
+    /// chunks are full.
+    len: Cell<usize>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> TypedArena<T> {
+    /// Creates a new arena that allocates from [`Global`].
+    pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+}
+
+impl<T> Default for TypedArena<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A: Allocator> TypedArena<T, A> {
+    /// Creates a new arena that allocates from `alloc`.
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            alloc,
+            head: Cell::new(None),
+            len: Cell::new(0),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the layout of a chunk with the given capacity, along with the
+    /// offset of its elements.
+    fn chunk_layout(capacity: usize) -> (Layout, usize) {
+        Layout::array::<T>(capacity)
+            .ok()
+            .and_then(|array| Layout::new::<ChunkHeader>().extend(array).ok())
+            .expect("capacity overflow")
+    }
+
+    /// Returns a pointer to the elements of `chunk`.
+    ///
+    /// # Safety
+    ///
+    /// `chunk` must be a chunk allocated by this arena.
+    unsafe fn elements(chunk: NonNull<ChunkHeader>) -> *mut T {
+        // SAFETY: Checked by caller.
+        let capacity = unsafe { (*chunk.as_ptr()).capacity };
+        let offset = Self::chunk_layout(capacity).1;
+        // SAFETY: `offset` is within the chunk.
+        unsafe { (chunk.as_ptr() as *mut u8).add(offset) as *mut T }
+    }
+
+    /// Allocates a new chunk.
+    fn grow(&self) -> NonNull<ChunkHeader> {
+        let capacity = if mem::size_of::<T>() == 0 {
+            usize::MAX
+        } else {
+            match self.head.get() {
+                None => INITIAL_CAPACITY,
+                // SAFETY: `self.head` is always a chunk allocated by this
+                // arena.
+                Some(chunk) => unsafe { (*chunk.as_ptr()).capacity }
+                    .checked_mul(2)
+                    .expect("capacity overflow"),
+            }
+        };
+        let layout = Self::chunk_layout(capacity).0;
+        let chunk = match self.alloc.allocate(layout) {
+            Ok(chunk) => chunk.cast::<ChunkHeader>(),
+            Err(_) => handle_alloc_error(layout),
+        };
+        // SAFETY: `chunk` is valid for `layout`, which begins with a
+        // `ChunkHeader`.
+        unsafe {
+            chunk.as_ptr().write(ChunkHeader {
+                prev: self.head.get(),
+                capacity,
+            });
+        }
+        self.head.set(Some(chunk));
+        self.len.set(0);
+        chunk
+    }
+
+    /// Moves `value` into the arena, returning a reference to it.
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc(&self, value: T) -> &mut T {
+        let chunk = match self.head.get() {
+            // SAFETY: `self.head` is always a chunk allocated by this arena.
+            Some(chunk)
+                if self.len.get() < unsafe { (*chunk.as_ptr()).capacity } =>
+            {
+                chunk
+            }
+            _ => self.grow(),
+        };
+        let len = self.len.get();
+        // SAFETY: `len` is less than the chunk’s capacity. Each element is
+        // initialized and borrowed at most once.
+        unsafe {
+            let ptr = Self::elements(chunk).add(len);
+            ptr.write(value);
+            self.len.set(len + 1);
+            &mut *ptr
+        }
+    }
+}
+
+impl<T, A: Allocator> Drop for TypedArena<T, A> {
+    fn drop(&mut self) {
+        let mut len = self.len.get();
+        let mut next = self.head.get();
+        while let Some(chunk) = next {
+            // SAFETY: `chunk` was allocated by this arena, and the first
+            // `len` elements are initialized (all chunks other than the head
+            // are full).
+            unsafe {
+                let header = chunk.as_ptr().read();
+                if mem::needs_drop::<T>() {
+                    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                        Self::elements(chunk),
+                        len,
+                    ));
+                }
+                let layout = Self::chunk_layout(header.capacity).0;
+                self.alloc.deallocate(chunk.cast(), layout);
+                next = header.prev;
+                len = header.prev.map_or(0, |prev| (*prev.as_ptr()).capacity);
+            }
+        }
+    }
+}
+
+impl<T, A: Allocator + Debug> Debug for TypedArena<T, A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedArena").field("alloc", &self.alloc).finish()
+    }
+}
+
+// SAFETY: The arena owns its values and chunks, so it can be sent to another
+// thread if they can.
+unsafe impl<T: Send, A: Allocator + Send> Send for TypedArena<T, A> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bump;
+    use core::mem::MaybeUninit;
+
+    struct DropCounter<'a>(&'a Cell<usize>, usize);
+
+    impl Drop for DropCounter<'_> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn drop_count() {
+        let count = Cell::new(0);
+        let arena = TypedArena::new();
+        let values: alloc::vec::Vec<_> =
+            (0..100).map(|i| &*arena.alloc(DropCounter(&count, i))).collect();
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(value.1, i);
+        }
+        assert_eq!(count.get(), 0);
+        drop(arena);
+        assert_eq!(count.get(), 100);
+    }
+
+    #[test]
+    fn bump() {
+        let mut buf = [MaybeUninit::uninit(); 1024];
+        let bump = Bump::new(&mut buf);
+        let arena = TypedArena::new_in(&bump);
+        let a = arena.alloc(1_u64);
+        let b = arena.alloc(2_u64);
+        *a += 10;
+        assert_eq!((*a, *b), (11, 2));
+        for i in 0..20 {
+            assert_eq!(*arena.alloc(i), i);
+        }
+    }
+
+    #[test]
+    fn zero_sized() {
+        use core::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        struct Zst;
+        impl Drop for Zst {
+            fn drop(&mut self) {
+                COUNT.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        let arena = TypedArena::new();
+        for _ in 0..50 {
+            arena.alloc(Zst);
+        }
+        drop(arena);
+        assert_eq!(COUNT.load(Ordering::Relaxed), 50);
+    }
+}
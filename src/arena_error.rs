@@ -0,0 +1,137 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::AllocError;
+use core::fmt::{self, Display, Formatter};
+
+/// The reason an arena allocator couldn’t satisfy a request.
+///
+/// [`Allocator`](crate::Allocator) methods can only return [`AllocError`],
+/// but arena types like [`Bump`](crate::Bump) also provide inherent methods
+/// that return this type, so callers can tell a full arena (which can be
+/// reset and retried) from a request that could never succeed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArenaError {
+    /// The arena doesn’t have enough space left.
+    Exhausted {
+        /// The number of bytes needed, including padding for alignment.
+        requested: usize,
+        /// The number of bytes left in the arena.
+        available: usize,
+    },
+    /// The size of the request, including padding, overflows `usize`.
+    ///
+    /// [`Layout`](alloc::alloc::Layout) limits sizes to `isize::MAX`, so
+    /// this is only possible for arenas that add their own overhead.
+    LayoutOverflow,
+    /// The arena is frozen, so it can’t allocate until it’s thawed.
+    Frozen,
+}
+
+impl Display for ArenaError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Exhausted {
+                requested,
+                available,
+            } => write!(
+                f,
+                "arena exhausted: requested {} bytes, but only {} are \
+                 available",
+                requested, available,
+            ),
+            Self::LayoutOverflow => write!(f, "allocation size overflow"),
+            Self::Frozen => write!(f, "arena is frozen"),
+        }
+    }
+}
+
+impl From<ArenaError> for AllocError {
+    fn from(_: ArenaError) -> Self {
+        AllocError
+    }
+}
+
+// See the corresponding impls for `AllocError`.
+#[cfg(has_core_error)]
+impl core::error::Error for ArenaError {}
+
+#[cfg(all(feature = "std", not(has_core_error)))]
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+impl std::error::Error for ArenaError {}
+
+/// The error returned when a buffer is too small for an arena allocator.
+///
+/// This is returned by constructors like
+/// [`Bump::try_new`](crate::Bump::try_new)
+/// and [`FreeListAllocator::try_new`](crate::FreeListAllocator::try_new),
+/// which reject buffers the allocator couldn’t serve any allocation from.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TooSmall {
+    /// The length of the buffer.
+    pub len: usize,
+    /// The minimum length a buffer at the same address would need.
+    pub required: usize,
+}
+
+impl Display for TooSmall {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "buffer of {} bytes is too small; at least {} are needed",
+            self.len, self.required,
+        )
+    }
+}
+
+#[cfg(has_core_error)]
+impl core::error::Error for TooSmall {}
+
+#[cfg(all(feature = "std", not(has_core_error)))]
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+impl std::error::Error for TooSmall {}
+
+#[test]
+fn test() {
+    use alloc::string::ToString;
+
+    let exhausted = ArenaError::Exhausted {
+        requested: 40,
+        available: 24,
+    };
+    assert_eq!(
+        exhausted.to_string(),
+        "arena exhausted: requested 40 bytes, but only 24 are available",
+    );
+    assert_eq!(
+        ArenaError::LayoutOverflow.to_string(),
+        "allocation size overflow",
+    );
+    assert_eq!(ArenaError::Frozen.to_string(), "arena is frozen");
+    assert_eq!(AllocError::from(exhausted), AllocError);
+    assert_eq!(AllocError::from(ArenaError::LayoutOverflow), AllocError);
+    assert_eq!(
+        TooSmall {
+            len: 1,
+            required: 16,
+        }
+        .to_string(),
+        "buffer of 1 bytes is too small; at least 16 are needed",
+    );
+}
@@ -0,0 +1,249 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+// This module is only compiled with the `backtrace` feature, which requires
+// Rust 1.65 or later.
+#![allow(clippy::incompatible_msrv)]
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Bound;
+use core::ptr::NonNull;
+use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+type LiveMap = BTreeMap<usize, (Layout, Arc<Backtrace>)>;
+
+/// An allocator that records a backtrace for every live block, to help find
+/// the source of memory leaks.
+///
+/// [`Self::leaks`] returns the blocks that haven’t been deallocated yet,
+/// along with where they were allocated. When the allocator is dropped, any
+/// remaining blocks are reported to standard error.
+///
+/// Backtraces are captured regardless of the `RUST_BACKTRACE` environment
+/// variable, which makes allocation very slow. Zero-sized blocks aren’t
+/// tracked.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "backtrace")))]
+#[derive(Debug, Default)]
+pub struct BacktraceAllocator<A> {
+    inner: A,
+    live: Mutex<LiveMap>,
+}
+
+impl<A> BacktraceAllocator<A> {
+    /// Creates a new [`BacktraceAllocator`] that allocates from `inner`.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            live: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the layout of every live block, along with a backtrace of
+    /// where it was allocated.
+    pub fn leaks(&self) -> Vec<(Layout, Arc<Backtrace>)> {
+        self.lock().values().cloned().collect()
+    }
+
+    /// Returns an iterator over the address and layout of every live
+    /// block, in order of address.
+    ///
+    /// The iterator holds a lock for its entire lifetime, so allocating from
+    /// or deallocating to this allocator while it exists deadlocks.
+    pub fn live_allocations(
+        &self,
+    ) -> impl Iterator<Item = (NonNull<u8>, Layout)> + '_ {
+        LiveAllocations {
+            live: self.lock(),
+            last: None,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, LiveMap> {
+        // A panic while holding the lock can't leave the map inconsistent.
+        self.live.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, ptr: *mut u8, layout: Layout, trace: Arc<Backtrace>) {
+        if layout.size() != 0 {
+            self.lock().insert(ptr as usize, (layout, trace));
+        }
+    }
+
+    fn remove(&self, ptr: NonNull<u8>) -> Option<Arc<Backtrace>> {
+        self.lock().remove(&(ptr.as_ptr() as usize)).map(|(_, trace)| trace)
+    }
+
+    /// Grows or shrinks the block at `ptr` with `f`, moving its backtrace to
+    /// the returned block. If the operation fails, the old block is still
+    /// live.
+    ///
+    /// The backtrace is removed before calling `f`: if the block moves, `A`
+    /// frees the old address, which another thread could then be given and
+    /// record before this call resumes.
+    fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        f: impl FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let trace = self
+            .remove(ptr)
+            .unwrap_or_else(|| Arc::new(Backtrace::force_capture()));
+        let result = f();
+        match result {
+            Ok(new) => self.record(new.as_ptr() as *mut u8, new_layout, trace),
+            Err(_) => self.record(ptr.as_ptr(), old_layout, trace),
+        }
+        result
+    }
+}
+
+/// Iterates over live blocks while holding the lock.
+struct LiveAllocations<'a> {
+    live: MutexGuard<'a, LiveMap>,
+    /// The address of the last block returned.
+    last: Option<usize>,
+}
+
+impl Iterator for LiveAllocations<'_> {
+    type Item = (NonNull<u8>, Layout);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lower = self.last.map_or(Bound::Unbounded, Bound::Excluded);
+        let (&addr, &(layout, _)) =
+            self.live.range((lower, Bound::Unbounded)).next()?;
+        self.last = Some(addr);
+        // SAFETY: `addr` is the address of an allocated block.
+        Some((unsafe { NonNull::new_unchecked(addr as *mut u8) }, layout))
+    }
+}
+
+impl<A> Drop for BacktraceAllocator<A> {
+    fn drop(&mut self) {
+        for (layout, trace) in self.lock().values() {
+            std::eprintln!("leaked block with {:?}, allocated at:", layout);
+            std::eprintln!("{}", trace);
+        }
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for BacktraceAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.allocate(layout)?;
+        let trace = Arc::new(Backtrace::force_capture());
+        self.record(ptr.as_ptr() as *mut u8, layout, trace);
+        Ok(ptr)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.allocate_zeroed(layout)?;
+        let trace = Arc::new(Backtrace::force_capture());
+        self.record(ptr.as_ptr() as *mut u8, layout, trace);
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.remove(ptr);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.grow(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.shrink(ptr, old_layout, new_layout) }
+        })
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    use std::backtrace::BacktraceStatus;
+    use std::string::ToString;
+
+    let alloc = BacktraceAllocator::new(Global);
+    let small = Layout::new::<[u8; 8]>();
+    let large = Layout::new::<[u8; 64]>();
+    let a = alloc.allocate(small).unwrap();
+    let b = alloc.allocate(small).unwrap();
+    unsafe {
+        alloc.deallocate(a.cast(), small);
+        let b = alloc.grow(b.cast(), small, large).unwrap();
+        let leaks = alloc.leaks();
+        assert_eq!(leaks.len(), 1);
+        assert_eq!(leaks[0].0, large);
+        assert_ne!(leaks[0].1.status(), BacktraceStatus::Disabled);
+        assert!(!leaks[0].1.to_string().is_empty());
+        alloc.deallocate(b.cast(), large);
+    }
+    assert!(alloc.leaks().is_empty());
+
+    let blocks: Vec<_> = (1..=3)
+        .map(|i| {
+            let layout = Layout::from_size_align(i * 8, 8).unwrap();
+            (alloc.allocate(layout).unwrap().cast::<u8>(), layout)
+        })
+        .collect();
+    unsafe { alloc.deallocate(blocks[1].0, blocks[1].1) };
+    let mut live: Vec<_> = alloc.live_allocations().collect();
+    live.sort_by_key(|&(_, layout)| layout.size());
+    assert_eq!(live, [blocks[0], blocks[2]]);
+    for &(ptr, layout) in &[blocks[0], blocks[2]] {
+        unsafe { alloc.deallocate(ptr, layout) };
+    }
+}
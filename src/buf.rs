@@ -0,0 +1,167 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use bytes::Bytes;
+use core::fmt::{self, Debug, Formatter};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+use core::slice;
+
+/// A byte buffer allocated from `A`, which can be converted into a
+/// [`Bytes`] that deallocates through `A` when dropped.
+///
+/// This lets code that passes around [`Bytes`] payloads, like network
+/// code, use custom allocators for them:
+///
+/// ```
+/// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+/// use allocator_fallback::{AllocBuf, Global};
+///
+/// let buf = AllocBuf::from_slice_in(b"hello", Global).unwrap();
+/// let bytes = buf.into_bytes();
+/// assert_eq!(&bytes[..], b"hello");
+/// ```
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "bytes")))]
+pub struct AllocBuf<A: Allocator> {
+    ptr: NonNull<u8>,
+    len: usize,
+    alloc: A,
+}
+
+impl<A: Allocator> AllocBuf<A> {
+    /// Allocates a zeroed buffer of `len` bytes from `alloc`.
+    ///
+    /// Empty buffers don’t allocate.
+    pub fn new_zeroed_in(len: usize, alloc: A) -> Result<Self, AllocError> {
+        let ptr = if len == 0 {
+            NonNull::dangling()
+        } else {
+            let layout = Layout::array::<u8>(len).map_err(|_| AllocError)?;
+            alloc.allocate_zeroed(layout)?.cast()
+        };
+        Ok(Self {
+            ptr,
+            len,
+            alloc,
+        })
+    }
+
+    /// Allocates a buffer from `alloc` containing a copy of `data`.
+    pub fn from_slice_in(data: &[u8], alloc: A) -> Result<Self, AllocError> {
+        let mut buf = Self::new_zeroed_in(data.len(), alloc)?;
+        buf.copy_from_slice(data);
+        Ok(buf)
+    }
+
+    /// Returns the allocator used by this buffer.
+    pub fn allocator(&self) -> &A {
+        &self.alloc
+    }
+
+    /// Converts this buffer into a [`Bytes`] without copying.
+    ///
+    /// The memory is deallocated through the allocator once the [`Bytes`]
+    /// and all of its clones and slices are dropped. Converting the result
+    /// into a [`BytesMut`](bytes::BytesMut) copies the data.
+    pub fn into_bytes(self) -> Bytes
+    where
+        A: Send + 'static,
+    {
+        Bytes::from_owner(self)
+    }
+}
+
+impl<A: Allocator> Deref for AllocBuf<A> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: `self.ptr` is valid for `self.len` initialized bytes.
+        unsafe { slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<A: Allocator> DerefMut for AllocBuf<A> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: `self.ptr` is valid for `self.len` initialized bytes.
+        unsafe { slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<A: Allocator> AsRef<[u8]> for AllocBuf<A> {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl<A: Allocator> AsMut<[u8]> for AllocBuf<A> {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl<A: Allocator> Drop for AllocBuf<A> {
+    fn drop(&mut self) {
+        if self.len != 0 {
+            // SAFETY: `self.ptr` was allocated with the layout of a
+            // `[u8; self.len]`.
+            unsafe {
+                let layout = Layout::from_size_align_unchecked(self.len, 1);
+                self.alloc.deallocate(self.ptr, layout);
+            }
+        }
+    }
+}
+
+impl<A: Allocator> Debug for AllocBuf<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+// SAFETY: The buffer owns its memory and allocator.
+unsafe impl<A: Allocator + Send> Send for AllocBuf<A> {}
+
+// SAFETY: The buffer owns its memory and allocator, and `&AllocBuf` provides
+// only shared access to them.
+unsafe impl<A: Allocator + Sync> Sync for AllocBuf<A> {}
+
+#[test]
+fn test() {
+    use crate::{Global, TrackingAllocator};
+    use alloc::boxed::Box;
+
+    let alloc: &'static _ =
+        Box::leak(Box::new(TrackingAllocator::new(Global)));
+    let mut buf = AllocBuf::new_zeroed_in(16, alloc).unwrap();
+    buf[..5].copy_from_slice(b"hello");
+    let bytes = buf.into_bytes();
+    let hello = bytes.slice(..5);
+    drop(bytes);
+    assert_eq!(&hello[..], b"hello");
+    assert_eq!(alloc.stats().deallocations, 0);
+    drop(hello);
+    let stats = alloc.stats();
+    assert_eq!((stats.allocations, stats.deallocations), (1, 1));
+    assert_eq!(stats.current, 0);
+
+    let empty = AllocBuf::from_slice_in(&[], alloc).unwrap().into_bytes();
+    assert!(empty.is_empty());
+    assert_eq!(alloc.stats().allocations, 1);
+}
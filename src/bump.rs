@@ -0,0 +1,661 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{
+    AllocError, Allocator, AllocatorEq, ArenaError, GrowNear, LayoutBounds,
+    OwningAllocator, TooSmall,
+};
+use crate::align_check::debug_aligned;
+use crate::owning::in_buffer;
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::MaybeUninit;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+
+/// A bump allocator that carves allocations out of a fixed buffer.
+///
+/// Allocations are served sequentially from the start of the buffer.
+/// Deallocating the most recent allocation rewinds the buffer, so memory
+/// used in a strictly last-in, first-out order is reclaimed immediately.
+/// Deallocating any other block is a no-op; its memory is reclaimed only by
+/// [`Self::reset`].
+///
+/// [`Allocator`] is implemented for `&Bump`, so that [`Self::reset`], which
+/// requires a unique reference, cannot be called while any allocations are
+/// still in use.
+#[derive(Debug)]
+pub struct Bump<'a> {
+    start: NonNull<u8>,
+    len: usize,
+    /// The offset of the first unallocated byte.
+    offset: Cell<usize>,
+    /// The end of the region that may have been written to since the buffer
+    /// was last known to be all zeros. Bytes at or beyond this offset are
+    /// zero.
+    dirty: Cell<usize>,
+    /// The start of the most recent allocation and the offset before the
+    /// padding that preceded it, if it hasn’t been freed.
+    last: Cell<Option<(usize, usize)>>,
+    frozen: bool,
+    phantom: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> Bump<'a> {
+    /// Creates a new bump allocator that allocates from `buf`.
+    pub fn new(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self::with_dirty(buf, buf.len())
+    }
+
+    /// Like [`Self::new`], but returns an error if `buf` is empty, as the
+    /// allocator couldn’t serve any non-empty allocation from it.
+    ///
+    /// The allocator keeps its bookkeeping outside the buffer, so any
+    /// non-empty buffer is accepted.
+    pub fn try_new(buf: &'a mut [MaybeUninit<u8>]) -> Result<Self, TooSmall> {
+        if buf.is_empty() {
+            return Err(TooSmall {
+                len: 0,
+                required: 1,
+            });
+        }
+        Ok(Self::new(buf))
+    }
+
+    /// Creates a new bump allocator that allocates from `buf`, which
+    /// contains only zeros.
+    ///
+    /// This allows [`allocate_zeroed`](Allocator::allocate_zeroed) to skip
+    /// zeroing memory that hasn’t been handed out since the allocator was
+    /// created.
+    ///
+    /// # Safety
+    ///
+    /// Every byte of `buf` must be initialized to zero.
+    pub unsafe fn new_zeroed(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self::with_dirty(buf, 0)
+    }
+
+    fn with_dirty(buf: &'a mut [MaybeUninit<u8>], dirty: usize) -> Self {
+        Self {
+            start: NonNull::new(buf.as_mut_ptr() as *mut u8).unwrap(),
+            len: buf.len(),
+            offset: Cell::new(0),
+            dirty: Cell::new(dirty),
+            last: Cell::new(None),
+            frozen: false,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Makes the entire buffer available for allocation again.
+    pub fn reset(&mut self) {
+        self.offset.set(0);
+        self.last.set(None);
+    }
+
+    /// Prevents any further allocations until [`Self::thaw`] is called.
+    ///
+    /// While frozen, allocation fails with [`ArenaError::Frozen`], so once
+    /// a data structure has been built in the arena, it can be shared
+    /// knowing nothing else will be placed alongside it. The buffer is
+    /// borrowed and generally not page-aligned, so its pages aren’t
+    /// write-protected.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// Allows allocation again after [`Self::freeze`].
+    pub fn thaw(&mut self) {
+        self.frozen = false;
+    }
+
+    /// Returns whether the allocator is frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Returns the layout of the buffer this allocator allocates from.
+    ///
+    /// The buffer is a byte slice, so the layout’s alignment is 1, even if
+    /// the buffer happens to be more aligned.
+    pub fn backing_layout(&self) -> Layout {
+        // SAFETY: `self.len` is the length of a slice, so it can’t exceed
+        // `isize::MAX`.
+        unsafe { Layout::from_size_align_unchecked(self.len, 1) }
+    }
+
+    /// Returns the number of bytes used by allocations since the allocator
+    /// was created or last reset, including padding for alignment.
+    pub fn used(&self) -> usize {
+        self.offset.get()
+    }
+
+    /// Allocates a block for `layout`, returning a reference tied to the
+    /// lifetime of this allocator.
+    ///
+    /// Unlike [`Allocator::allocate`], which returns a raw pointer, this lets
+    /// the compiler check that the block isn’t used after the allocator is
+    /// reset or dropped:
+    ///
+    /// ```compile_fail
+    /// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+    /// use allocator_fallback::Bump;
+    /// use core::alloc::Layout;
+    /// use core::mem::MaybeUninit;
+    ///
+    /// let mut buf = [MaybeUninit::uninit(); 64];
+    /// let mut bump = Bump::new(&mut buf);
+    /// let bytes = bump.alloc_bytes(Layout::new::<u64>()).unwrap();
+    /// bump.reset();
+    /// bytes[0] = MaybeUninit::new(1);
+    /// ```
+    #[allow(clippy::mut_from_ref)]
+    pub fn alloc_bytes(
+        &self,
+        layout: Layout,
+    ) -> Result<&mut [MaybeUninit<u8>], AllocError> {
+        let ptr = self.allocate(layout)?;
+        // SAFETY: The block is within the buffer, which outlives `self`, and
+        // won’t be handed out again until `Self::reset` is called, which
+        // requires a unique reference.
+        Ok(unsafe { &mut *(ptr.as_ptr() as *mut [MaybeUninit<u8>]) })
+    }
+
+    /// Like [`Allocator::allocate`], but returns an [`ArenaError`] describing
+    /// why the request failed.
+    pub fn try_allocate_in_arena(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, ArenaError> {
+        let start = self.bump(layout)?;
+        let end = start + layout.size();
+        self.dirty.set(self.dirty.get().max(end));
+        Ok(debug_aligned(self.slice(start, layout.size()), layout))
+    }
+
+    /// Returns a guard that, when dropped, frees all allocations made since
+    /// this method was called.
+    ///
+    /// The guard dereferences to this allocator, so it can be allocated from
+    /// as usual (and scopes can be nested). Anything that borrows the guard,
+    /// like a collection using `&Bump` as its allocator, can’t outlive it:
+    ///
+    /// ```compile_fail
+    /// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+    /// use allocator_fallback::Bump;
+    /// use allocator_fallback::collections::Vec;
+    /// use core::mem::MaybeUninit;
+    ///
+    /// let mut buf = [MaybeUninit::uninit(); 64];
+    /// let mut bump = Bump::new(&mut buf);
+    /// let vec = {
+    ///     let scope = bump.scope();
+    ///     let mut vec = Vec::new_in(&*scope);
+    ///     vec.push(1_u8);
+    ///     vec
+    /// };
+    /// ```
+    pub fn scope(&mut self) -> ResetGuard<'_, 'a> {
+        ResetGuard {
+            checkpoint: self.offset.get(),
+            bump: self,
+        }
+    }
+
+    /// Reserves space for `layout`, returning the offset of the allocation.
+    fn bump(&self, layout: Layout) -> Result<usize, ArenaError> {
+        debug_assert!(
+            layout.size() <= isize::MAX as usize - (layout.align() - 1),
+            "layout size overflows when padded to its alignment",
+        );
+        if self.frozen {
+            return Err(ArenaError::Frozen);
+        }
+        let offset = self.offset.get();
+        let addr = self.start.as_ptr() as usize + offset;
+        let padding = addr.wrapping_neg() & (layout.align() - 1);
+        let end = offset
+            .checked_add(padding)
+            .and_then(|start| start.checked_add(layout.size()))
+            .ok_or(ArenaError::LayoutOverflow)?;
+        if end > self.len {
+            return Err(ArenaError::Exhausted {
+                requested: end - offset,
+                available: self.len - offset,
+            });
+        }
+        self.offset.set(end);
+        self.last.set(Some((end - layout.size(), offset)));
+        Ok(end - layout.size())
+    }
+
+    fn slice(&self, start: usize, len: usize) -> NonNull<[u8]> {
+        // SAFETY: `Self::bump` ensures `start + len` is within the buffer.
+        let ptr = unsafe { self.start.as_ptr().add(start) };
+        // SAFETY: `ptr` is derived from a `NonNull`.
+        unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(ptr, len))
+        }
+    }
+}
+
+/// Frees all allocations made from a [`Bump`] within a scope when dropped.
+///
+/// This is returned by [`Bump::scope`].
+#[derive(Debug)]
+pub struct ResetGuard<'b, 'a> {
+    bump: &'b mut Bump<'a>,
+    checkpoint: usize,
+}
+
+impl<'a> Deref for ResetGuard<'_, 'a> {
+    type Target = Bump<'a>;
+
+    fn deref(&self) -> &Bump<'a> {
+        self.bump
+    }
+}
+
+impl<'a> DerefMut for ResetGuard<'_, 'a> {
+    fn deref_mut(&mut self) -> &mut Bump<'a> {
+        self.bump
+    }
+}
+
+impl Drop for ResetGuard<'_, '_> {
+    fn drop(&mut self) {
+        // The guard uniquely borrows the allocator, so no `&Bump` can be
+        // live. If the allocator was reset within the scope, its offset may
+        // already be lower than the checkpoint.
+        let offset = self.bump.offset.get();
+        self.bump.offset.set(offset.min(self.checkpoint));
+        self.bump.last.set(None);
+    }
+}
+
+// SAFETY: Allocations never overlap, as `Self::bump` only ever moves the
+// offset forwards, `deallocate` only moves it back over the block being
+// freed (and the padding before it), and `Bump::reset` can't be called while
+// any `&Bump` is live. Copies of a `&Bump` refer to the same allocator.
+unsafe impl Allocator for &Bump<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Ok(self.try_allocate_in_arena(layout)?)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let start = self.bump(layout)?;
+        let end = start + layout.size();
+        let dirty = self.dirty.get();
+        if start < dirty {
+            let len = dirty.min(end) - start;
+            // SAFETY: `Self::bump` ensures `start + len` is within the
+            // buffer.
+            unsafe {
+                self.start.as_ptr().add(start).write_bytes(0_u8, len);
+            }
+        }
+        self.dirty.set(dirty.max(end));
+        Ok(debug_aligned(self.slice(start, layout.size()), layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let start =
+            (ptr.as_ptr() as usize).wrapping_sub(self.start.as_ptr() as usize);
+        // Blocks are exactly `layout.size()` bytes long, so this is the last
+        // block in the buffer only if it ends at the offset.
+        if start.wrapping_add(layout.size()) != self.offset.get() {
+            return;
+        }
+        let offset = match self.last.take() {
+            Some((last, before)) if last == start => before,
+            // An earlier block, left at the end after later ones were freed.
+            // The padding before it is unknown, so only the block itself is
+            // reclaimed.
+            _ => start,
+        };
+        self.offset.set(offset);
+    }
+}
+
+/// If `ptr` is the most recent allocation, this extends it in place when
+/// possible, without copying. Otherwise, the hint is ignored.
+impl GrowNear for &Bump<'_> {
+    unsafe fn grow_near(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        _hint: Option<NonNull<u8>>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let start =
+            (ptr.as_ptr() as usize).wrapping_sub(self.start.as_ptr() as usize);
+        let aligned = ptr.as_ptr() as usize & (new_layout.align() - 1) == 0;
+        let end = start.checked_add(new_layout.size()).filter(|&end| {
+            aligned
+                && !self.frozen
+                && end <= self.len
+                && start + old_layout.size() == self.offset.get()
+        });
+        if let Some(end) = end {
+            self.offset.set(end);
+            self.dirty.set(self.dirty.get().max(end));
+            let block = self.slice(start, new_layout.size());
+            return Ok(debug_aligned(block, new_layout));
+        }
+        // SAFETY: Checked by caller.
+        unsafe { self.grow(ptr, old_layout, new_layout) }
+    }
+}
+
+/// The largest layout is the whole buffer, regardless of how much of it is
+/// in use. Its alignment is that of the start of the buffer, so an empty
+/// allocator can serve it.
+impl LayoutBounds for &Bump<'_> {
+    fn max_layout(&self) -> Option<Layout> {
+        let start = self.start.as_ptr() as usize;
+        let mut align = 1 << start.trailing_zeros();
+        loop {
+            if let Ok(layout) = Layout::from_size_align(self.len, align) {
+                return Some(layout);
+            }
+            align >>= 1;
+        }
+    }
+}
+
+// SAFETY: The allocator uniquely borrows its buffer, so it can be sent to
+// another thread.
+unsafe impl Send for Bump<'_> {}
+
+// SAFETY: All blocks are allocated from the buffer.
+unsafe impl OwningAllocator for &Bump<'_> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        in_buffer(self.start, self.len, ptr, layout)
+    }
+}
+
+// SAFETY: Two bump allocators with the same buffer must be the same
+// allocator, as each one uniquely borrows its buffer.
+unsafe impl AllocatorEq for &Bump<'_> {
+    fn same_allocator(&self, other: &Self) -> bool {
+        self.start == other.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_zeroed(bump: &Bump<'_>) {
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = bump.allocate_zeroed(layout).unwrap();
+        let ptr = ptr.as_ptr() as *mut u8;
+        assert_eq!(ptr as usize % 8, 0);
+        // SAFETY: `ptr` points to `layout.size()` initialized bytes.
+        let bytes = unsafe { core::slice::from_raw_parts_mut(ptr, 24) };
+        assert!(bytes.iter().all(|&b| b == 0));
+        bytes.fill(0xaa);
+    }
+
+    #[test]
+    fn zeroed_new() {
+        let mut buf = [MaybeUninit::new(0xff_u8); 128];
+        let mut bump = Bump::new(&mut buf);
+        for _ in 0..2 {
+            check_zeroed(&bump);
+            check_zeroed(&bump);
+            bump.reset();
+        }
+    }
+
+    #[test]
+    fn zeroed_new_zeroed() {
+        let mut buf = [MaybeUninit::new(0); 128];
+        // SAFETY: `buf` is zeroed.
+        let mut bump = unsafe { Bump::new_zeroed(&mut buf) };
+        for _ in 0..2 {
+            check_zeroed(&bump);
+            check_zeroed(&bump);
+            bump.reset();
+        }
+        let layout = Layout::new::<[u8; 48]>();
+        (&bump).allocate(layout).unwrap();
+        let ptr = (&bump).allocate_zeroed(layout).unwrap();
+        // SAFETY: `ptr` points to `layout.size()` initialized bytes.
+        let bytes = unsafe { &*(ptr.as_ptr() as *const [u8]) };
+        assert!(bytes.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn exhausted() {
+        let mut buf = [MaybeUninit::uninit(); 16];
+        let bump = Bump::new(&mut buf);
+        let layout = Layout::new::<[u8; 12]>();
+        assert!((&bump).allocate(layout).is_ok());
+        assert_eq!((&bump).allocate(layout), Err(AllocError));
+    }
+
+    #[test]
+    fn same_allocator() {
+        let mut buf1 = [MaybeUninit::uninit(); 16];
+        let mut buf2 = [MaybeUninit::uninit(); 16];
+        let bump1 = Bump::new(&mut buf1);
+        let bump2 = Bump::new(&mut buf2);
+        assert!((&bump1).same_allocator(&&bump1));
+        assert!(!(&bump1).same_allocator(&&bump2));
+    }
+
+    #[test]
+    fn huge() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let bump = Bump::new(&mut buf);
+        (&bump).allocate(Layout::new::<[u8; 3]>()).unwrap();
+        let size = isize::MAX as usize - 63;
+        let layout = Layout::from_size_align(size, 64).unwrap();
+        assert_eq!((&bump).allocate(layout), Err(AllocError));
+        assert_eq!((&bump).allocate_zeroed(layout), Err(AllocError));
+    }
+
+    #[test]
+    fn grow_near() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let bump = Bump::new(&mut buf);
+        let old = Layout::new::<[u8; 8]>();
+        let new = Layout::new::<[u8; 24]>();
+        let a = (&bump).allocate(old).unwrap().cast::<u8>();
+        unsafe {
+            a.as_ptr().write(5);
+            let grown = (&bump).grow_near(a, old, new, Some(a)).unwrap();
+            assert_eq!(grown.cast(), a);
+            assert_eq!(
+                (&*(grown.as_ptr() as *const [MaybeUninit<u8>])).len(),
+                24
+            );
+            assert_eq!(*a.as_ptr(), 5);
+
+            // `a` is no longer the last allocation, so it must be copied.
+            let b = (&bump).allocate(old).unwrap().cast::<u8>();
+            let moved =
+                (&bump).grow_near(a, new, Layout::new::<[u8; 32]>(), None);
+            let moved = moved.unwrap().cast::<u8>();
+            assert!(moved > b);
+            assert_eq!(*moved.as_ptr(), 5);
+        }
+    }
+
+    #[test]
+    fn scope() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let mut bump = Bump::new(&mut buf);
+        let layout = Layout::new::<[u8; 16]>();
+        let addr = |ptr: NonNull<[u8]>| ptr.as_ptr() as *mut u8 as usize;
+        let a = addr((&bump).allocate(layout).unwrap());
+        {
+            let mut outer = bump.scope();
+            let b = addr((&*outer).allocate(layout).unwrap());
+            assert_eq!(b, a + 16);
+            {
+                let inner = outer.scope();
+                (&*inner).allocate(layout).unwrap();
+                (&*inner).allocate(layout).unwrap();
+                assert_eq!((&*inner).allocate(layout), Err(AllocError));
+            }
+            assert_eq!(addr((&*outer).allocate(layout).unwrap()), b + 16);
+        }
+        assert_eq!(addr((&bump).allocate(layout).unwrap()), a + 16);
+    }
+
+    #[test]
+    fn alloc_bytes() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let mut bump = Bump::new(&mut buf);
+        let a = bump.alloc_bytes(Layout::new::<[u8; 16]>()).unwrap();
+        let b = bump.alloc_bytes(Layout::new::<[u8; 16]>()).unwrap();
+        a.fill(MaybeUninit::new(1));
+        b.fill(MaybeUninit::new(2));
+        assert_eq!(unsafe { a[15].assume_init() }, 1);
+        assert_eq!(
+            bump.alloc_bytes(Layout::new::<[u8; 48]>()).err(),
+            Some(AllocError),
+        );
+        bump.reset();
+        assert!(bump.alloc_bytes(Layout::new::<[u8; 48]>()).is_ok());
+    }
+
+    #[test]
+    fn used() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let mut bump = Bump::new(&mut buf);
+        assert_eq!(bump.backing_layout().size(), 64);
+        assert_eq!(bump.used(), 0);
+        let mut used = 0;
+        for &size in &[1, 8, 3, 16] {
+            let layout = Layout::from_size_align(size, 8).unwrap();
+            (&bump).allocate(layout).unwrap();
+            assert!(bump.used() >= used + size);
+            used = bump.used();
+        }
+        assert!(used <= 64);
+        bump.reset();
+        assert_eq!(bump.used(), 0);
+    }
+
+    #[test]
+    fn try_allocate_in_arena() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let bump = Bump::new(&mut buf);
+        let layout = Layout::new::<[u8; 40]>();
+        bump.try_allocate_in_arena(layout).unwrap();
+        assert_eq!(
+            bump.try_allocate_in_arena(layout),
+            Err(ArenaError::Exhausted {
+                requested: 40,
+                available: 24,
+            }),
+        );
+        assert_eq!((&bump).allocate(layout), Err(AllocError));
+    }
+
+    #[test]
+    fn freeze() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let mut bump = Bump::new(&mut buf);
+        let layout = Layout::new::<[u8; 8]>();
+        let a = (&bump).allocate(layout).unwrap().cast::<u8>();
+        bump.freeze();
+        assert!(bump.is_frozen());
+        assert_eq!(
+            bump.try_allocate_in_arena(layout),
+            Err(ArenaError::Frozen)
+        );
+        assert_eq!((&bump).allocate_zeroed(layout), Err(AllocError));
+        let new = Layout::new::<[u8; 16]>();
+        assert_eq!(
+            unsafe { (&bump).grow_near(a, layout, new, None) },
+            Err(AllocError)
+        );
+        bump.thaw();
+        assert!((&bump).allocate(layout).is_ok());
+    }
+
+    #[test]
+    fn try_new() {
+        let mut buf = [MaybeUninit::uninit(); 1];
+        assert_eq!(
+            Bump::try_new(&mut buf[..0]).err(),
+            Some(TooSmall {
+                len: 0,
+                required: 1,
+            }),
+        );
+        let bump = Bump::try_new(&mut buf).unwrap();
+        assert!((&bump).allocate(Layout::new::<u8>()).is_ok());
+    }
+
+    #[test]
+    fn lifo_deallocate() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let bump = Bump::new(&mut buf);
+        let byte = Layout::new::<u8>();
+        let word = Layout::new::<u64>();
+        let a = (&bump).allocate(byte).unwrap().cast();
+        let b = (&bump).allocate(byte).unwrap().cast();
+        let used = bump.used();
+        let c = (&bump).allocate(word).unwrap().cast();
+        // Rewinds past the padding before `c`, too.
+        unsafe { (&bump).deallocate(c, word) };
+        assert_eq!(bump.used(), used);
+        unsafe {
+            (&bump).deallocate(b, byte);
+            (&bump).deallocate(a, byte);
+        }
+        assert_eq!(bump.used(), 0);
+        assert_eq!((&bump).allocate(byte).unwrap().cast(), a);
+    }
+
+    #[test]
+    fn out_of_order_deallocate() {
+        let mut buf = [MaybeUninit::uninit(); 64];
+        let bump = Bump::new(&mut buf);
+        let layout = Layout::new::<u32>();
+        let a = (&bump).allocate(layout).unwrap().cast();
+        let b = (&bump).allocate(layout).unwrap().cast();
+        let used = bump.used();
+        unsafe { (&bump).deallocate(a, layout) };
+        assert_eq!(bump.used(), used);
+        unsafe { (&bump).deallocate(b, layout) };
+        // `a` was already freed, but only `b` is reclaimed.
+        assert_eq!(bump.used(), used - 4);
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn std_vec() {
+        let mut buf = [MaybeUninit::uninit(); 256];
+        let bump = Bump::new(&mut buf);
+        let mut vec = alloc::vec::Vec::new_in(&bump);
+        vec.extend(0..16_u32);
+        assert_eq!(vec.iter().sum::<u32>(), 120);
+        assert!(bump.used() >= 64);
+    }
+}
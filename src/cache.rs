@@ -0,0 +1,219 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::fmt::{self, Debug, Formatter};
+use core::ptr::{self, NonNull};
+
+/// An allocator that forwards to `A`, but keeps the most recently
+/// deallocated block to serve the next allocation with the same layout.
+///
+/// This speeds up patterns that repeatedly free a block and immediately
+/// allocate another of the same size. At most one block is cached; when a
+/// different block is deallocated, the cached block is returned to `A`
+/// first. The cached block is also returned when this allocator is dropped.
+pub struct OneBlockCache<A: Allocator> {
+    inner: A,
+    cached: Cell<Option<(NonNull<u8>, Layout)>>,
+}
+
+impl<A: Allocator> OneBlockCache<A> {
+    /// Creates a new [`OneBlockCache`] that allocates from `inner`.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            cached: Cell::new(None),
+        }
+    }
+
+    /// Returns the cached block to the underlying allocator, if there is
+    /// one.
+    pub fn flush(&self) {
+        if let Some((ptr, layout)) = self.cached.take() {
+            // SAFETY: Cached blocks were allocated by `self.inner`, and the
+            // caller of `deallocate` gave up ownership of them.
+            unsafe { self.inner.deallocate(ptr, layout) };
+        }
+    }
+
+    /// Takes the cached block if it has the given layout.
+    fn take(&self, layout: Layout) -> Option<NonNull<[u8]>> {
+        match self.cached.get() {
+            Some((ptr, cached)) if cached == layout => {
+                self.cached.set(None);
+                let ptr =
+                    ptr::slice_from_raw_parts_mut(ptr.as_ptr(), layout.size());
+                // SAFETY: `ptr` came from a `NonNull`.
+                Some(unsafe { NonNull::new_unchecked(ptr) })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl<A: Allocator> Drop for OneBlockCache<A> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<A: Allocator + Debug> Debug for OneBlockCache<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OneBlockCache")
+            .field("inner", &self.inner)
+            .field("cached", &self.cached.get())
+            .finish()
+    }
+}
+
+// SAFETY: The cached block is owned by the allocator, so it can be sent to
+// another thread along with `A`.
+unsafe impl<A: Allocator + Send> Send for OneBlockCache<A> {}
+
+// SAFETY: This impl forwards to `A`. Cached blocks were allocated by `A`
+// with the same layout, and aren’t in use.
+unsafe impl<A: Allocator> Allocator for OneBlockCache<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        match self.take(layout) {
+            Some(ptr) => Ok(ptr),
+            None => self.inner.allocate(layout),
+        }
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match self.take(layout) {
+            Some(ptr) => {
+                // SAFETY: `ptr` is valid for `layout.size()` bytes.
+                unsafe {
+                    (ptr.as_ptr() as *mut u8).write_bytes(0, layout.size());
+                }
+                Ok(ptr)
+            }
+            None => self.inner.allocate_zeroed(layout),
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.flush();
+        self.cached.set(Some((ptr, layout)));
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+
+    /// Counts live blocks.
+    #[derive(Default)]
+    struct Counting {
+        live: Cell<usize>,
+    }
+
+    unsafe impl Allocator for &Counting {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.live.set(self.live.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.set(self.live.get() - 1);
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn reuse() {
+        let counting = Counting::default();
+        let alloc = OneBlockCache::new(&counting);
+        let layout = Layout::new::<[u8; 32]>();
+        let a = alloc.allocate(layout).unwrap();
+        unsafe {
+            alloc.deallocate(a.cast(), layout);
+            assert_eq!(counting.live.get(), 1);
+            let b = alloc.allocate_zeroed(layout).unwrap();
+            assert_eq!(b, a);
+            assert_eq!(counting.live.get(), 1);
+            let bytes = &*(b.as_ptr() as *const [u8]);
+            assert!(bytes.iter().all(|&b| b == 0));
+            alloc.deallocate(b.cast(), layout);
+        }
+        drop(alloc);
+        assert_eq!(counting.live.get(), 0);
+    }
+
+    #[test]
+    fn mismatch() {
+        let counting = Counting::default();
+        let alloc = OneBlockCache::new(&counting);
+        let small = Layout::new::<[u8; 8]>();
+        let large = Layout::new::<[u8; 64]>();
+        let a = alloc.allocate(small).unwrap();
+        let b = alloc.allocate(large).unwrap();
+        unsafe {
+            alloc.deallocate(a.cast(), small);
+            let c = alloc.allocate(large).unwrap();
+            assert_eq!(counting.live.get(), 3);
+            alloc.deallocate(b.cast(), large);
+            // `a` was flushed.
+            assert_eq!(counting.live.get(), 2);
+            alloc.deallocate(c.cast(), large);
+            assert_eq!(counting.live.get(), 1);
+        }
+        alloc.flush();
+        assert_eq!(counting.live.get(), 0);
+    }
+}
@@ -0,0 +1,218 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use crate::move_block::move_block;
+use alloc::alloc::Layout;
+use core::mem::MaybeUninit;
+use core::ptr::{self, NonNull};
+
+/// An allocator that forwards to `A`, raising the alignment of large
+/// requests to a cache line.
+///
+/// Requests larger than [`Self::THRESHOLD`] bytes are aligned to at least
+/// [`Self::LINE`] bytes, so large buffers shared between threads don’t share
+/// cache lines with neighbouring allocations. Smaller requests are forwarded
+/// unchanged, so they don’t waste space on padding.
+///
+/// Blocks for small requests are returned with a length of at most
+/// [`Self::THRESHOLD`], so any layout that fits a block gets the same
+/// alignment it was allocated with.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CacheAligned<A>(pub A);
+
+impl<A> CacheAligned<A> {
+    /// Requests larger than this many bytes are cache-aligned.
+    pub const THRESHOLD: usize = 256;
+
+    /// The alignment given to large requests.
+    pub const LINE: usize = 64;
+
+    fn adjust(layout: Layout) -> Result<Layout, AllocError> {
+        if layout.size() <= Self::THRESHOLD {
+            return Ok(layout);
+        }
+        layout.align_to(Self::LINE).map_err(|_| AllocError)
+    }
+
+    /// # Safety
+    ///
+    /// `layout` must describe a block currently allocated via this
+    /// allocator.
+    unsafe fn adjust_unchecked(layout: Layout) -> Layout {
+        if layout.size() <= Self::THRESHOLD {
+            return layout;
+        }
+        let align = layout.align().max(Self::LINE);
+        // SAFETY: `Self::adjust` must have succeeded for this layout when
+        // the block was allocated.
+        unsafe { Layout::from_size_align_unchecked(layout.size(), align) }
+    }
+
+    /// Limits the length of blocks for small requests to
+    /// [`Self::THRESHOLD`].
+    fn clamp(
+        result: Result<NonNull<[u8]>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let block = result?;
+        if layout.size() > Self::THRESHOLD {
+            return Ok(block);
+        }
+        // SAFETY: Allocators always return pointers to valid memory.
+        let len =
+            unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len();
+        let ptr = ptr::slice_from_raw_parts_mut(
+            block.as_ptr() as *mut u8,
+            len.min(Self::THRESHOLD),
+        );
+        // SAFETY: `ptr` came from a `NonNull`.
+        Ok(unsafe { NonNull::new_unchecked(ptr) })
+    }
+}
+
+// SAFETY: This impl forwards to `A`. Every block is allocated with a layout
+// at least as aligned as the requested one, and deallocated with the same
+// adjusted layout, since the adjustment depends only on the size, and blocks
+// never straddle the threshold.
+unsafe impl<A: Allocator> Allocator for CacheAligned<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let layout = Self::adjust(layout)?;
+        Self::clamp(self.0.allocate(layout), layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let layout = Self::adjust(layout)?;
+        Self::clamp(self.0.allocate_zeroed(layout), layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(ptr, Self::adjust_unchecked(layout)) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let old = unsafe { Self::adjust_unchecked(old_layout) };
+        let new = Self::adjust(new_layout)?;
+        if old.align() != new.align() {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, false)
+            };
+        }
+        // SAFETY: Checked by caller.
+        Self::clamp(unsafe { self.0.grow(ptr, old, new) }, new)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let old = unsafe { Self::adjust_unchecked(old_layout) };
+        let new = Self::adjust(new_layout)?;
+        if old.align() != new.align() {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, true)
+            };
+        }
+        // SAFETY: Checked by caller.
+        Self::clamp(unsafe { self.0.grow_zeroed(ptr, old, new) }, new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let old = unsafe { Self::adjust_unchecked(old_layout) };
+        let new = Self::adjust(new_layout)?;
+        if old.align() != new.align() {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, false)
+            };
+        }
+        // SAFETY: Checked by caller.
+        Self::clamp(unsafe { self.0.shrink(ptr, old, new) }, new)
+    }
+}
+
+impl<A: Overhead> Overhead for CacheAligned<A> {
+    fn overhead(&self, layout: Layout) -> usize {
+        Self::adjust(layout).map_or(0, |layout| self.0.overhead(layout))
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    use core::cell::Cell;
+
+    /// Forwards to `Global`, recording the last alignment it saw.
+    #[derive(Default)]
+    struct Record(Cell<usize>);
+
+    unsafe impl Allocator for Record {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.0.set(layout.align());
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.set(layout.align());
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+
+    let alloc = CacheAligned(Record::default());
+    let small = Layout::from_size_align(256, 4).unwrap();
+    let large = Layout::from_size_align(257, 4).unwrap();
+    unsafe {
+        let ptr = alloc.allocate(small).unwrap();
+        assert_eq!(alloc.0.0.get(), 4);
+        let ptr = alloc.grow(ptr.cast(), small, large).unwrap();
+        assert_eq!(alloc.0.0.get(), 4);
+        assert_eq!(ptr.as_ptr() as *mut u8 as usize % 64, 0);
+        alloc.deallocate(ptr.cast(), large);
+        assert_eq!(alloc.0.0.get(), 64);
+    }
+
+    // Requests that are already more aligned keep their alignment.
+    let page = Layout::from_size_align(4096, 4096).unwrap();
+    let ptr = alloc.allocate(page).unwrap();
+    assert_eq!(alloc.0.0.get(), 4096);
+    unsafe { alloc.deallocate(ptr.cast(), page) };
+}
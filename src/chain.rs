@@ -0,0 +1,215 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, OwningAllocator};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that tries to allocate with `A`, falling back to `B` if that
+/// fails.
+///
+/// Deallocations are routed to `A` if [`A::owns`](OwningAllocator::owns) the
+/// block, and to `B` otherwise. Growing or shrinking a block owned by `A`
+/// tries `A` first; if `A` can’t resize the block, it is moved into a new
+/// block allocated by `B`. This way, a buffer in a bump arena can keep
+/// growing after the arena is exhausted.
+///
+/// ```
+/// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+/// use allocator_fallback::{Allocator, Bump, Chain, Global};
+/// # use core::alloc::Layout;
+/// # use core::mem::MaybeUninit;
+///
+/// let mut buf = [MaybeUninit::uninit(); 64];
+/// let bump = Bump::new(&mut buf);
+/// let alloc = Chain(&bump, Global);
+/// # let ptr = alloc.allocate(Layout::new::<u64>()).unwrap();
+/// # unsafe { alloc.deallocate(ptr.cast(), Layout::new::<u64>()) };
+/// ```
+///
+/// This is a tuple struct rather than an impl for `(A, B)` because
+/// [`Allocator`] may be the trait from the standard library (see
+/// [Crate features](crate#crate-features)), which this crate can’t implement
+/// for foreign types like tuples.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Chain<A, B>(pub A, pub B);
+
+impl<A, B> Chain<A, B>
+where
+    A: OwningAllocator,
+    B: Allocator,
+{
+    /// Moves a block owned by `A` into a new block allocated by `B`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block currently allocated via `A`, and
+    /// `old_layout` must fit that block.
+    unsafe fn relocate(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = if zeroed {
+            self.1.allocate_zeroed(new_layout)?
+        } else {
+            self.1.allocate(new_layout)?
+        };
+        let size = old_layout.size().min(new_layout.size());
+        // SAFETY: Checked by caller. `new` is a distinct allocation at least
+        // `size` bytes long.
+        unsafe {
+            (new.as_ptr() as *mut u8)
+                .copy_from_nonoverlapping(ptr.as_ptr(), size);
+            self.0.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+}
+
+// SAFETY: Every block is allocated by exactly one of `A` and `B`, and
+// `OwningAllocator` guarantees that each block is deallocated by the
+// allocator that allocated it.
+unsafe impl<A, B> Allocator for Chain<A, B>
+where
+    A: OwningAllocator,
+    B: Allocator,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate(layout).or_else(|_| self.1.allocate(layout))
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0
+            .allocate_zeroed(layout)
+            .or_else(|_| self.1.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller. `OwningAllocator::owns` tells us which
+        // allocator allocated the block.
+        unsafe {
+            if self.0.owns(ptr, layout) {
+                self.0.deallocate(ptr, layout);
+            } else {
+                self.1.deallocate(ptr, layout);
+            }
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            if !self.0.owns(ptr, old_layout) {
+                return self.1.grow(ptr, old_layout, new_layout);
+            }
+            self.0
+                .grow(ptr, old_layout, new_layout)
+                .or_else(|_| self.relocate(ptr, old_layout, new_layout, false))
+        }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            if !self.0.owns(ptr, old_layout) {
+                return self.1.grow_zeroed(ptr, old_layout, new_layout);
+            }
+            self.0
+                .grow_zeroed(ptr, old_layout, new_layout)
+                .or_else(|_| self.relocate(ptr, old_layout, new_layout, true))
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            if !self.0.owns(ptr, old_layout) {
+                return self.1.shrink(ptr, old_layout, new_layout);
+            }
+            self.0
+                .shrink(ptr, old_layout, new_layout)
+                .or_else(|_| self.relocate(ptr, old_layout, new_layout, false))
+        }
+    }
+}
+
+#[test]
+fn test() {
+    use crate::{Bump, Global};
+    use alloc::vec::Vec;
+    use core::mem::MaybeUninit;
+
+    let mut buf = [MaybeUninit::uninit(); 64];
+    let bump = Bump::new(&mut buf);
+    let alloc = Chain(&bump, Global);
+    let layout = Layout::new::<[u8; 16]>();
+    let blocks: Vec<_> =
+        (0..8).map(|_| alloc.allocate(layout).unwrap()).collect();
+    let owned = blocks.iter().filter(|b| bump_owns(&bump, b)).count();
+    assert_eq!(owned, 4);
+    for block in blocks {
+        unsafe { alloc.deallocate(block.cast(), layout) };
+    }
+
+    fn bump_owns(bump: &Bump<'_>, block: &NonNull<[u8]>) -> bool {
+        (&bump).owns(block.cast(), Layout::new::<[u8; 16]>())
+    }
+}
+
+#[test]
+fn test_grow_past_primary() {
+    use crate::{Bump, Global};
+    use core::mem::MaybeUninit;
+
+    let mut buf = [MaybeUninit::uninit(); 64];
+    let bump = Bump::new(&mut buf);
+    let alloc = Chain(&bump, Global);
+    let small = Layout::new::<[u8; 16]>();
+    let large = Layout::new::<[u8; 256]>();
+    unsafe {
+        let ptr = alloc.allocate(small).unwrap().cast::<[u8; 16]>();
+        assert!((&bump).owns(ptr.cast(), small));
+        ptr.as_ptr().write(*b"0123456789abcdef");
+        let ptr = alloc.grow(ptr.cast(), small, large).unwrap();
+        assert!(!(&bump).owns(ptr.cast(), large));
+        let ptr = ptr.cast::<[u8; 16]>();
+        assert_eq!(&*ptr.as_ptr(), b"0123456789abcdef");
+        alloc.deallocate(ptr.cast(), large);
+    }
+}
@@ -0,0 +1,405 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, SizeTrackingAllocator};
+use alloc::alloc::Layout;
+use core::mem::MaybeUninit;
+use core::ops::Bound;
+use core::ptr::NonNull;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// An allocator that verifies that every block is deallocated (or grown, or
+/// shrunk) with the same layout it was allocated with.
+///
+/// Passing the wrong layout to [`Allocator::deallocate`] is undefined
+/// behavior that most allocators can’t detect. This wrapper records the
+/// layout of every live block allocated via `A`, and panics if a block is
+/// freed with a layout that doesn’t fit it, freed twice, or wasn’t
+/// allocated via this allocator. Like [`Allocator::deallocate`], a layout
+/// fits if it has the alignment the block was allocated with and a size
+/// between the requested size and the length of the returned block. This is
+/// intended for use in tests: to detect double frees, the address of every
+/// freed block is remembered until it is allocated again.
+///
+/// Zero-sized blocks aren’t tracked, as allocators may return the same
+/// dangling pointer for all of them.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "test-util")))]
+#[derive(Debug, Default)]
+pub struct CheckedAllocator<A> {
+    inner: A,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The layouts and lengths of live blocks, by address.
+    live: BTreeMap<usize, (Layout, usize)>,
+    /// The addresses of blocks that have been freed and not reallocated.
+    freed: HashSet<usize>,
+}
+
+impl<A> CheckedAllocator<A> {
+    /// Creates a new [`CheckedAllocator`] that allocates from `inner`.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the number of live blocks with non-zero sizes.
+    pub fn live_count(&self) -> usize {
+        self.lock().live.len()
+    }
+
+    /// Returns an iterator over the live blocks with non-zero sizes, in
+    /// order of address.
+    ///
+    /// The iterator holds a lock for its entire lifetime, so allocating from
+    /// or deallocating to this allocator while it exists deadlocks.
+    pub fn live_allocations(
+        &self,
+    ) -> impl Iterator<Item = (NonNull<u8>, Layout)> + '_ {
+        LiveAllocations {
+            state: self.lock(),
+            last: None,
+        }
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic while holding the lock can't leave the state inconsistent.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, block: NonNull<[u8]>, layout: Layout) {
+        // SAFETY: `block` was just returned by `A`, so it is valid for its
+        // length.
+        let len =
+            unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len();
+        self.record_len(block.as_ptr() as *mut u8, layout, len);
+    }
+
+    fn record_len(&self, ptr: *mut u8, layout: Layout, len: usize) {
+        if layout.size() != 0 {
+            let mut state = self.lock();
+            state.freed.remove(&(ptr as usize));
+            state.live.insert(ptr as usize, (layout, len));
+        }
+    }
+
+    /// Removes `ptr` from the set of live blocks, panicking if `layout`
+    /// doesn’t fit it. Returns the length of the block.
+    fn verify(&self, ptr: NonNull<u8>, layout: Layout) -> usize {
+        let addr = ptr.as_ptr() as usize;
+        let mut state = self.lock();
+        let (actual, len) = if layout.size() == 0 {
+            match state.live.get(&addr) {
+                Some(&actual) => actual,
+                None => return 0,
+            }
+        } else {
+            match state.live.remove(&addr) {
+                Some(actual) => {
+                    state.freed.insert(addr);
+                    actual
+                }
+                None if state.freed.contains(&addr) => {
+                    drop(state);
+                    panic!("double free of block at {:p}", ptr);
+                }
+                None => {
+                    drop(state);
+                    panic!(
+                        "block at {:p} was not allocated by this allocator",
+                        ptr,
+                    );
+                }
+            }
+        };
+        drop(state);
+        let fits = layout.align() == actual.align()
+            && (actual.size()..=len).contains(&layout.size());
+        if !fits {
+            panic!(
+                "layout mismatch for block at {:p}: allocated with {:?} \
+                 (length {}), freed with {:?}",
+                ptr, actual, len, layout,
+            );
+        }
+        len
+    }
+
+    /// Records the result of growing or shrinking the block at `ptr`. If the
+    /// operation failed, the old block is still live.
+    fn update(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        old_len: usize,
+        result: Result<NonNull<[u8]>, AllocError>,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match result {
+            Ok(new) => self.record(new, new_layout),
+            Err(_) => self.record_len(ptr.as_ptr(), old_layout, old_len),
+        }
+        result
+    }
+}
+
+/// Iterates over live blocks while holding the lock.
+struct LiveAllocations<'a> {
+    state: MutexGuard<'a, State>,
+    /// The address of the last block returned.
+    last: Option<usize>,
+}
+
+impl Iterator for LiveAllocations<'_> {
+    type Item = (NonNull<u8>, Layout);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let lower = self.last.map_or(Bound::Unbounded, Bound::Excluded);
+        let (&addr, &(layout, _)) =
+            self.state.live.range((lower, Bound::Unbounded)).next()?;
+        self.last = Some(addr);
+        // SAFETY: `addr` is the address of an allocated block.
+        Some((unsafe { NonNull::new_unchecked(addr as *mut u8) }, layout))
+    }
+}
+
+// SAFETY: This impl forwards to `A`. Panicking on misuse doesn’t affect the
+// blocks that are returned.
+unsafe impl<A: Allocator> Allocator for CheckedAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.allocate(layout)?;
+        self.record(ptr, layout);
+        Ok(ptr)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = self.inner.allocate_zeroed(layout)?;
+        self.record(ptr, layout);
+        Ok(ptr)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.verify(ptr, layout);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_len = self.verify(ptr, old_layout);
+        // SAFETY: Checked by caller.
+        let result = unsafe { self.inner.grow(ptr, old_layout, new_layout) };
+        self.update(ptr, old_layout, old_len, result, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_len = self.verify(ptr, old_layout);
+        // SAFETY: Checked by caller.
+        let result =
+            unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) };
+        self.update(ptr, old_layout, old_len, result, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let old_len = self.verify(ptr, old_layout);
+        // SAFETY: Checked by caller.
+        let result = unsafe { self.inner.shrink(ptr, old_layout, new_layout) };
+        self.update(ptr, old_layout, old_len, result, new_layout)
+    }
+}
+
+/// Looks up the layout of the block, panicking if it isn’t live or wasn’t
+/// allocated with `align`.
+impl<A: Allocator> SizeTrackingAllocator for CheckedAllocator<A> {
+    unsafe fn deallocate_unsized(&self, ptr: NonNull<u8>, align: usize) {
+        let live = self.lock().live.get(&(ptr.as_ptr() as usize)).copied();
+        let layout = live.map(|(layout, _)| layout);
+        let layout = layout.unwrap_or_else(|| {
+            panic!("size of block at {:p} is unknown", ptr);
+        });
+        if layout.align() != align {
+            panic!(
+                "alignment mismatch for block at {:p}: allocated with {}, \
+                 freed with {}",
+                ptr,
+                layout.align(),
+                align,
+            );
+        }
+        // SAFETY: Checked by caller.
+        unsafe { self.deallocate(ptr, layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+
+    #[test]
+    fn matching() {
+        let alloc = CheckedAllocator::new(Global);
+        let small = Layout::new::<[u8; 8]>();
+        let large = Layout::new::<[u8; 32]>();
+        let ptr = alloc.allocate(small).unwrap();
+        assert_eq!(alloc.live_count(), 1);
+        unsafe {
+            let ptr = alloc.grow(ptr.cast(), small, large).unwrap();
+            let ptr = alloc.shrink(ptr.cast(), large, small).unwrap();
+            alloc.deallocate(ptr.cast(), small);
+        }
+        assert_eq!(alloc.live_count(), 0);
+    }
+
+    #[test]
+    fn live_allocations() {
+        let alloc = CheckedAllocator::new(Global);
+        let layouts = [
+            Layout::new::<[u8; 8]>(),
+            Layout::new::<[u8; 16]>(),
+            Layout::new::<[u8; 24]>(),
+        ];
+        let blocks: alloc::vec::Vec<_> = layouts
+            .iter()
+            .map(|&layout| alloc.allocate(layout).unwrap().cast::<u8>())
+            .collect();
+        unsafe { alloc.deallocate(blocks[1], layouts[1]) };
+        let mut live: alloc::vec::Vec<_> = alloc.live_allocations().collect();
+        live.sort_by_key(|&(_, layout)| layout.size());
+        assert_eq!(live, [(blocks[0], layouts[0]), (blocks[2], layouts[2])],);
+        unsafe {
+            alloc.deallocate(blocks[0], layouts[0]);
+            alloc.deallocate(blocks[2], layouts[2]);
+        }
+        assert_eq!(alloc.live_allocations().count(), 0);
+    }
+
+    #[test]
+    fn fitting() {
+        use crate::RoundLen;
+
+        // Blocks are 32 bytes, so any size from the requested one to 32
+        // fits.
+        let alloc = CheckedAllocator::new(RoundLen::<_, 32>(Global));
+        let small = Layout::new::<[u8; 10]>();
+        let full = Layout::new::<[u8; 32]>();
+        let large = Layout::new::<[u8; 40]>();
+        let ptr = alloc.allocate(small).unwrap();
+        unsafe {
+            let ptr = alloc.grow(ptr.cast(), full, large).unwrap();
+            alloc.deallocate(ptr.cast(), Layout::new::<[u8; 64]>());
+        }
+        let ptr = alloc.allocate(small).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), small) };
+        assert_eq!(alloc.live_count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "layout mismatch")]
+    fn too_small() {
+        use crate::RoundLen;
+
+        let alloc = CheckedAllocator::new(RoundLen::<_, 32>(Global));
+        let ptr = alloc.allocate(Layout::new::<[u8; 10]>()).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), Layout::new::<[u8; 8]>()) };
+    }
+
+    #[test]
+    #[should_panic(expected = "layout mismatch")]
+    fn size_mismatch() {
+        let alloc = CheckedAllocator::new(Global);
+        let ptr = alloc.allocate(Layout::new::<[u8; 8]>()).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), Layout::new::<[u8; 16]>()) };
+    }
+
+    #[test]
+    #[should_panic(expected = "layout mismatch")]
+    fn align_mismatch() {
+        let alloc = CheckedAllocator::new(Global);
+        let ptr = alloc.allocate(Layout::new::<u64>()).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), Layout::new::<[u8; 8]>()) };
+    }
+
+    #[test]
+    #[should_panic(expected = "double free")]
+    fn double_free() {
+        let alloc = CheckedAllocator::new(Global);
+        let layout = Layout::new::<u64>();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe {
+            alloc.deallocate(ptr.cast(), layout);
+            alloc.deallocate(ptr.cast(), layout);
+        }
+    }
+
+    #[test]
+    fn reuse_after_free() {
+        let alloc = CheckedAllocator::new(Global);
+        let layout = Layout::new::<u64>();
+        for _ in 0..10 {
+            let ptr = alloc.allocate(layout).unwrap();
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+        }
+        assert_eq!(alloc.live_count(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "not allocated by this allocator")]
+    fn foreign() {
+        let alloc = CheckedAllocator::new(Global);
+        let layout = Layout::new::<u64>();
+        let ptr = Global.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+    }
+
+    #[test]
+    fn deallocate_unsized() {
+        let alloc = CheckedAllocator::new(Global);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate_unsized(ptr.cast(), 8) };
+        assert_eq!(alloc.live_count(), 0);
+    }
+}
@@ -0,0 +1,284 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::ptr::NonNull;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// The number of recent allocations remembered by a [`ChurnDetector`].
+const WINDOW: usize = 32;
+
+/// The maximum number of sizes listed in a [`ChurnReport`].
+const REPORT_LEN: usize = 8;
+
+/// An allocator that forwards to `A`, detecting blocks that are freed soon
+/// after they’re allocated.
+///
+/// The most recent allocations are kept in a small ring buffer. A block
+/// freed while it’s still there, i.e., before many more allocations have
+/// been made, counts as churn for its size. Sizes with a lot of churn come
+/// from short-lived allocations, such as temporary buffers in a loop, which
+/// would likely benefit from pooling or reuse. [`Self::churn_report`] lists
+/// them.
+///
+/// Blocks that are grown or shrunk aren’t counted as churn.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+#[derive(Debug, Default)]
+pub struct ChurnDetector<A> {
+    inner: A,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// The addresses and sizes of recent allocations. Entries are cleared
+    /// when their blocks are freed.
+    recent: [Option<(usize, usize)>; WINDOW],
+    /// The index in `recent` of the next allocation.
+    next: usize,
+    /// The counts for each size.
+    sizes: BTreeMap<usize, SizeChurn>,
+}
+
+/// Churn statistics for allocations of one size.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SizeChurn {
+    /// The size of the allocations, in bytes.
+    pub size: usize,
+    /// The number of allocations.
+    pub allocations: usize,
+    /// The number of allocations that were freed soon after being made.
+    pub churned: usize,
+}
+
+impl SizeChurn {
+    /// Returns the fraction of allocations that were freed soon after being
+    /// made, from 0 to 1.
+    pub fn score(&self) -> f64 {
+        if self.allocations == 0 {
+            return 0.0;
+        }
+        self.churned as f64 / self.allocations as f64
+    }
+}
+
+/// The sizes with the most churn, as returned by
+/// [`ChurnDetector::churn_report`].
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChurnReport {
+    /// The sizes with any churn, with the most churned first.
+    pub sizes: Vec<SizeChurn>,
+}
+
+impl<A> ChurnDetector<A> {
+    /// Creates a new [`ChurnDetector`] that allocates from `inner`.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Returns the (up to eight) sizes with the most churn so far.
+    pub fn churn_report(&self) -> ChurnReport {
+        let mut sizes: Vec<_> = self
+            .lock()
+            .sizes
+            .values()
+            .copied()
+            .filter(|size| size.churned > 0)
+            .collect();
+        sizes.sort_by_key(|size| Reverse(size.churned));
+        sizes.truncate(REPORT_LEN);
+        ChurnReport {
+            sizes,
+        }
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // A panic while holding the lock can't leave the state inconsistent.
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(
+        &self,
+        result: Result<NonNull<[u8]>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if let Ok(block) = result {
+            let size = layout.size();
+            let mut state = self.lock();
+            let next = state.next;
+            state.recent[next] =
+                Some((block.as_ptr() as *mut u8 as usize, size));
+            state.next = (next + 1) % WINDOW;
+            let entry = state.sizes.entry(size).or_insert(SizeChurn {
+                size,
+                ..SizeChurn::default()
+            });
+            entry.allocations += 1;
+        }
+        result
+    }
+
+    /// Removes `ptr` from the recent allocations, returning its index and
+    /// entry if it was there.
+    fn forget(
+        state: &mut State,
+        ptr: NonNull<u8>,
+    ) -> Option<(usize, (usize, usize))> {
+        let addr = ptr.as_ptr() as usize;
+        let index =
+            state.recent.iter().position(|r| r.map(|r| r.0) == Some(addr))?;
+        Some((index, state.recent[index].take()?))
+    }
+
+    /// Grows or shrinks the block at `ptr` with `f`. A resized block no
+    /// longer counts as a recent allocation.
+    ///
+    /// `ptr` is forgotten before calling `f`: if the block moves, `A` frees
+    /// the old address, which another thread could then be given and record
+    /// before this call resumes. If `f` fails, the entry is restored.
+    fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        f: impl FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let forgotten = Self::forget(&mut self.lock(), ptr);
+        let result = f();
+        if let (Err(_), Some((index, entry))) = (result, forgotten) {
+            let mut state = self.lock();
+            // The slot may have been reused in the meantime.
+            if state.recent[index].is_none() {
+                state.recent[index] = Some(entry);
+            }
+        }
+        result
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for ChurnDetector<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(self.inner.allocate(layout), layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record(self.inner.allocate_zeroed(layout), layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut state = self.lock();
+        if Self::forget(&mut state, ptr).is_some() {
+            if let Some(size) = state.sizes.get_mut(&layout.size()) {
+                size.churned += 1;
+            }
+        }
+        drop(state);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.grow(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.shrink(ptr, old_layout, new_layout) }
+        })
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+
+    let alloc = ChurnDetector::new(Global);
+    let temp = Layout::new::<[u8; 48]>();
+    let kept = Layout::new::<[u8; 16]>();
+    let mut blocks = Vec::new();
+    for _ in 0..100 {
+        let ptr = alloc.allocate(temp).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), temp) };
+        blocks.push(alloc.allocate(kept).unwrap());
+    }
+
+    // Freed long after they were allocated, so not churn.
+    for ptr in blocks.drain(..WINDOW * 2) {
+        unsafe { alloc.deallocate(ptr.cast(), kept) };
+    }
+
+    let report = alloc.churn_report();
+    assert_eq!(report.sizes.len(), 1);
+    let churn = report.sizes[0];
+    assert_eq!(churn.size, 48);
+    assert_eq!(churn.allocations, 100);
+    assert!(churn.score() > 0.99);
+
+    // Blocks still in the window do count.
+    for ptr in blocks {
+        unsafe { alloc.deallocate(ptr.cast(), kept) };
+    }
+    let report = alloc.churn_report();
+    assert_eq!(report.sizes.len(), 2);
+    assert_eq!(report.sizes[1].size, 16);
+    assert!(report.sizes[1].score() < 0.5);
+}
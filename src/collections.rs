@@ -0,0 +1,359 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Allocator-generic collections that work on stable Rust.
+
+use super::{Allocator, Global};
+use alloc::alloc::{Layout, handle_alloc_error};
+use core::fmt::{self, Debug, Formatter};
+use core::marker::PhantomData;
+use core::mem::{self, MaybeUninit};
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+use core::slice;
+
+/// The smallest non-zero capacity of a [`Vec`] with non-zero-sized
+/// elements.
+const MIN_CAPACITY: usize = 4;
+
+/// Owns a buffer of `T` allocated from `A`, without tracking which elements
+/// are initialized.
+struct RawVec<T, A: Allocator> {
+    ptr: NonNull<T>,
+    cap: usize,
+    alloc: A,
+    phantom: PhantomData<T>,
+}
+
+impl<T, A: Allocator> RawVec<T, A> {
+    const IS_ZST: bool = mem::size_of::<T>() == 0;
+
+    fn new_in(alloc: A) -> Self {
+        Self {
+            ptr: NonNull::dangling(),
+            cap: if Self::IS_ZST {
+                usize::MAX
+            } else {
+                0
+            },
+            alloc,
+            phantom: PhantomData,
+        }
+    }
+
+    fn layout(cap: usize) -> Layout {
+        Layout::array::<T>(cap).expect("capacity overflow")
+    }
+
+    fn current(&self) -> Option<(NonNull<u8>, Layout)> {
+        if Self::IS_ZST || self.cap == 0 {
+            None
+        } else {
+            Some((self.ptr.cast(), Self::layout(self.cap)))
+        }
+    }
+
+    /// Sets the buffer to `block`, which must have been allocated with a
+    /// layout for at least `min_cap` elements.
+    fn set_block(&mut self, block: NonNull<[u8]>, min_cap: usize) {
+        // SAFETY: Allocators always return pointers to valid memory.
+        let len =
+            unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len();
+        self.ptr = block.cast();
+        self.cap = (len / mem::size_of::<T>()).max(min_cap);
+    }
+
+    /// Ensures there is space for at least `len + additional` elements.
+    fn reserve(&mut self, len: usize, additional: usize) {
+        let required = len.checked_add(additional).expect("capacity overflow");
+        if required <= self.cap {
+            return;
+        }
+        let cap = required.max(self.cap * 2).max(MIN_CAPACITY);
+        let layout = Self::layout(cap);
+        let result = match self.current() {
+            // SAFETY: `ptr` is currently allocated with `old`, and `layout`
+            // is larger.
+            Some((ptr, old)) => unsafe { self.alloc.grow(ptr, old, layout) },
+            None => self.alloc.allocate(layout),
+        };
+        match result {
+            Ok(block) => self.set_block(block, cap),
+            Err(_) => handle_alloc_error(layout),
+        }
+    }
+
+    /// Shrinks the buffer to hold exactly `cap` elements.
+    fn shrink_to(&mut self, cap: usize) {
+        let (ptr, old) = match self.current() {
+            Some(current) if cap < self.cap => current,
+            _ => return,
+        };
+        if cap == 0 {
+            // SAFETY: `ptr` is currently allocated with `old`.
+            unsafe { self.alloc.deallocate(ptr, old) };
+            self.ptr = NonNull::dangling();
+            self.cap = 0;
+            return;
+        }
+        let layout = Self::layout(cap);
+        // SAFETY: `ptr` is currently allocated with `old`, and `layout` is
+        // smaller.
+        match unsafe { self.alloc.shrink(ptr, old, layout) } {
+            Ok(block) => self.set_block(block, cap),
+            Err(_) => handle_alloc_error(layout),
+        }
+    }
+}
+
+impl<T, A: Allocator> Drop for RawVec<T, A> {
+    fn drop(&mut self) {
+        if let Some((ptr, layout)) = self.current() {
+            // SAFETY: `ptr` is currently allocated with `layout`.
+            unsafe { self.alloc.deallocate(ptr, layout) };
+        }
+    }
+}
+
+/// A contiguous growable array that allocates from `A`.
+///
+/// This is a minimal version of the standard library’s [`Vec`], which can’t
+/// use custom allocators on stable Rust.
+///
+/// [`Vec`]: alloc::vec::Vec
+pub struct Vec<T, A: Allocator = Global> {
+    buf: RawVec<T, A>,
+    len: usize,
+}
+
+impl<T> Vec<T> {
+    /// Creates a new, empty vector that allocates from [`Global`].
+    pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+}
+
+impl<T> Default for Vec<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T, A: Allocator> Vec<T, A> {
+    /// Creates a new, empty vector that allocates from `alloc`.
+    ///
+    /// This doesn’t allocate until elements are pushed.
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            buf: RawVec::new_in(alloc),
+            len: 0,
+        }
+    }
+
+    /// Creates a new, empty vector with space for at least `capacity`
+    /// elements.
+    pub fn with_capacity_in(capacity: usize, alloc: A) -> Self {
+        let mut vec = Self::new_in(alloc);
+        vec.reserve(capacity);
+        vec
+    }
+
+    /// Returns the allocator used by this vector.
+    pub fn allocator(&self) -> &A {
+        &self.buf.alloc
+    }
+
+    /// Returns the number of elements in the vector.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether the vector is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the number of elements the vector can hold without
+    /// reallocating.
+    pub fn capacity(&self) -> usize {
+        self.buf.cap
+    }
+
+    /// Ensures there is space for at least `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        self.buf.reserve(self.len, additional);
+    }
+
+    /// Shrinks the capacity of the vector as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.buf.shrink_to(self.len);
+    }
+
+    /// Appends an element to the end of the vector.
+    pub fn push(&mut self, value: T) {
+        if self.len == self.buf.cap {
+            self.reserve(1);
+        }
+        // SAFETY: `self.len` is less than the capacity.
+        unsafe { self.buf.ptr.as_ptr().add(self.len).write(value) };
+        self.len += 1;
+    }
+
+    /// Removes and returns the last element of the vector, if any.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        // SAFETY: The element at `self.len` was initialized, and is now
+        // considered uninitialized.
+        Some(unsafe { self.buf.ptr.as_ptr().add(self.len).read() })
+    }
+
+    /// Shortens the vector to `len` elements, dropping the rest.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+        let tail = ptr::slice_from_raw_parts_mut(
+            // SAFETY: `len` is less than `self.len`.
+            unsafe { self.buf.ptr.as_ptr().add(len) },
+            self.len - len,
+        );
+        self.len = len;
+        // SAFETY: The elements in `tail` were initialized, and are now
+        // considered uninitialized.
+        unsafe { ptr::drop_in_place(tail) };
+    }
+
+    /// Removes all elements from the vector.
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+}
+
+impl<T, A: Allocator> Drop for Vec<T, A> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T, A: Allocator> Deref for Vec<T, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: The first `self.len` elements are initialized.
+        unsafe { slice::from_raw_parts(self.buf.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T, A: Allocator> DerefMut for Vec<T, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: The first `self.len` elements are initialized.
+        unsafe { slice::from_raw_parts_mut(self.buf.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Debug, A: Allocator> Debug for Vec<T, A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        Debug::fmt(&**self, f)
+    }
+}
+
+// SAFETY: The vector owns its elements and allocator.
+unsafe impl<T: Send, A: Allocator + Send> Send for Vec<T, A> {}
+
+// SAFETY: The vector owns its elements and allocator, and `&Vec` provides
+// only shared access to them.
+unsafe impl<T: Sync, A: Allocator + Sync> Sync for Vec<T, A> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Bump;
+    use core::cell::Cell;
+    use core::mem::MaybeUninit;
+
+    #[test]
+    fn push_pop() {
+        let mut vec = Vec::new();
+        for i in 0..1000_u32 {
+            vec.push(i);
+            assert!(vec.capacity() >= vec.len());
+        }
+        assert_eq!(vec.len(), 1000);
+        assert!(vec.iter().copied().eq(0..1000));
+        for i in (500..1000).rev() {
+            assert_eq!(vec.pop(), Some(i));
+        }
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), 500);
+        assert!(vec.iter().copied().eq(0..500));
+        vec.clear();
+        vec.shrink_to_fit();
+        assert_eq!(vec.capacity(), 0);
+        assert_eq!(vec.pop(), None);
+    }
+
+    #[test]
+    fn zero_sized() {
+        let mut vec = Vec::new();
+        assert_eq!(vec.capacity(), usize::MAX);
+        for _ in 0..1000 {
+            vec.push(());
+        }
+        assert_eq!(vec.len(), 1000);
+        vec.shrink_to_fit();
+        assert_eq!(vec.pop(), Some(()));
+        assert_eq!(vec.len(), 999);
+    }
+
+    #[test]
+    fn bump() {
+        let mut buf = [MaybeUninit::uninit(); 4096];
+        let bump = Bump::new(&mut buf);
+        let mut vec = Vec::new_in(&bump);
+        for i in 0..100_u64 {
+            vec.push(i);
+        }
+        vec[0] = 100;
+        assert_eq!(vec[..3], [100, 1, 2]);
+    }
+
+    #[test]
+    fn drop_elements() {
+        struct DropCounter<'a>(&'a Cell<usize>);
+
+        impl Drop for DropCounter<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let count = Cell::new(0);
+        let mut vec = Vec::with_capacity_in(10, Global);
+        assert!(vec.capacity() >= 10);
+        for _ in 0..20 {
+            vec.push(DropCounter(&count));
+        }
+        vec.truncate(15);
+        assert_eq!(count.get(), 5);
+        drop(vec);
+        assert_eq!(count.get(), 20);
+    }
+}
@@ -0,0 +1,259 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt::{self, Debug, Formatter};
+use core::ptr::NonNull;
+
+/// An allocator that forwards to `A`, but defers deallocations, forwarding
+/// them to `A` in batches.
+///
+/// Deallocated blocks are recorded in a buffer, and once it holds
+/// `threshold` blocks, they are all deallocated via `A`. This trades memory
+/// (blocks stay allocated until the batch is flushed) for throughput when
+/// `A`’s deallocation is expensive, like when it takes a lock or makes a
+/// system call. Pending blocks can also be deallocated early with
+/// [`Self::flush`], and are always deallocated when this allocator is
+/// dropped.
+///
+/// The buffer is allocated from the global allocator when this allocator is
+/// created, with room for exactly `threshold` blocks, so recording a
+/// deallocation never allocates. Flushing doesn’t hold a borrow of the
+/// buffer while calling `A`, so `A` may allocate or deallocate through this
+/// allocator while a batch is being flushed.
+pub struct DeferredFree<A: Allocator> {
+    alloc: A,
+    threshold: usize,
+    pending: RefCell<Vec<(NonNull<u8>, Layout)>>,
+}
+
+impl<A: Allocator> DeferredFree<A> {
+    /// Creates a new allocator that forwards deallocations to `alloc` in
+    /// batches of `threshold` blocks.
+    ///
+    /// A threshold of 0 is treated as 1, which forwards every deallocation
+    /// immediately.
+    pub fn new(alloc: A, threshold: usize) -> Self {
+        let threshold = threshold.max(1);
+        Self {
+            alloc,
+            threshold,
+            pending: RefCell::new(Vec::with_capacity(threshold)),
+        }
+    }
+
+    /// Returns the number of deallocated blocks that haven’t been forwarded
+    /// to `A` yet.
+    pub fn pending(&self) -> usize {
+        self.pending.borrow().len()
+    }
+
+    /// Deallocates all pending blocks via `A`.
+    pub fn flush(&self) {
+        loop {
+            // The borrow ends before `A` is called.
+            let next = self.pending.borrow_mut().pop();
+            let (ptr, layout) = match next {
+                Some(block) => block,
+                None => return,
+            };
+            // SAFETY: `ptr` was passed to `Self::deallocate` with `layout`,
+            // so it was allocated via `A`, and it has been removed from the
+            // buffer, so it is deallocated only once.
+            unsafe { self.alloc.deallocate(ptr, layout) };
+        }
+    }
+}
+
+impl<A: Allocator> Drop for DeferredFree<A> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+impl<A: Allocator + Debug> Debug for DeferredFree<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredFree")
+            .field("alloc", &self.alloc)
+            .field("threshold", &self.threshold)
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+// SAFETY: The pending blocks were allocated via `A` and are owned by this
+// allocator, so it can be sent to another thread if `A` can.
+unsafe impl<A: Allocator + Send> Send for DeferredFree<A> {}
+
+// SAFETY: This impl forwards to `A`. Blocks are deallocated via `A` exactly
+// once, either when their batch is flushed or when this allocator is
+// dropped, so they stay valid until then. Growing and shrinking forward to
+// `A` directly.
+unsafe impl<A: Allocator> Allocator for DeferredFree<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc.allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.alloc.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let full = {
+            let mut pending = self.pending.borrow_mut();
+            pending.push((ptr, layout));
+            pending.len() >= self.threshold
+        };
+        if full {
+            self.flush();
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.alloc.grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.alloc.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.alloc.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[test]
+fn test() {
+    use crate::{Global, TrackingAllocator};
+
+    let backing = TrackingAllocator::new(Global);
+    let alloc = DeferredFree::new(&backing, 4);
+    let layout = Layout::new::<[u64; 2]>();
+    let blocks: Vec<_> =
+        (0..6).map(|_| alloc.allocate(layout).unwrap().cast()).collect();
+    for (i, &ptr) in blocks[..3].iter().enumerate() {
+        unsafe { alloc.deallocate(ptr, layout) };
+        assert_eq!(alloc.pending(), i + 1);
+        assert_eq!(backing.stats().deallocations, 0);
+    }
+    unsafe { alloc.deallocate(blocks[3], layout) };
+    assert_eq!(alloc.pending(), 0);
+    assert_eq!(backing.stats().deallocations, 4);
+
+    unsafe { alloc.deallocate(blocks[4], layout) };
+    alloc.flush();
+    assert_eq!(backing.stats().deallocations, 5);
+    unsafe { alloc.deallocate(blocks[5], layout) };
+    drop(alloc);
+    assert_eq!(backing.stats().deallocations, 6);
+    assert_eq!(backing.stats().current, 0);
+}
+
+#[test]
+fn reentrant() {
+    use crate::{Global, TrackingAllocator};
+    use core::cell::Cell;
+
+    /// Deallocates one of `blocks` through `outer` every time it
+    /// deallocates a block, until there are none left.
+    struct Reentrant {
+        inner: TrackingAllocator<Global>,
+        outer: Cell<*const DeferredFree<&'static Reentrant>>,
+        blocks: RefCell<Vec<NonNull<u8>>>,
+        max_pending: Cell<usize>,
+    }
+
+    // SAFETY: This impl forwards to `inner`.
+    unsafe impl Allocator for Reentrant {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.inner.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.deallocate(ptr, layout) };
+            // SAFETY: `outer` is set before anything is deallocated, and is
+            // dropped after its last deallocation.
+            let outer = unsafe { &*self.outer.get() };
+            let next = self.blocks.borrow_mut().pop();
+            if let Some(block) = next {
+                // SAFETY: `blocks` were allocated via `outer` with `layout`.
+                unsafe { outer.deallocate(block, layout) };
+            }
+            let pending = outer.pending();
+            self.max_pending.set(self.max_pending.get().max(pending));
+        }
+    }
+
+    let backing = Reentrant {
+        inner: TrackingAllocator::new(Global),
+        outer: Cell::new(core::ptr::null()),
+        blocks: RefCell::new(Vec::new()),
+        max_pending: Cell::new(0),
+    };
+    let alloc = DeferredFree::new(&backing, 2);
+    let outer: *const DeferredFree<&Reentrant> = &alloc;
+    backing.outer.set(outer.cast());
+    let capacity = alloc.pending.borrow().capacity();
+    let layout = Layout::new::<u64>();
+    let blocks: Vec<_> =
+        (0..6).map(|_| alloc.allocate(layout).unwrap().cast()).collect();
+    backing.blocks.borrow_mut().extend_from_slice(&blocks[3..]);
+
+    // Flushing deallocates every extra block through `alloc` while the
+    // batch is still being flushed.
+    unsafe { alloc.deallocate(blocks[0], layout) };
+    unsafe { alloc.deallocate(blocks[1], layout) };
+    assert_eq!(alloc.pending(), 0);
+    assert!(backing.blocks.borrow().is_empty());
+    assert_eq!(backing.inner.stats().deallocations, 5);
+
+    unsafe { alloc.deallocate(blocks[2], layout) };
+    assert_eq!(alloc.pending.borrow().capacity(), capacity);
+    drop(alloc);
+    assert!(backing.max_pending.get() <= 2);
+    assert_eq!(backing.inner.stats().deallocations, 6);
+    assert_eq!(backing.inner.stats().current, 0);
+}
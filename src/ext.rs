@@ -0,0 +1,1121 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, CAllocCallbacks};
+use alloc::alloc::{Layout, handle_alloc_error};
+use alloc::boxed::Box;
+use core::mem::{self, MaybeUninit};
+use core::ptr::{self, NonNull};
+
+/// Convenience methods for all [`Allocator`]s.
+///
+/// These methods can’t be part of [`Allocator`] itself, because [`Allocator`]
+/// must match the unstable trait in the standard library, which it may be
+/// re-exported from (see [Crate features](crate#crate-features)). This trait
+/// is implemented for every [`Allocator`].
+pub trait AllocatorExt: Allocator {
+    /// Like [`Allocator::allocate`], but calls [`handle_alloc_error`] on
+    /// failure instead of returning an error.
+    ///
+    /// By default, [`handle_alloc_error`] aborts the process, so this is
+    /// suitable for infallible allocation APIs.
+    fn alloc_or_abort(&self, layout: Layout) -> NonNull<[u8]> {
+        match self.allocate(layout) {
+            Ok(ptr) => ptr,
+            Err(_) => handle_alloc_error(layout),
+        }
+    }
+
+    /// Allocates a new block with the same layout as an existing one, and
+    /// copies the contents of the existing block into it.
+    ///
+    /// The original block is not deallocated.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block of memory currently allocated via this
+    /// allocator, and `layout` must fit that block (see
+    /// [`alloc::alloc::Allocator`]). The first `layout.size()` bytes of the
+    /// block must be initialized.
+    unsafe fn duplicate(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = self.allocate(layout)?;
+        // SAFETY: Checked by caller. `new` is a distinct allocation, so it
+        // can't overlap `ptr`.
+        unsafe {
+            (new.as_ptr() as *mut u8)
+                .copy_from_nonoverlapping(ptr.as_ptr(), layout.size());
+        }
+        Ok(new)
+    }
+
+    /// Like [`Allocator::grow`], but grows the block to at least twice its
+    /// old size, so that repeatedly growing a block by small amounts takes
+    /// amortized constant time.
+    ///
+    /// The new size is the larger of `new_layout.size()` and double
+    /// `old_layout.size()`. The length of the returned slice reflects this
+    /// larger size.
+    ///
+    /// # Safety
+    ///
+    /// See [`alloc::alloc::Allocator::grow`].
+    unsafe fn grow_amortized(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let layout = old_layout
+            .size()
+            .checked_mul(2)
+            .filter(|&size| size > new_layout.size())
+            .and_then(|size| {
+                Layout::from_size_align(size, new_layout.align()).ok()
+            })
+            .unwrap_or(new_layout);
+        // SAFETY: Checked by caller. `layout` is at least as large as
+        // `new_layout`.
+        unsafe { self.grow(ptr, old_layout, layout) }
+    }
+
+    /// Resizes a block to `new_layout`, like C’s `realloc`, by calling
+    /// [`Allocator::grow`] or [`Allocator::shrink`] depending on the new
+    /// size.
+    ///
+    /// If the layouts are equal, this returns `ptr` unchanged, with a length
+    /// of `new_layout.size()`. If only their alignments differ, this calls
+    /// [`Allocator::grow`], which may move the block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block of memory currently allocated via this
+    /// allocator, and `old_layout` must fit that block (see
+    /// [`alloc::alloc::Allocator`]). On success, the block must no longer be
+    /// accessed through `ptr` unless it was returned unchanged.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout == old_layout {
+            let ptr =
+                ptr::slice_from_raw_parts_mut(ptr.as_ptr(), new_layout.size());
+            // SAFETY: `ptr` came from a `NonNull`.
+            return Ok(unsafe { NonNull::new_unchecked(ptr) });
+        }
+        if new_layout.size() < old_layout.size() {
+            // SAFETY: Checked by caller. `new_layout` is smaller.
+            unsafe { self.shrink(ptr, old_layout, new_layout) }
+        } else {
+            // SAFETY: Checked by caller. `new_layout` is at least as large.
+            unsafe { self.grow(ptr, old_layout, new_layout) }
+        }
+    }
+
+    /// Splits a block into two new blocks containing the bytes before and
+    /// after offset `at`, and deallocates the original block.
+    ///
+    /// Both new blocks use `layout`’s alignment, and have sizes `at` and
+    /// `layout.size() - at`. A general allocator can’t split a block in
+    /// place, so this allocates two new blocks and copies into them. On
+    /// failure, the original block is left untouched.
+    ///
+    /// If `at` is 0 or `layout.size()`, the empty half is a dangling,
+    /// aligned pointer returned without calling the allocator, which must not
+    /// be deallocated.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `at` is greater than `layout.size()`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block of memory currently allocated via this
+    /// allocator, and `layout` must fit that block (see
+    /// [`alloc::alloc::Allocator`]). The first `layout.size()` bytes of the
+    /// block must be initialized.
+    #[allow(clippy::type_complexity)]
+    unsafe fn split_alloc(
+        &self,
+        ptr: NonNull<[u8]>,
+        layout: Layout,
+        at: usize,
+    ) -> Result<(NonNull<[u8]>, NonNull<[u8]>), AllocError> {
+        assert!(at <= layout.size(), "split offset out of bounds");
+        let ptr = ptr.as_ptr() as *mut u8;
+        let align = layout.align();
+        // SAFETY: `at` and `layout.size() - at` are no greater than
+        // `layout.size()`, so rounding them up to `align` can’t overflow
+        // `isize`.
+        let (first_layout, second_layout) = unsafe {
+            (
+                Layout::from_size_align_unchecked(at, align),
+                Layout::from_size_align_unchecked(layout.size() - at, align),
+            )
+        };
+        let first = allocate_or_dangling(self, first_layout)?;
+        let second = match allocate_or_dangling(self, second_layout) {
+            Ok(second) => second,
+            Err(e) => {
+                // SAFETY: `first` was just allocated with `first_layout`.
+                unsafe { deallocate_unless_empty(self, first, first_layout) };
+                return Err(e);
+            }
+        };
+        // SAFETY: Checked by caller. The new blocks are distinct
+        // allocations (or empty), so they can't overlap `ptr`.
+        unsafe {
+            first.as_ptr().copy_from_nonoverlapping(ptr, at);
+            second
+                .as_ptr()
+                .copy_from_nonoverlapping(ptr.add(at), layout.size() - at);
+            self.deallocate(NonNull::new_unchecked(ptr), layout);
+        }
+        let slice = |ptr: NonNull<u8>, len| {
+            let ptr = ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len);
+            // SAFETY: `ptr` came from a `NonNull`.
+            unsafe { NonNull::new_unchecked(ptr) }
+        };
+        Ok((slice(first, at), slice(second, layout.size() - at)))
+    }
+
+    /// Shrinks a block to `used` bytes, but only if that frees more than half
+    /// of it.
+    ///
+    /// This is equivalent to [`Self::shrink_if_slack_exceeds`] with a
+    /// threshold of `layout.size() / 2`.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `used` is greater than `layout.size()`.
+    ///
+    /// # Safety
+    ///
+    /// See [`alloc::alloc::Allocator::shrink`].
+    unsafe fn shrink_if_worthwhile(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        used: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            self.shrink_if_slack_exceeds(ptr, layout, used, layout.size() / 2)
+        }
+    }
+
+    /// Shrinks a block to `used` bytes, but only if the slack
+    /// (`layout.size() - used`) is greater than `threshold` bytes.
+    ///
+    /// Shrinking usually means copying the block, so it isn’t worth the cost
+    /// when it would free only a little memory. If the block isn’t shrunk,
+    /// this returns `ptr` unchanged, with a length of `layout.size()`. In
+    /// either case, the block must later be deallocated with a layout that
+    /// fits the returned length.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `used` is greater than `layout.size()`.
+    ///
+    /// # Safety
+    ///
+    /// See [`alloc::alloc::Allocator::shrink`].
+    unsafe fn shrink_if_slack_exceeds(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+        used: usize,
+        threshold: usize,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        assert!(used <= layout.size(), "used size exceeds block size");
+        if layout.size() - used <= threshold {
+            let ptr =
+                ptr::slice_from_raw_parts_mut(ptr.as_ptr(), layout.size());
+            // SAFETY: `ptr` came from a `NonNull`.
+            return Ok(unsafe { NonNull::new_unchecked(ptr) });
+        }
+        // SAFETY: `used` is no greater than `layout.size()`, so rounding it
+        // up to `layout.align()` can’t overflow `isize`.
+        let new =
+            unsafe { Layout::from_size_align_unchecked(used, layout.align()) };
+        // SAFETY: Checked by caller. `new` is smaller than `layout`.
+        unsafe { self.shrink(ptr, layout, new) }
+    }
+
+    /// Copies `len` bytes from `src` to `dst`, which must not overlap.
+    ///
+    /// This is a convenience for moving data between blocks, which never
+    /// overlap if they were allocated separately. Use
+    /// [`Self::copy_between_overlapping`] if the regions may overlap.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be valid for reads of `len` bytes, `dst` must be valid for
+    /// writes of `len` bytes, and the two regions must not overlap (see
+    /// [`ptr::copy_nonoverlapping`]).
+    unsafe fn copy_between(
+        &self,
+        src: NonNull<u8>,
+        dst: NonNull<u8>,
+        len: usize,
+    ) {
+        // SAFETY: Checked by caller.
+        unsafe { ptr::copy_nonoverlapping(src.as_ptr(), dst.as_ptr(), len) };
+    }
+
+    /// Copies `len` bytes from `src` to `dst`, which may overlap.
+    ///
+    /// # Safety
+    ///
+    /// `src` must be valid for reads of `len` bytes, and `dst` must be valid
+    /// for writes of `len` bytes (see [`ptr::copy`]).
+    unsafe fn copy_between_overlapping(
+        &self,
+        src: NonNull<u8>,
+        dst: NonNull<u8>,
+        len: usize,
+    ) {
+        // SAFETY: Checked by caller.
+        unsafe { ptr::copy(src.as_ptr(), dst.as_ptr(), len) };
+    }
+
+    /// Allocates memory for an array of `n` values of type `T`.
+    ///
+    /// Unlike computing the size manually, this returns [`AllocError`] if
+    /// `n * size_of::<T>()` overflows. The returned slice has exactly `n`
+    /// elements.
+    ///
+    /// If `T` is zero-sized or `n` is 0, this returns a dangling, aligned
+    /// pointer without calling the allocator, which must not be deallocated.
+    /// This applies to all the typed helpers in this trait.
+    fn allocate_n<T>(&self, n: usize) -> Result<NonNull<[T]>, AllocError> {
+        let layout = Layout::array::<T>(n).map_err(|_| AllocError)?;
+        let ptr = allocate_or_dangling(self, layout)?.as_ptr() as *mut T;
+        // SAFETY: `ptr` came from a `NonNull`.
+        Ok(unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(ptr, n))
+        })
+    }
+
+    /// Allocates an uninitialized 2D buffer of `rows` rows of `cols` values
+    /// of type `T`, where each row starts at a multiple of `row_align` bytes.
+    ///
+    /// Returns the base pointer and the stride: the distance in elements
+    /// between the starts of consecutive rows. The stride is the smallest
+    /// number of elements at least `cols` whose size is a multiple of
+    /// `row_align`, so row `i` starts at `ptr.add(i * stride)`. The block is
+    /// `rows * stride` elements long and aligned to `row_align` (or
+    /// `T`’s alignment, if larger).
+    ///
+    /// Returns [`AllocError`] if `row_align` isn’t a power of two, or if the
+    /// size computation overflows. Like [`Self::allocate_n`], this doesn’t
+    /// call the allocator if the block would be empty.
+    fn allocate_2d<T>(
+        &self,
+        rows: usize,
+        cols: usize,
+        row_align: usize,
+    ) -> Result<(NonNull<T>, usize), AllocError> {
+        if !row_align.is_power_of_two() {
+            return Err(AllocError);
+        }
+        // The smallest number of elements whose size is a multiple of
+        // `row_align`; `row_align / gcd(size, row_align)`.
+        let shift = mem::size_of::<T>()
+            .trailing_zeros()
+            .min(row_align.trailing_zeros());
+        let step = row_align >> shift;
+        let stride = cols
+            .checked_add(step - 1)
+            .map(|n| n & !(step - 1))
+            .ok_or(AllocError)?;
+        let len = rows.checked_mul(stride).ok_or(AllocError)?;
+        let layout = Layout::array::<T>(len)
+            .and_then(|layout| layout.align_to(row_align))
+            .map_err(|_| AllocError)?;
+        Ok((allocate_or_dangling(self, layout)?.cast(), stride))
+    }
+
+    /// Like [`Allocator::allocate`], but returns the base pointer and the
+    /// usable size of the block separately.
+    ///
+    /// The usable size may be larger than `layout.size()`, so containers can
+    /// record the block’s true capacity right away.
+    fn allocate_with_capacity(
+        &self,
+        layout: Layout,
+    ) -> Result<(NonNull<u8>, usize), AllocError> {
+        let block = self.allocate(layout)?;
+        // SAFETY: Allocators always return pointers to valid memory.
+        let len =
+            unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len();
+        Ok((block.cast(), len))
+    }
+
+    /// Allocates memory for a value of type `T`, and initializes it with
+    /// `init()`.
+    ///
+    /// If allocation fails, `init` is never called. If `init` panics, the
+    /// memory is deallocated before the panic propagates. Like
+    /// [`Self::allocate_n`], this doesn’t call the allocator if `T` is
+    /// zero-sized.
+    fn allocate_with<T, F>(&self, init: F) -> Result<NonNull<T>, AllocError>
+    where
+        F: FnOnce() -> T,
+    {
+        /// Deallocates the block if `init` panics.
+        struct Guard<'a, A: Allocator + ?Sized> {
+            alloc: &'a A,
+            ptr: NonNull<u8>,
+            layout: Layout,
+        }
+
+        impl<A: Allocator + ?Sized> Drop for Guard<'_, A> {
+            fn drop(&mut self) {
+                // SAFETY: `self.ptr` was allocated with `self.layout`.
+                unsafe {
+                    deallocate_unless_empty(self.alloc, self.ptr, self.layout);
+                }
+            }
+        }
+
+        let layout = Layout::new::<T>();
+        let ptr = allocate_or_dangling(self, layout)?.cast::<T>();
+        let guard = Guard {
+            alloc: self,
+            ptr: ptr.cast(),
+            layout,
+        };
+        let value = init();
+        mem::forget(guard);
+        // SAFETY: `ptr` was allocated with the layout of `T`.
+        unsafe { ptr.as_ptr().write(value) };
+        Ok(ptr)
+    }
+
+    /// Allocates an array of `len` values of type `T`, initializing the
+    /// element at each index `i` with `init(i)`.
+    ///
+    /// If allocation fails, `init` is never called. If `init` panics, the
+    /// elements initialized so far are dropped and the memory is deallocated
+    /// before the panic propagates.
+    fn allocate_slice_with<T, F>(
+        &self,
+        len: usize,
+        mut init: F,
+    ) -> Result<NonNull<[T]>, AllocError>
+    where
+        F: FnMut(usize) -> T,
+    {
+        /// Cleans up a partially initialized array if `init` panics.
+        struct Guard<'a, T, A: Allocator + ?Sized> {
+            alloc: &'a A,
+            ptr: NonNull<T>,
+            layout: Layout,
+            initialized: usize,
+        }
+
+        impl<T, A: Allocator + ?Sized> Drop for Guard<'_, T, A> {
+            fn drop(&mut self) {
+                // SAFETY: The first `self.initialized` elements were
+                // initialized, and `self.ptr` was allocated with
+                // `self.layout`.
+                unsafe {
+                    ptr::drop_in_place(ptr::slice_from_raw_parts_mut(
+                        self.ptr.as_ptr(),
+                        self.initialized,
+                    ));
+                    deallocate_unless_empty(
+                        self.alloc,
+                        self.ptr.cast(),
+                        self.layout,
+                    );
+                }
+            }
+        }
+
+        let layout = Layout::array::<T>(len).map_err(|_| AllocError)?;
+        let slice = self.allocate_n::<T>(len)?;
+        let mut guard = Guard {
+            alloc: self,
+            ptr: slice.cast::<T>(),
+            layout,
+            initialized: 0,
+        };
+        while guard.initialized < len {
+            let value = init(guard.initialized);
+            // SAFETY: `guard.initialized` is less than `len`.
+            unsafe { guard.ptr.as_ptr().add(guard.initialized).write(value) };
+            guard.initialized += 1;
+        }
+        mem::forget(guard);
+        Ok(slice)
+    }
+
+    /// Returns C-compatible `malloc`, `free`, and `realloc` functions that
+    /// allocate from this allocator.
+    ///
+    /// See [`CAllocCallbacks`] for details, including the per-block overhead.
+    fn as_c_callbacks(&'static self) -> CAllocCallbacks
+    where
+        Self: Sized + Sync,
+    {
+        CAllocCallbacks::new(self)
+    }
+
+    /// Moves `value` into a new allocation that is never freed, returning a
+    /// reference to it that lasts for the rest of the program.
+    ///
+    /// This is the allocator-generic analog of [`Box::leak`], useful for
+    /// initializing global singletons at startup. The memory is leaked
+    /// intentionally: nothing will deallocate it or drop `value`. This
+    /// requires a `'static` reference to the allocator, so the allocator
+    /// can’t be dropped (which could free the memory) while the returned
+    /// reference is in use.
+    ///
+    /// Like [`Self::alloc_or_abort`], this calls [`handle_alloc_error`] if
+    /// allocation fails.
+    #[allow(clippy::mut_from_ref)]
+    fn leak_alloc<T>(&'static self, value: T) -> &'static mut T
+    where
+        Self: Sized,
+    {
+        match self.allocate_with(|| value) {
+            // SAFETY: The block holds an initialized `T`, and is never
+            // deallocated or accessed other than through this reference.
+            // The allocator lives for the rest of the program.
+            Ok(ptr) => unsafe { &mut *ptr.as_ptr() },
+            Err(_) => handle_alloc_error(Layout::new::<T>()),
+        }
+    }
+
+    /// Bundles a block with this allocator, returning a token that
+    /// deallocates the block when [called](Deallocator::call).
+    ///
+    /// This is convenient for APIs that register cleanup to run later, such
+    /// as event loops: `move || dealloc.call()` is a closure that frees the
+    /// block. The token is consumed when called, so it can free the block at
+    /// most once, and it borrows the allocator, so it can’t outlive it.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block of memory currently allocated via this
+    /// allocator, and `layout` must fit that block. If the token is called,
+    /// the block must not be used afterwards, nor deallocated by other
+    /// means.
+    unsafe fn deallocator(
+        &self,
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> Deallocator<'_, Self> {
+        Deallocator {
+            alloc: self,
+            ptr,
+            layout,
+        }
+    }
+
+    /// Boxes this allocator into a trait object, erasing its type.
+    ///
+    /// [`Allocator`] is implemented for [`Box<dyn Allocator>`](Box), so this
+    /// allows allocators of different types to be stored together, e.g., in
+    /// a registry.
+    fn into_dyn(self) -> Box<dyn Allocator>
+    where
+        Self: Sized + 'static,
+    {
+        Box::new(self)
+    }
+}
+
+impl<A: Allocator + ?Sized> AllocatorExt for A {}
+
+/// Allocates a block for `layout`, or returns a dangling pointer aligned to
+/// `layout` if its size is zero.
+fn allocate_or_dangling<A: Allocator + ?Sized>(
+    alloc: &A,
+    layout: Layout,
+) -> Result<NonNull<u8>, AllocError> {
+    if layout.size() == 0 {
+        // SAFETY: Alignments are never zero.
+        return Ok(unsafe {
+            NonNull::new_unchecked(layout.align() as *mut u8)
+        });
+    }
+    Ok(alloc.allocate(layout)?.cast())
+}
+
+/// Deallocates a block returned by [`allocate_or_dangling`].
+///
+/// # Safety
+///
+/// `ptr` must have been returned by [`allocate_or_dangling`] with `alloc`
+/// and `layout`, and not yet deallocated.
+unsafe fn deallocate_unless_empty<A: Allocator + ?Sized>(
+    alloc: &A,
+    ptr: NonNull<u8>,
+    layout: Layout,
+) {
+    if layout.size() != 0 {
+        // SAFETY: Checked by caller.
+        unsafe { alloc.deallocate(ptr, layout) };
+    }
+}
+
+/// Deallocates a block when called.
+///
+/// This is returned by [`AllocatorExt::deallocator`]. Dropping it without
+/// calling it leaves the block allocated.
+#[derive(Debug)]
+#[must_use = "the block is deallocated only if this is called"]
+pub struct Deallocator<'a, A: Allocator + ?Sized> {
+    alloc: &'a A,
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl<A: Allocator + ?Sized> Deallocator<'_, A> {
+    /// Deallocates the block.
+    pub fn call(self) {
+        // SAFETY: Checked by the caller of `AllocatorExt::deallocator`.
+        unsafe { self.alloc.deallocate(self.ptr, self.layout) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+    use core::cell::Cell;
+
+    /// `NonNull<[T]>::len` needs Rust 1.63.
+    fn len<T>(block: NonNull<[T]>) -> usize {
+        // SAFETY: Test blocks are valid for their length.
+        unsafe { &*(block.as_ptr() as *const [MaybeUninit<T>]) }.len()
+    }
+
+    #[derive(Default)]
+    struct Counting {
+        allocations: Cell<usize>,
+    }
+
+    unsafe impl Allocator for Counting {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.allocations.set(self.allocations.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+
+    /// Grows a block one byte at a time up to 1000 bytes, returning the
+    /// number of allocations performed.
+    fn grow_bytewise(amortized: bool) -> usize {
+        let alloc = Counting::default();
+        let mut layout = Layout::new::<u8>();
+        let mut block = alloc.allocate(layout).unwrap();
+        unsafe {
+            (block.as_ptr() as *mut u8).write(0);
+            for i in 1..1000 {
+                if len(block) <= i {
+                    let new = Layout::array::<u8>(i + 1).unwrap();
+                    block = if amortized {
+                        alloc.grow_amortized(block.cast(), layout, new)
+                    } else {
+                        alloc.grow(block.cast(), layout, new)
+                    }
+                    .unwrap();
+                    layout = Layout::array::<u8>(len(block)).unwrap();
+                }
+                (block.as_ptr() as *mut u8).add(i).write(i as u8);
+            }
+            for i in 0..1000 {
+                assert_eq!(*(block.as_ptr() as *mut u8).add(i), i as u8);
+            }
+            alloc.deallocate(block.cast(), layout);
+        }
+        alloc.allocations.get()
+    }
+
+    #[test]
+    fn split_alloc() {
+        let layout = Layout::new::<[u8; 100]>();
+        let ptr = Global.allocate(layout).unwrap();
+        unsafe {
+            let bytes = ptr.as_ptr() as *mut u8;
+            for i in 0..100 {
+                bytes.add(i).write(i as u8);
+            }
+            let (a, b) = Global.split_alloc(ptr, layout, 40).unwrap();
+            let a_bytes = &*(a.as_ptr() as *const [u8]);
+            let b_bytes = &*(b.as_ptr() as *const [u8]);
+            assert!(a_bytes[..40].iter().copied().eq(0..40));
+            assert!(b_bytes[..60].iter().copied().eq(40..100));
+            Global.deallocate(a.cast(), Layout::new::<[u8; 40]>());
+            Global.deallocate(b.cast(), Layout::new::<[u8; 60]>());
+        }
+    }
+
+    #[test]
+    fn split_alloc_at_ends() {
+        let layout = Layout::new::<[u8; 8]>();
+        for &at in &[0, 8] {
+            let ptr = Global.allocate(layout).unwrap();
+            unsafe {
+                (ptr.as_ptr() as *mut [u8; 8]).write(*b"abcdefgh");
+                let (a, b) = Global.split_alloc(ptr, layout, at).unwrap();
+                let (empty, full) = if at == 0 {
+                    (a, b)
+                } else {
+                    (b, a)
+                };
+                assert_eq!((&*(empty.as_ptr() as *const [u8])).len(), 0);
+                assert_eq!(&*(full.as_ptr() as *const [u8]), b"abcdefgh");
+                Global.deallocate(full.cast(), layout);
+            }
+        }
+    }
+
+    #[test]
+    fn resize() {
+        let alloc = Counting::default();
+        let small = Layout::new::<[u8; 8]>();
+        let medium = Layout::new::<[u8; 16]>();
+        let large = Layout::new::<[u8; 64]>();
+        let ptr = alloc.allocate(medium).unwrap().cast::<u8>();
+        unsafe {
+            ptr.as_ptr().write_bytes(3, 16);
+            let ptr = alloc.resize(ptr, medium, large).unwrap();
+            assert_eq!(alloc.allocations.get(), 2);
+            assert_eq!(len(ptr), 64);
+
+            let same = alloc.resize(ptr.cast(), large, large).unwrap();
+            assert_eq!(same.cast::<u8>(), ptr.cast::<u8>());
+            assert_eq!(alloc.allocations.get(), 2);
+
+            let ptr = alloc.resize(ptr.cast(), large, small).unwrap();
+            assert_eq!(alloc.allocations.get(), 3);
+            let bytes = &*(ptr.as_ptr() as *const [u8]);
+            assert_eq!(bytes.len(), 8);
+            assert!(bytes.iter().all(|&b| b == 3));
+            alloc.deallocate(ptr.cast(), small);
+        }
+    }
+
+    #[test]
+    fn shrink_if_worthwhile() {
+        let alloc = Counting::default();
+        let layout = Layout::new::<[u8; 100]>();
+        let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+        unsafe {
+            // Slack of exactly half isn’t enough.
+            let same = alloc.shrink_if_worthwhile(ptr, layout, 50).unwrap();
+            assert_eq!(same.cast(), ptr);
+            assert_eq!(len(same), 100);
+            assert_eq!(alloc.allocations.get(), 1);
+
+            ptr.as_ptr().write_bytes(7, 49);
+            let new = alloc.shrink_if_worthwhile(ptr, layout, 49).unwrap();
+            assert_eq!(alloc.allocations.get(), 2);
+            assert!(
+                (&*(new.as_ptr() as *const [u8]))[..49]
+                    .iter()
+                    .all(|&b| b == 7)
+            );
+            alloc.deallocate(new.cast(), Layout::new::<[u8; 49]>());
+        }
+    }
+
+    #[test]
+    fn shrink_if_slack_exceeds() {
+        let alloc = Counting::default();
+        let layout = Layout::new::<[u8; 100]>();
+        let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+        unsafe {
+            let same = alloc.shrink_if_slack_exceeds(ptr, layout, 90, 10);
+            assert_eq!(same.unwrap().cast(), ptr);
+            assert_eq!(alloc.allocations.get(), 1);
+            let new = alloc.shrink_if_slack_exceeds(ptr, layout, 89, 10);
+            assert_eq!(alloc.allocations.get(), 2);
+            alloc.deallocate(new.unwrap().cast(), Layout::new::<[u8; 89]>());
+        }
+    }
+
+    #[test]
+    fn copy_between() {
+        let layout = Layout::new::<[u8; 8]>();
+        let a = Global.allocate(layout).unwrap().cast::<[u8; 8]>();
+        let b = Global.allocate(layout).unwrap().cast::<[u8; 8]>();
+        unsafe {
+            a.as_ptr().write(*b"abcdefgh");
+            Global.copy_between(a.cast(), b.cast(), 8);
+            assert_eq!(&*b.as_ptr(), b"abcdefgh");
+            Global.deallocate(a.cast(), layout);
+            Global.deallocate(b.cast(), layout);
+        }
+    }
+
+    #[test]
+    fn copy_between_overlapping() {
+        let mut buf = *b"abcdefgh";
+        let start = NonNull::from(&mut buf).cast::<u8>();
+        unsafe {
+            let mid = NonNull::new_unchecked(start.as_ptr().add(2));
+            Global.copy_between_overlapping(start, mid, 6);
+            assert_eq!(&buf, b"ababcdef");
+            let start = NonNull::from(&mut buf).cast::<u8>();
+            let mid = NonNull::new_unchecked(start.as_ptr().add(2));
+            Global.copy_between_overlapping(mid, start, 6);
+        }
+        assert_eq!(&buf, b"abcdefef");
+    }
+
+    #[test]
+    fn alloc_or_abort() {
+        let layout = Layout::new::<u64>();
+        let ptr = Global.alloc_or_abort(layout);
+        assert_eq!(len(ptr), 8);
+        assert_eq!(ptr.as_ptr() as *mut u8 as usize % 8, 0);
+        unsafe {
+            (ptr.as_ptr() as *mut u64).write(1);
+            Global.deallocate(ptr.cast(), layout);
+        }
+    }
+
+    #[test]
+    fn allocate_slice_with() {
+        let ptr = Global.allocate_slice_with(10, |i| i * i).unwrap();
+        unsafe {
+            assert!(
+                (*ptr.as_ptr()).iter().copied().eq((0..10).map(|i| i * i))
+            );
+            Global.deallocate(ptr.cast(), Layout::array::<usize>(10).unwrap());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn allocate_slice_with_panic() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        struct DropCounter<'a>(&'a Cell<usize>);
+
+        impl Drop for DropCounter<'_> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        #[derive(Default)]
+        struct Live(Cell<isize>);
+
+        unsafe impl Allocator for Live {
+            fn allocate(
+                &self,
+                layout: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                self.0.set(self.0.get() + 1);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                self.0.set(self.0.get() - 1);
+                unsafe { Global.deallocate(ptr, layout) };
+            }
+        }
+
+        let alloc = Live::default();
+        let drops = Cell::new(0);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            alloc.allocate_slice_with(10, |i| {
+                if i == 4 {
+                    panic!("init failed");
+                }
+                DropCounter(&drops)
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(drops.get(), 4);
+        assert_eq!(alloc.0.get(), 0);
+    }
+
+    #[test]
+    fn leak_alloc() {
+        use crate::TrackingAllocator;
+        static TRACKER: TrackingAllocator<Global> =
+            TrackingAllocator::new(Global);
+
+        let value: &'static mut [u32; 4] = TRACKER.leak_alloc([1, 2, 3, 4]);
+        value[3] = 40;
+        assert_eq!(*value, [1, 2, 3, 40]);
+        let stats = TRACKER.stats();
+        assert_eq!(stats.current, 16);
+        assert_eq!(stats.deallocations, 0);
+        assert_eq!(
+            *Global.leak_alloc(alloc::string::String::from("leaked")),
+            "leaked"
+        );
+    }
+
+    #[test]
+    fn allocate_with() {
+        let ptr = Global.allocate_with(|| [1_u64, 2, 3]).unwrap();
+        unsafe {
+            assert_eq!(*ptr.as_ptr(), [1, 2, 3]);
+            Global.deallocate(ptr.cast(), Layout::new::<[u64; 3]>());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn allocate_with_panic() {
+        use crate::TrackingAllocator;
+        use std::panic::{self, AssertUnwindSafe};
+
+        let alloc = TrackingAllocator::new(Global);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            alloc.allocate_with::<u64, _>(|| panic!("init failed"))
+        }));
+        assert!(result.is_err());
+        let stats = alloc.stats();
+        assert_eq!(stats.allocations, 1);
+        assert_eq!(stats.deallocations, 1);
+        assert_eq!(stats.current, 0);
+
+        // Panicking partway through a slice balances as well, whichever
+        // element panics.
+        for i in 0..4 {
+            let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                alloc.allocate_slice_with(4, |j| {
+                    if i == j {
+                        panic!("init failed");
+                    }
+                    alloc::boxed::Box::new(j)
+                })
+            }));
+            assert!(result.is_err());
+        }
+        let stats = alloc.stats();
+        assert_eq!(stats.allocations, stats.deallocations);
+        assert_eq!(stats.current, 0);
+    }
+
+    #[test]
+    fn allocate_n() {
+        let ptr = Global.allocate_n::<u32>(10).unwrap();
+        assert_eq!(len(ptr), 10);
+        assert_eq!(ptr.as_ptr() as *mut u32 as usize % 4, 0);
+        unsafe {
+            Global.deallocate(ptr.cast(), Layout::array::<u32>(10).unwrap());
+        }
+        assert_eq!(Global.allocate_n::<u32>(usize::MAX / 2), Err(AllocError));
+    }
+
+    #[test]
+    fn allocate_with_capacity() {
+        /// Reports blocks twice as large as requested.
+        struct OverReporting;
+
+        unsafe impl Allocator for OverReporting {
+            fn allocate(
+                &self,
+                layout: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                let layout =
+                    Layout::from_size_align(layout.size() * 2, layout.align())
+                        .unwrap();
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+                let layout =
+                    Layout::from_size_align(layout.size() * 2, layout.align())
+                        .unwrap();
+                unsafe { Global.deallocate(ptr, layout) };
+            }
+        }
+
+        let layout = Layout::new::<[u32; 6]>();
+        let (ptr, cap) = OverReporting.allocate_with_capacity(layout).unwrap();
+        assert_eq!(cap, 48);
+        assert_eq!(ptr.as_ptr() as usize % 4, 0);
+        unsafe { OverReporting.deallocate(ptr, layout) };
+    }
+
+    #[test]
+    fn allocate_2d() {
+        let check = |rows, cols, row_align, stride| unsafe {
+            let (ptr, s) =
+                Global.allocate_2d::<[u8; 3]>(rows, cols, row_align).unwrap();
+            assert_eq!(s, stride);
+            assert_eq!(ptr.as_ptr() as usize % row_align, 0);
+            assert_eq!((s * 3) % row_align, 0);
+            let layout = Layout::array::<[u8; 3]>(rows * stride)
+                .unwrap()
+                .align_to(row_align)
+                .unwrap();
+            Global.deallocate(ptr.cast(), layout);
+        };
+        check(7, 5, 1, 5);
+        check(7, 5, 4, 8);
+        check(3, 11, 16, 16);
+        check(5, 17, 16, 32);
+
+        let (ptr, stride) = Global.allocate_2d::<u32>(3, 5, 32).unwrap();
+        assert_eq!(stride, 8);
+        let layout = Layout::from_size_align(96, 32).unwrap();
+        unsafe { Global.deallocate(ptr.cast(), layout) };
+
+        assert_eq!(Global.allocate_2d::<u8>(2, 2, 3), Err(AllocError));
+        assert_eq!(
+            Global.allocate_2d::<u8>(usize::MAX, 2, 1),
+            Err(AllocError),
+        );
+        assert_eq!(
+            Global.allocate_2d::<u8>(1, usize::MAX, 2),
+            Err(AllocError),
+        );
+    }
+
+    #[test]
+    fn grow_amortized() {
+        assert!(grow_bytewise(true) <= 11);
+        assert!(grow_bytewise(true) < grow_bytewise(false));
+    }
+
+    #[test]
+    fn duplicate() {
+        let layout = Layout::new::<[u8; 16]>();
+        let a = Global.allocate(layout).unwrap().cast::<[u8; 16]>();
+        unsafe {
+            a.as_ptr().write(*b"0123456789abcdef");
+            let b = Global.duplicate(a.cast(), layout).unwrap();
+            let b = b.cast::<[u8; 16]>();
+            assert_ne!(a, b);
+            (*b.as_ptr())[0] = b'x';
+            assert_eq!(&*a.as_ptr(), b"0123456789abcdef");
+            assert_eq!(&*b.as_ptr(), b"x123456789abcdef");
+            Global.deallocate(a.cast(), layout);
+            Global.deallocate(b.cast(), layout);
+        }
+    }
+
+    #[test]
+    fn into_dyn() {
+        use crate::TrackingAllocator;
+        use alloc::vec::Vec;
+
+        struct Null;
+
+        unsafe impl Allocator for Null {
+            fn allocate(
+                &self,
+                _: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                Err(AllocError)
+            }
+
+            unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+        }
+
+        let allocs: Vec<Box<dyn Allocator>> = alloc::vec![
+            Global.into_dyn(),
+            Null.into_dyn(),
+            TrackingAllocator::new(Global).into_dyn(),
+        ];
+        let layout = Layout::new::<u64>();
+        let results: Vec<_> = allocs
+            .iter()
+            .map(|alloc| {
+                let ptr = alloc.allocate(layout).ok()?;
+                unsafe { alloc.deallocate(ptr.cast(), layout) };
+                Some(())
+            })
+            .collect();
+        assert_eq!(results, [Some(()), None, Some(())]);
+        assert!(Counting::default().into_dyn().allocate(layout).is_ok());
+    }
+
+    #[test]
+    fn deallocator() {
+        use crate::TrackingAllocator;
+        use alloc::vec::Vec;
+
+        let alloc = TrackingAllocator::new(Global);
+        let layout = Layout::new::<[u8; 16]>();
+        let mut cleanup: Vec<alloc::boxed::Box<dyn FnOnce()>> = Vec::new();
+        for _ in 0..3 {
+            let ptr = alloc.allocate(layout).unwrap();
+            let dealloc = unsafe { alloc.deallocator(ptr.cast(), layout) };
+            cleanup.push(alloc::boxed::Box::new(move || dealloc.call()));
+        }
+        assert_eq!(alloc.stats().current, 48);
+        for f in cleanup {
+            f();
+        }
+        let stats = alloc.stats();
+        assert_eq!(stats.current, 0);
+        assert_eq!(stats.deallocations, 3);
+    }
+
+    #[test]
+    fn typed_zero_sized() {
+        /// Panics if the helpers reach the backend.
+        struct Unreachable;
+
+        unsafe impl Allocator for Unreachable {
+            fn allocate(
+                &self,
+                _: Layout,
+            ) -> Result<NonNull<[u8]>, AllocError> {
+                panic!("allocator called for an empty block");
+            }
+
+            unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {
+                panic!("allocator called for an empty block");
+            }
+        }
+
+        #[repr(align(32))]
+        struct Zst;
+
+        let unit = Unreachable.allocate_with(|| ()).unwrap();
+        assert_eq!(unit, NonNull::dangling());
+        let zst = Unreachable.allocate_with(|| Zst).unwrap();
+        assert_eq!(zst.as_ptr() as usize % 32, 0);
+        let units = Unreachable.allocate_n::<()>(usize::MAX).unwrap();
+        assert_eq!(len(units), usize::MAX);
+        let empty = Unreachable.allocate_n::<u64>(0).unwrap();
+        assert_eq!(len(empty), 0);
+        assert_eq!(empty.as_ptr() as *mut u64 as usize % 8, 0);
+        let zsts = Unreachable.allocate_slice_with(5, |_| Zst).unwrap();
+        assert_eq!(zsts.as_ptr() as *mut Zst as usize % 32, 0);
+        let (grid, _) = Unreachable.allocate_2d::<u32>(0, 4, 64).unwrap();
+        assert_eq!(grid.as_ptr() as usize % 64, 0);
+    }
+}
@@ -16,12 +16,42 @@
  * limitations under the License.
  */
 
+use crate::align_check::debug_aligned;
 use alloc::alloc::Layout;
+use alloc::boxed::Box;
 use core::fmt::{self, Display, Formatter};
 use core::mem::MaybeUninit;
 use core::ptr::{self, NonNull};
+#[cfg(feature = "std")]
+use std::alloc::GlobalAlloc;
 
 /// A fallback for [`alloc::alloc::AllocError`], which is currently unstable.
+///
+/// Like its counterpart in the standard library, this is a unit struct, so
+/// custom allocators can construct it directly:
+///
+/// ```
+/// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+/// use allocator_fallback::{AllocError, Allocator};
+/// use core::alloc::Layout;
+/// use core::ptr::NonNull;
+///
+/// struct Exhausted;
+///
+/// unsafe impl Allocator for Exhausted {
+///     fn allocate(&self, _: Layout) -> Result<NonNull<[u8]>, AllocError> {
+///         Err(AllocError)
+///     }
+///
+///     unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+/// }
+///
+/// assert_eq!(Exhausted.allocate(Layout::new::<u8>()), Err(AllocError));
+/// ```
+///
+/// There is intentionally no `AllocError::new`, as the standard library’s
+/// type, which this one may be replaced with (see
+/// [Crate features](crate#crate-features)), doesn’t have one.
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct AllocError;
 
@@ -31,7 +61,12 @@ impl Display for AllocError {
     }
 }
 
-#[cfg(feature = "std")]
+// On Rust 1.81 and later, `std::error::Error` is a re-export of
+// `core::error::Error`, so only one of these impls may exist.
+#[cfg(has_core_error)]
+impl core::error::Error for AllocError {}
+
+#[cfg(all(feature = "std", not(has_core_error)))]
 #[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
 impl std::error::Error for AllocError {}
 
@@ -59,7 +94,7 @@ pub unsafe trait Allocator {
         let ptr = self.allocate(layout)?;
         // SAFETY: `Self::allocate` always returns a pointer to valid memory.
         unsafe {
-            let len = (*(ptr.as_ptr() as *mut [MaybeUninit<u8>])).len();
+            let len = (&*(ptr.as_ptr() as *const [MaybeUninit<u8>])).len();
             (ptr.as_ptr() as *mut u8).write_bytes(0_u8, len);
         }
         Ok(ptr)
@@ -77,6 +112,8 @@ pub unsafe trait Allocator {
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
         let new = self.allocate(new_layout)?;
+        // No user code runs between allocating `new` and deallocating `ptr`
+        // (the copy is of raw bytes), so neither block can leak by unwinding.
         // SAFETY: Checked by caller.
         unsafe {
             (new.as_ptr() as *mut u8)
@@ -99,10 +136,23 @@ pub unsafe trait Allocator {
     ) -> Result<NonNull<[u8]>, AllocError> {
         let new = self.allocate(new_layout)?;
         // SAFETY: `Self::allocate` always returns a pointer to valid memory.
-        // Sizes are checked by caller (new size must not be less than old
-        // size).
+        let len =
+            unsafe { &*(new.as_ptr() as *const [MaybeUninit<u8>]) }.len();
+        debug_assert!(
+            len >= new_layout.size(),
+            "allocator returned a block smaller than requested",
+        );
+        // A buggy allocator could report a length smaller than the old size,
+        // in which case `len - old_layout.size()` would underflow, and we'd
+        // zero far past the end of the block.
+        if len < old_layout.size() {
+            // SAFETY: `new` was just allocated with `new_layout`.
+            unsafe { self.deallocate(new.cast(), new_layout) };
+            return Err(AllocError);
+        }
+        // SAFETY: Sizes are checked by caller (new size must not be less than
+        // old size), and `len` is at least the old size.
         unsafe {
-            let len = (*(new.as_ptr() as *mut [MaybeUninit<u8>])).len();
             (new.as_ptr() as *mut u8)
                 .copy_from_nonoverlapping(ptr.as_ptr(), old_layout.size());
             (new.as_ptr() as *mut u8)
@@ -115,6 +165,12 @@ pub unsafe trait Allocator {
 
     /// See [`alloc::alloc::Allocator::shrink`].
     ///
+    /// Implementations that can shrink a block in place may return `ptr`
+    /// itself, possibly with a length greater than `new_layout.size()` (up
+    /// to the length of the old block) if the memory can’t be given back.
+    /// Callers must use the returned pointer and length, and shouldn’t
+    /// assume the block moved.
+    ///
     /// # Safety
     ///
     /// See [`alloc::alloc::Allocator::shrink`].
@@ -127,11 +183,11 @@ pub unsafe trait Allocator {
         let new = self.allocate(new_layout)?;
         // SAFETY: `Self::allocate` always returns a pointer to valid memory.
         // Sizes are checked by caller (new size must not be greater than old
-        // size).
+        // size). Note that the new block may be larger than `new_layout`
+        // (and even `old_layout`), so we copy only `new_layout.size()` bytes.
         unsafe {
-            let len = (*(new.as_ptr() as *mut [MaybeUninit<u8>])).len();
             (new.as_ptr() as *mut u8)
-                .copy_from_nonoverlapping(ptr.as_ptr(), len);
+                .copy_from_nonoverlapping(ptr.as_ptr(), new_layout.size());
             self.deallocate(ptr, old_layout);
         }
         Ok(new)
@@ -163,26 +219,288 @@ where
     }
 }
 
+// SAFETY: This impl simply forwards to `A`. Moving the box doesn’t move the
+// allocator, so blocks remain valid.
+unsafe impl<A> Allocator for Box<A>
+where
+    A: Allocator + ?Sized,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (**self).allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { (**self).deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { (**self).grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { (**self).grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { (**self).shrink(ptr, old_layout, new_layout) }
+    }
+}
+
 /// A fallback for [`alloc::alloc::Global`], which is currently unstable.
-#[derive(Clone, Copy, Debug, Default)]
+///
+/// Like its counterpart in the standard library, this forwards to
+/// [`alloc::alloc::alloc`] and related functions, so it uses whichever
+/// allocator the binary registers with `#[global_allocator]`.
+///
+/// Because this type is stateless, it implements [`Eq`], [`Ord`], and
+/// [`Hash`](core::hash::Hash) (all instances are equal), so it can be used
+/// as a key in registries of allocators. The standard library’s `Global`
+/// doesn’t implement these traits, so they aren’t available when the
+/// `allocator_api` feature is enabled.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
 pub struct Global;
 
+impl Global {
+    /// Grows the block at `ptr` with [`alloc::alloc::realloc`], which can
+    /// often extend the block in place. If `zeroed` is true, only the bytes
+    /// past the old size are zeroed.
+    ///
+    /// # Safety
+    ///
+    /// See [`Allocator::grow`].
+    unsafe fn grow_impl(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let allocate = |layout| {
+            if zeroed {
+                self.allocate_zeroed(layout)
+            } else {
+                self.allocate(layout)
+            }
+        };
+        if old_layout.size() == 0 {
+            return allocate(new_layout);
+        }
+        if old_layout.align() != new_layout.align() {
+            // `realloc` keeps the old alignment, so the block has to move.
+            let new = allocate(new_layout)?;
+            // SAFETY: Checked by caller (new size must not be less than old
+            // size).
+            unsafe {
+                (new.as_ptr() as *mut u8)
+                    .copy_from_nonoverlapping(ptr.as_ptr(), old_layout.size());
+                self.deallocate(ptr, old_layout);
+            }
+            return Ok(new);
+        }
+        // SAFETY: Checked by caller. `ptr` is non-dangling, since its size is
+        // not 0, and `new_layout` is a valid layout with the same alignment,
+        // so its size doesn’t overflow when padded.
+        let raw = unsafe {
+            alloc::alloc::realloc(ptr.as_ptr(), old_layout, new_layout.size())
+        };
+        let new = NonNull::new(raw).ok_or(AllocError)?;
+        if zeroed {
+            // SAFETY: The block is `new_layout.size()` bytes, and the first
+            // `old_layout.size()` were copied from the old block.
+            unsafe {
+                new.as_ptr()
+                    .add(old_layout.size())
+                    .write_bytes(0_u8, new_layout.size() - old_layout.size());
+            }
+        }
+        // SAFETY: `new` is non-null.
+        let new = unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                new.as_ptr(),
+                new_layout.size(),
+            ))
+        };
+        Ok(debug_aligned(new, new_layout))
+    }
+}
+
 // SAFETY: The `alloc` and `dealloc` functions in the standard library behave
 // as required. Clones of this allocator will necessarily behave the same, as
 // they forward to the global allocator.
 unsafe impl Allocator for Global {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         assert!(layout.size() != 0);
+        debug_assert!(
+            layout.size() <= isize::MAX as usize - (layout.align() - 1),
+            "layout size overflows when padded to its alignment",
+        );
         NonNull::new(ptr::slice_from_raw_parts_mut(
             // SAFETY: We ensured that the size of the layout is not 0.
             unsafe { alloc::alloc::alloc(layout) },
             layout.size(),
         ))
+        .map(|ptr| debug_aligned(ptr, layout))
+        .ok_or(AllocError)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        assert!(layout.size() != 0);
+        // This uses the global allocator’s `alloc_zeroed`, which may be able
+        // to avoid zeroing memory that’s already known to be zero.
+        NonNull::new(ptr::slice_from_raw_parts_mut(
+            // SAFETY: We ensured that the size of the layout is not 0.
+            unsafe { alloc::alloc::alloc_zeroed(layout) },
+            layout.size(),
+        ))
+        .map(|ptr| debug_aligned(ptr, layout))
+        .ok_or(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // Zero-sized blocks are dangling pointers returned by `Self::shrink`.
+        if layout.size() != 0 {
+            // SAFETY: Ensured by caller.
+            unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) };
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.grow_impl(ptr, old_layout, new_layout, false) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.grow_impl(ptr, old_layout, new_layout, true) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if new_layout.size() != 0 && new_layout.align() == old_layout.align() {
+            // `realloc` can usually shrink the block in place, returning the
+            // original pointer. The block can’t simply be returned as is,
+            // since `dealloc` must be passed the size it was allocated with.
+            // SAFETY: Checked by caller. `new_layout` is a valid layout with
+            // the same alignment, so its size doesn’t overflow when padded.
+            let raw = unsafe {
+                alloc::alloc::realloc(
+                    ptr.as_ptr(),
+                    old_layout,
+                    new_layout.size(),
+                )
+            };
+            let new = NonNull::new(raw).ok_or(AllocError)?;
+            // SAFETY: `new` is non-null.
+            let new = unsafe {
+                NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                    new.as_ptr(),
+                    new_layout.size(),
+                ))
+            };
+            return Ok(debug_aligned(new, new_layout));
+        }
+        if new_layout.size() != 0 {
+            let new = self.allocate(new_layout)?;
+            // SAFETY: Checked by caller (new size must not be greater than
+            // old size).
+            unsafe {
+                (new.as_ptr() as *mut u8)
+                    .copy_from_nonoverlapping(ptr.as_ptr(), new_layout.size());
+                self.deallocate(ptr, old_layout);
+            }
+            return Ok(new);
+        }
+        // SAFETY: Checked by caller.
+        unsafe { self.deallocate(ptr, old_layout) };
+        // A pointer with the value of the alignment is non-null, aligned, and
+        // valid for zero-sized accesses.
+        let dangling = new_layout.align() as *mut u8;
+        // SAFETY: Alignments are never zero.
+        Ok(unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(dangling, 0))
+        })
+    }
+}
+
+// SAFETY: The `GlobalAlloc` methods of `System` behave as required. Like the
+// standard library’s implementation of the real `Allocator` trait, this uses
+// `alloc_zeroed` for zeroed allocations, which uses `calloc` on Unix
+// platforms (falling back to zeroing manually for large alignments), so large
+// zeroed blocks can be served by zero pages from the OS.
+#[cfg(feature = "std")]
+unsafe impl Allocator for std::alloc::System {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        assert!(layout.size() != 0);
+        NonNull::new(ptr::slice_from_raw_parts_mut(
+            // SAFETY: We ensured that the size of the layout is not 0.
+            unsafe { GlobalAlloc::alloc(self, layout) },
+            layout.size(),
+        ))
+        .map(|ptr| debug_aligned(ptr, layout))
+        .ok_or(AllocError)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        assert!(layout.size() != 0);
+        NonNull::new(ptr::slice_from_raw_parts_mut(
+            // SAFETY: We ensured that the size of the layout is not 0.
+            unsafe { GlobalAlloc::alloc_zeroed(self, layout) },
+            layout.size(),
+        ))
+        .map(|ptr| debug_aligned(ptr, layout))
         .ok_or(AllocError)
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         // SAFETY: Ensured by caller.
-        unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) };
+        unsafe { GlobalAlloc::dealloc(self, ptr.as_ptr(), layout) };
     }
 }
@@ -0,0 +1,291 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::Allocator;
+use alloc::alloc::Layout;
+use core::ffi::c_void;
+use core::mem::size_of;
+use core::ptr::{self, NonNull};
+
+/// The alignment of blocks returned by the callbacks, which matches what
+/// `malloc` guarantees on common platforms.
+const ALIGN: usize = 16;
+
+/// The size of the header that precedes each block, which stores the size of
+/// the block (including the header).
+const HEADER: usize = ALIGN;
+
+/// A `malloc`-style function that allocates from the allocator `ctx`.
+pub type CMalloc =
+    unsafe extern "C" fn(ctx: *mut c_void, size: usize) -> *mut c_void;
+
+/// A `free`-style function that deallocates from the allocator `ctx`.
+pub type CFree = unsafe extern "C" fn(ctx: *mut c_void, ptr: *mut c_void);
+
+/// A `realloc`-style function that reallocates from the allocator `ctx`.
+pub type CRealloc = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    ptr: *mut c_void,
+    size: usize,
+) -> *mut c_void;
+
+/// An allocation function taking a size and alignment, as returned by
+/// [`c_alloc_shim`].
+pub type CAllocShim =
+    unsafe extern "C" fn(size: usize, align: usize) -> *mut u8;
+
+/// Returns a C-compatible function that allocates from `alloc`.
+///
+/// This is the simplest possible bridge for C code that only needs to
+/// allocate memory. The function takes a size and an alignment, and returns
+/// null if the alignment isn’t a power of two, the size overflows when
+/// rounded up to the alignment, or the allocation fails. Blocks must be
+/// freed by passing the same size and alignment to [`Allocator::deallocate`]
+/// (for example, through another shim). A size of 0 gives a dangling pointer
+/// that needn’t be freed.
+///
+/// A function pointer can’t carry any context, so `A` must be a zero-sized
+/// type, like [`Global`](crate::Global). For allocators with state, use
+/// [`AllocatorExt::as_c_callbacks`], which passes a context pointer.
+///
+/// # Panics
+///
+/// This function panics if `A` isn’t zero-sized.
+///
+/// [`AllocatorExt::as_c_callbacks`]: crate::AllocatorExt::as_c_callbacks
+pub fn c_alloc_shim<A>(alloc: &'static A) -> CAllocShim
+where
+    A: Allocator + Sync + 'static,
+{
+    assert!(size_of::<A>() == 0, "allocator isn’t zero-sized");
+    let _ = alloc;
+    c_alloc::<A>
+}
+
+/// C-compatible function pointers that forward to an [`Allocator`], for use
+/// with C libraries that accept custom allocation functions.
+///
+/// This is returned by [`AllocatorExt::as_c_callbacks`]. Each function takes
+/// [`Self::ctx`] as its first argument, and behaves like the corresponding C
+/// function: in particular, `realloc` with a null pointer allocates, and
+/// `realloc` with a size of 0 frees the block and returns null.
+///
+/// Because `free` doesn’t receive the size of the block, the size is stored
+/// in a header before each block. This adds 16 bytes of overhead to every
+/// allocation. Blocks are aligned to 16 bytes.
+///
+/// [`AllocatorExt::as_c_callbacks`]: crate::AllocatorExt::as_c_callbacks
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CAllocCallbacks {
+    /// The context pointer to pass to each function.
+    pub ctx: *mut c_void,
+    /// Allocates a block, returning null on failure.
+    pub malloc: CMalloc,
+    /// Deallocates a block. Does nothing if the pointer is null.
+    pub free: CFree,
+    /// Resizes a block, returning null on failure.
+    pub realloc: CRealloc,
+}
+
+impl CAllocCallbacks {
+    pub(crate) fn new<A: Allocator + Sync>(alloc: &'static A) -> Self {
+        Self {
+            ctx: alloc as *const A as *mut c_void,
+            malloc: c_malloc::<A>,
+            free: c_free::<A>,
+            realloc: c_realloc::<A>,
+        }
+    }
+}
+
+// SAFETY: `ctx` is a `&'static A`, where `A: Sync`.
+unsafe impl Send for CAllocCallbacks {}
+
+// SAFETY: `ctx` is a `&'static A`, where `A: Sync`.
+unsafe impl Sync for CAllocCallbacks {}
+
+/// Returns the layout of a block with `size` bytes of user data.
+fn layout(size: usize) -> Option<Layout> {
+    let size = size.checked_add(HEADER)?;
+    Layout::from_size_align(size, ALIGN).ok()
+}
+
+/// Returns the start of the block (including the header) for `ptr`, along
+/// with its layout.
+///
+/// # Safety
+///
+/// `ptr` must have been returned by one of the callbacks.
+unsafe fn block(ptr: *mut c_void) -> (NonNull<u8>, Layout) {
+    // SAFETY: Checked by caller.
+    unsafe {
+        let start = (ptr as *mut u8).sub(HEADER);
+        let size = (start as *mut usize).read();
+        let layout = Layout::from_size_align_unchecked(size, ALIGN);
+        (NonNull::new_unchecked(start), layout)
+    }
+}
+
+/// Writes the header to `block`, returning a pointer to the user data.
+///
+/// # Safety
+///
+/// `block` must be valid for `layout`.
+unsafe fn init(block: NonNull<[u8]>, layout: Layout) -> *mut c_void {
+    let start = block.as_ptr() as *mut u8;
+    // SAFETY: Checked by caller. `start` is aligned to `ALIGN`, and the
+    // block is at least `HEADER` bytes long.
+    unsafe {
+        (start as *mut usize).write(layout.size());
+        start.add(HEADER) as *mut c_void
+    }
+}
+
+unsafe extern "C" fn c_alloc<A: Allocator>(
+    size: usize,
+    align: usize,
+) -> *mut u8 {
+    let alloc = NonNull::<A>::dangling();
+    // SAFETY: `c_alloc_shim` ensures `A` is zero-sized, so any non-null,
+    // aligned pointer is a valid reference to it, and was given a `&'static
+    // A`, so a value of this type exists and will never be dropped.
+    let alloc = unsafe { alloc.as_ref() };
+    let layout = match Layout::from_size_align(size, align) {
+        Ok(layout) => layout,
+        Err(_) => return ptr::null_mut(),
+    };
+    if size == 0 {
+        // Allocators may not support zero-sized requests (and a panic here
+        // would abort), so return a dangling pointer, which is aligned.
+        return align as *mut u8;
+    }
+    match alloc.allocate(layout) {
+        Ok(block) => block.as_ptr() as *mut u8,
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn c_malloc<A: Allocator>(
+    ctx: *mut c_void,
+    size: usize,
+) -> *mut c_void {
+    // SAFETY: `ctx` is always a `&'static A`.
+    let alloc = unsafe { &*(ctx as *const A) };
+    match layout(size).map(|layout| (alloc.allocate(layout), layout)) {
+        // SAFETY: `block` was allocated with `layout`.
+        Some((Ok(block), layout)) => unsafe { init(block, layout) },
+        _ => ptr::null_mut(),
+    }
+}
+
+unsafe extern "C" fn c_free<A: Allocator>(ctx: *mut c_void, ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    // SAFETY: `ctx` is always a `&'static A`, and the caller must pass a
+    // pointer returned by one of the callbacks.
+    unsafe {
+        let (start, layout) = block(ptr);
+        (*(ctx as *const A)).deallocate(start, layout);
+    }
+}
+
+unsafe extern "C" fn c_realloc<A: Allocator>(
+    ctx: *mut c_void,
+    ptr: *mut c_void,
+    size: usize,
+) -> *mut c_void {
+    if ptr.is_null() {
+        // SAFETY: Checked by caller.
+        return unsafe { c_malloc::<A>(ctx, size) };
+    }
+    if size == 0 {
+        // SAFETY: Checked by caller.
+        unsafe { c_free::<A>(ctx, ptr) };
+        return ptr::null_mut();
+    }
+    let new_layout = match layout(size) {
+        Some(layout) => layout,
+        None => return ptr::null_mut(),
+    };
+    // SAFETY: `ctx` is always a `&'static A`, and the caller must pass a
+    // pointer returned by one of the callbacks.
+    let alloc = unsafe { &*(ctx as *const A) };
+    // SAFETY: Checked by caller.
+    let (start, old_layout) = unsafe { block(ptr) };
+    // SAFETY: `start` is currently allocated with `old_layout`, and both
+    // layouts have the same alignment.
+    let result = unsafe {
+        if new_layout.size() > old_layout.size() {
+            alloc.grow(start, old_layout, new_layout)
+        } else {
+            alloc.shrink(start, old_layout, new_layout)
+        }
+    };
+    match result {
+        // SAFETY: `block` was allocated with `new_layout`.
+        Ok(block) => unsafe { init(block, new_layout) },
+        Err(_) => ptr::null_mut(),
+    }
+}
+
+#[test]
+fn test() {
+    use crate::{AllocatorExt, Global};
+    let callbacks = Global.as_c_callbacks();
+    let ctx = callbacks.ctx;
+    unsafe {
+        let ptr = (callbacks.malloc)(ctx, 100) as *mut u8;
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % ALIGN, 0);
+        for i in 0..100 {
+            ptr.add(i).write(i as u8);
+        }
+        let ptr = (callbacks.realloc)(ctx, ptr as _, 1000) as *mut u8;
+        assert!((0..100).all(|i| *ptr.add(i) == i as u8));
+        let ptr = (callbacks.realloc)(ctx, ptr as _, 10) as *mut u8;
+        assert!((0..10).all(|i| *ptr.add(i) == i as u8));
+        (callbacks.free)(ctx, ptr as _);
+
+        (callbacks.free)(ctx, ptr::null_mut());
+        let ptr = (callbacks.realloc)(ctx, ptr::null_mut(), 8);
+        assert!(!ptr.is_null());
+        assert!((callbacks.realloc)(ctx, ptr, 0).is_null());
+        assert!((callbacks.malloc)(ctx, usize::MAX).is_null());
+    }
+}
+
+#[test]
+fn shim() {
+    use crate::Global;
+    let alloc = c_alloc_shim(&Global);
+    unsafe {
+        let ptr = alloc(100, 64);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % 64, 0);
+        ptr.write_bytes(1, 100);
+        let layout = Layout::from_size_align(100, 64).unwrap();
+        Global.deallocate(NonNull::new(ptr).unwrap(), layout);
+
+        assert!(alloc(usize::MAX - 8, 16).is_null());
+        assert!(alloc(8, 3).is_null());
+        assert_eq!(alloc(0, 8) as usize, 8);
+        assert!(alloc(0, 3).is_null());
+    }
+}
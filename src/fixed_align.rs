@@ -0,0 +1,173 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that forwards to `A`, replacing the alignment of every
+/// request with `ALIGN`.
+///
+/// This is useful for hardware buffers that require every allocation to
+/// share a single alignment. Requests with an alignment greater than `ALIGN`
+/// can’t be satisfied and fail with [`AllocError`]; requests with a smaller
+/// alignment are forwarded with their alignment *replaced* by `ALIGN`, so
+/// `A` only ever sees layouts aligned to exactly `ALIGN`.
+///
+/// `ALIGN` must be a power of two; otherwise, all requests fail.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FixedAlign<A, const ALIGN: usize>(pub A);
+
+impl<A, const ALIGN: usize> FixedAlign<A, ALIGN> {
+    fn fix(layout: Layout) -> Result<Layout, AllocError> {
+        if layout.align() > ALIGN {
+            return Err(AllocError);
+        }
+        Layout::from_size_align(layout.size(), ALIGN).map_err(|_| AllocError)
+    }
+
+    /// # Safety
+    ///
+    /// `layout` must describe a block currently allocated via this
+    /// allocator.
+    unsafe fn fix_unchecked(layout: Layout) -> Layout {
+        // SAFETY: `Self::fix` must have succeeded for this layout when the
+        // block was allocated.
+        unsafe { Layout::from_size_align_unchecked(layout.size(), ALIGN) }
+    }
+}
+
+// SAFETY: This impl forwards to `A`. Every block is allocated with a fixed
+// layout at least as aligned as the requested one, and deallocated with the
+// same fixed layout.
+unsafe impl<A, const ALIGN: usize> Allocator for FixedAlign<A, ALIGN>
+where
+    A: Allocator,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate(Self::fix(layout)?)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate_zeroed(Self::fix(layout)?)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(ptr, Self::fix_unchecked(layout)) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_layout = Self::fix(new_layout)?;
+        // SAFETY: Checked by caller.
+        unsafe {
+            self.0.grow(ptr, Self::fix_unchecked(old_layout), new_layout)
+        }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_layout = Self::fix(new_layout)?;
+        // SAFETY: Checked by caller.
+        unsafe {
+            self.0.grow_zeroed(
+                ptr,
+                Self::fix_unchecked(old_layout),
+                new_layout,
+            )
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_layout = Self::fix(new_layout)?;
+        // SAFETY: Checked by caller.
+        unsafe {
+            self.0.shrink(ptr, Self::fix_unchecked(old_layout), new_layout)
+        }
+    }
+}
+
+impl<A: Overhead, const ALIGN: usize> Overhead for FixedAlign<A, ALIGN> {
+    fn overhead(&self, layout: Layout) -> usize {
+        Self::fix(layout).map_or(0, |layout| self.0.overhead(layout))
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    use core::cell::Cell;
+
+    /// Forwards to `Global`, asserting that every layout is 32-byte aligned.
+    #[derive(Default)]
+    struct Check(Cell<usize>);
+
+    impl Check {
+        fn check(&self, layout: Layout) {
+            assert_eq!(layout.align(), 32);
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    unsafe impl Allocator for Check {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.check(layout);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.check(layout);
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+
+    let alloc = FixedAlign::<_, 32>(Check::default());
+    let small = Layout::from_size_align(16, 4).unwrap();
+    let large = Layout::from_size_align(64, 8).unwrap();
+    unsafe {
+        let ptr = alloc.allocate(small).unwrap().cast::<u8>();
+        assert_eq!(ptr.as_ptr() as usize % 32, 0);
+        let ptr = alloc.grow(ptr, small, large).unwrap().cast::<u8>();
+        let ptr = alloc.shrink(ptr, large, small).unwrap().cast::<u8>();
+        alloc.deallocate(ptr, small);
+    }
+    assert!(alloc.0.0.get() >= 4);
+
+    let over = Layout::from_size_align(16, 64).unwrap();
+    assert_eq!(alloc.allocate(over), Err(AllocError));
+}
@@ -0,0 +1,41 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+/// Statistics about the free space in an allocator, useful for diagnosing
+/// why a large request fails despite plenty of total free space.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FragmentationStats {
+    /// The number of contiguous free regions.
+    pub free_blocks: usize,
+    /// The size in bytes of the largest contiguous free region.
+    pub largest_free_block: usize,
+    /// The total size in bytes of all free regions.
+    pub total_free_bytes: usize,
+}
+
+impl FragmentationStats {
+    /// Records a free region of `size` bytes. Empty regions are ignored.
+    pub(crate) fn add(&mut self, size: usize) {
+        if size > 0 {
+            self.free_blocks += 1;
+            self.largest_free_block = self.largest_free_block.max(size);
+            self.total_free_bytes += size;
+        }
+    }
+}
@@ -0,0 +1,428 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{
+    AllocError, Allocator, AllocatorEq, FragmentationStats, OwningAllocator,
+    TooSmall,
+};
+use crate::align_check::debug_aligned;
+use crate::owning::in_buffer;
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::{MaybeUninit, size_of};
+use core::ptr::{self, NonNull};
+
+/// Sentinel offset indicating the end of the free list.
+const NONE: usize = usize::MAX;
+
+/// Stored at the start of each free block.
+#[derive(Clone, Copy)]
+struct FreeBlock {
+    size: usize,
+    /// The offset of the next free block, or [`NONE`].
+    next: usize,
+}
+
+/// The granularity of blocks. Every block starts at a multiple of this
+/// offset and has a size that is a multiple of it, so a [`FreeBlock`] fits
+/// in any block.
+const UNIT: usize = size_of::<FreeBlock>();
+
+/// Rounds `size` up to a non-zero multiple of [`UNIT`].
+fn block_size(size: usize) -> Option<usize> {
+    Some(size.max(1).checked_add(UNIT - 1)? & !(UNIT - 1))
+}
+
+/// A general-purpose allocator that serves allocations from a fixed buffer,
+/// reusing deallocated memory.
+///
+/// Unlike [`Bump`](crate::Bump), blocks can be deallocated in any order, so
+/// this works as a small heap for `no_std` environments without an operating
+/// system. Free regions are kept in a list sorted by address; allocations
+/// take the first region that fits, and deallocated blocks are merged with
+/// adjacent free regions, so the buffer doesn’t fragment into ever-smaller
+/// pieces.
+///
+/// Allocated blocks have no header, but their sizes are rounded up to a
+/// multiple of two words. Allocation and deallocation take time linear in
+/// the number of free regions.
+#[derive(Debug)]
+pub struct FreeListAllocator<'a> {
+    start: NonNull<u8>,
+    len: usize,
+    /// The offset of the first free block, or [`NONE`].
+    head: Cell<usize>,
+    phantom: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> FreeListAllocator<'a> {
+    /// Creates a new free-list allocator that allocates from `buf`.
+    pub fn new(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        let skip = (buf.as_ptr() as *const u8).align_offset(UNIT);
+        let len = buf.len().saturating_sub(skip) & !(UNIT - 1);
+        let skip = skip.min(buf.len());
+        let buf = &mut buf[skip..];
+        let this = Self {
+            start: NonNull::new(buf.as_mut_ptr() as *mut u8).unwrap(),
+            len,
+            head: Cell::new(NONE),
+            phantom: PhantomData,
+        };
+        if len > 0 {
+            this.write(
+                0,
+                FreeBlock {
+                    size: len,
+                    next: NONE,
+                },
+            );
+            this.head.set(0);
+        }
+        this
+    }
+
+    /// Like [`Self::new`], but returns an error if `buf` can’t hold a
+    /// single block.
+    ///
+    /// Blocks are two words long and aligned, so depending on its address,
+    /// a buffer may need to be slightly larger than that.
+    pub fn try_new(buf: &'a mut [MaybeUninit<u8>]) -> Result<Self, TooSmall> {
+        let skip = (buf.as_ptr() as *const u8).align_offset(UNIT);
+        let required = skip.saturating_add(UNIT);
+        if buf.len() < required {
+            return Err(TooSmall {
+                len: buf.len(),
+                required,
+            });
+        }
+        Ok(Self::new(buf))
+    }
+
+    /// Returns statistics about the free space in the buffer.
+    ///
+    /// This walks the free list, so it takes time linear in the number of
+    /// free regions.
+    pub fn fragmentation(&self) -> FragmentationStats {
+        let mut stats = FragmentationStats::default();
+        let mut offset = self.head.get();
+        while offset != NONE {
+            let block = self.read(offset);
+            stats.add(block.size);
+            offset = block.next;
+        }
+        stats
+    }
+
+    fn read(&self, offset: usize) -> FreeBlock {
+        // SAFETY: Callers only pass offsets of free blocks, which are within
+        // the buffer and aligned.
+        unsafe { (self.start.as_ptr().add(offset) as *const FreeBlock).read() }
+    }
+
+    fn write(&self, offset: usize, block: FreeBlock) {
+        // SAFETY: Callers only pass offsets of free space at least `UNIT`
+        // bytes long, which are within the buffer and aligned.
+        unsafe {
+            (self.start.as_ptr().add(offset) as *mut FreeBlock).write(block);
+        }
+    }
+
+    /// Points the link before `next` (the list head if `prev` is [`NONE`])
+    /// at `next`.
+    fn link(&self, prev: usize, next: usize) {
+        if prev == NONE {
+            self.head.set(next);
+        } else {
+            let block = self.read(prev);
+            self.write(
+                prev,
+                FreeBlock {
+                    next,
+                    ..block
+                },
+            );
+        }
+    }
+}
+
+// SAFETY: Blocks are handed out only from free regions, which are removed
+// from the free list while allocated and added back when deallocated.
+unsafe impl Allocator for FreeListAllocator<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let size = block_size(layout.size()).ok_or(AllocError)?;
+        let align = layout.align().max(UNIT);
+        let addr = self.start.as_ptr() as usize;
+        let mut prev = NONE;
+        let mut offset = self.head.get();
+        while offset != NONE {
+            let block = self.read(offset);
+            let data = addr
+                .checked_add(offset)
+                .and_then(|a| a.checked_add(align - 1))
+                .map(|a| (a & !(align - 1)) - addr);
+            let end = data.and_then(|data| data.checked_add(size));
+            let (data, end) = match (data, end) {
+                (Some(data), Some(end)) if end <= offset + block.size => {
+                    (data, end)
+                }
+                _ => {
+                    prev = offset;
+                    offset = block.next;
+                    continue;
+                }
+            };
+
+            // Replace `block` with the space left before and after the new
+            // allocation. Both are multiples of `UNIT`, as `align` is.
+            let mut next = block.next;
+            if end < offset + block.size {
+                self.write(
+                    end,
+                    FreeBlock {
+                        size: offset + block.size - end,
+                        next,
+                    },
+                );
+                next = end;
+            }
+            if data > offset {
+                self.write(
+                    offset,
+                    FreeBlock {
+                        size: data - offset,
+                        next,
+                    },
+                );
+                next = offset;
+            }
+            self.link(prev, next);
+
+            // SAFETY: The block is within the buffer.
+            let ptr = unsafe { self.start.as_ptr().add(data) };
+            // SAFETY: `ptr` is derived from a `NonNull`.
+            let block = unsafe {
+                NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                    ptr, size,
+                ))
+            };
+            return Ok(debug_aligned(block, layout));
+        }
+        Err(AllocError)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let offset = ptr.as_ptr() as usize - self.start.as_ptr() as usize;
+        // Any layout that fits the block rounds to the size it was allocated
+        // with, since the block’s length is already a multiple of `UNIT`.
+        let mut size = match block_size(layout.size()) {
+            Some(size) => size,
+            // SAFETY: Checked by caller (the block was allocated, so its size
+            // didn’t overflow).
+            None => unsafe { core::hint::unreachable_unchecked() },
+        };
+
+        // Find the free blocks on either side of this one.
+        let mut prev = NONE;
+        let mut next = self.head.get();
+        while next != NONE && next < offset {
+            prev = next;
+            next = self.read(next).next;
+        }
+
+        if next != NONE && offset + size == next {
+            let block = self.read(next);
+            size += block.size;
+            next = block.next;
+        }
+        if prev != NONE {
+            let block = self.read(prev);
+            if prev + block.size == offset {
+                self.write(
+                    prev,
+                    FreeBlock {
+                        size: block.size + size,
+                        next,
+                    },
+                );
+                return;
+            }
+        }
+        self.write(
+            offset,
+            FreeBlock {
+                size,
+                next,
+            },
+        );
+        self.link(prev, offset);
+    }
+}
+
+// SAFETY: The allocator uniquely borrows its buffer, so it can be sent to
+// another thread.
+unsafe impl Send for FreeListAllocator<'_> {}
+
+// SAFETY: All blocks are allocated from the buffer.
+unsafe impl OwningAllocator for FreeListAllocator<'_> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        in_buffer(self.start, self.len, ptr, layout)
+    }
+}
+
+// SAFETY: Two free-list allocators with the same buffer must be the same
+// allocator, as each one uniquely borrows its buffer.
+unsafe impl AllocatorEq for FreeListAllocator<'_> {
+    fn same_allocator(&self, other: &Self) -> bool {
+        self.start == other.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    const LEN: usize = UNIT * 16;
+
+    #[repr(align(64))]
+    struct Buffer([MaybeUninit<u8>; LEN]);
+
+    fn layout() -> Layout {
+        Layout::from_size_align(UNIT, 1).unwrap()
+    }
+
+    #[test]
+    fn reuse() {
+        let mut buf = Buffer([MaybeUninit::uninit(); LEN]);
+        let alloc = FreeListAllocator::new(&mut buf.0);
+        let blocks: Vec<_> =
+            (0..16).map(|_| alloc.allocate(layout()).unwrap()).collect();
+        assert_eq!(alloc.allocate(layout()), Err(AllocError));
+        assert_eq!(alloc.fragmentation(), FragmentationStats::default());
+
+        // Writing to every block checks that none of them overlap.
+        for (i, block) in blocks.iter().enumerate() {
+            unsafe { (block.as_ptr() as *mut u8).write_bytes(i as u8, UNIT) };
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            assert!(unsafe { &*block.as_ptr() }.iter().all(|&b| b == i as u8));
+        }
+
+        unsafe { alloc.deallocate(blocks[3].cast(), layout()) };
+        let block = alloc.allocate(layout()).unwrap();
+        assert_eq!(block.cast::<u8>(), blocks[3].cast::<u8>());
+    }
+
+    #[test]
+    fn coalesce() {
+        let mut buf = Buffer([MaybeUninit::uninit(); LEN]);
+        let alloc = FreeListAllocator::new(&mut buf.0);
+        let blocks: Vec<_> =
+            (0..16).map(|_| alloc.allocate(layout()).unwrap()).collect();
+
+        // Free every other block, so no two free blocks are adjacent.
+        for block in blocks.iter().step_by(2) {
+            unsafe { alloc.deallocate(block.cast(), layout()) };
+        }
+        let stats = alloc.fragmentation();
+        assert_eq!(stats.free_blocks, 8);
+        assert_eq!(stats.largest_free_block, UNIT);
+        let big = Layout::from_size_align(UNIT * 2, 1).unwrap();
+        assert_eq!(alloc.allocate(big), Err(AllocError));
+
+        // Freeing the rest merges everything back into one block, in whatever
+        // order they are freed.
+        for &i in &[5, 1, 3, 15, 9, 13, 11, 7] {
+            unsafe { alloc.deallocate(blocks[i].cast(), layout()) };
+        }
+        let stats = alloc.fragmentation();
+        assert_eq!(stats.free_blocks, 1);
+        assert_eq!(stats.largest_free_block, LEN);
+        let all = Layout::from_size_align(LEN, 1).unwrap();
+        let block = alloc.allocate(all).unwrap();
+        assert_eq!(block.cast::<u8>(), blocks[0].cast::<u8>());
+    }
+
+    #[test]
+    fn align() {
+        let mut buf = Buffer([MaybeUninit::uninit(); LEN]);
+        let alloc = FreeListAllocator::new(&mut buf.0);
+        let a = alloc.allocate(layout()).unwrap();
+        let aligned = Layout::from_size_align(1, 64).unwrap();
+        let b = alloc.allocate(aligned).unwrap();
+        assert_eq!(b.as_ptr() as *mut u8 as usize % 64, 0);
+        assert_eq!(
+            unsafe { &*(b.as_ptr() as *const [MaybeUninit<u8>]) }.len(),
+            UNIT
+        );
+
+        // The gap before `b` is still usable.
+        let stats = alloc.fragmentation();
+        assert_eq!(stats.free_blocks, 2);
+        assert_eq!(stats.total_free_bytes, LEN - UNIT * 2);
+        let c = alloc.allocate(layout()).unwrap();
+        assert!(c.cast::<u8>() < b.cast::<u8>());
+
+        unsafe {
+            alloc.deallocate(a.cast(), layout());
+            alloc.deallocate(b.cast(), aligned);
+            alloc.deallocate(c.cast(), layout());
+        }
+        assert_eq!(alloc.fragmentation().largest_free_block, LEN);
+        let huge = Layout::from_size_align(isize::MAX as usize - 63, 64);
+        assert_eq!(alloc.allocate(huge.unwrap()), Err(AllocError));
+    }
+
+    #[test]
+    fn grow_from_empty() {
+        let mut buf = Buffer([MaybeUninit::uninit(); LEN]);
+        let alloc = FreeListAllocator::new(&mut buf.0);
+        // Empty blocks still take a unit, so growing one must free it.
+        let empty = Layout::from_size_align(0, 1).unwrap();
+        let large = Layout::from_size_align(UNIT * 2, 1).unwrap();
+        let ptr = alloc.allocate(empty).unwrap();
+        unsafe {
+            let ptr = alloc.grow(ptr.cast(), empty, large).unwrap();
+            alloc.deallocate(ptr.cast(), large);
+            let ptr = alloc.allocate(empty).unwrap();
+            let ptr = alloc.grow_zeroed(ptr.cast(), empty, large).unwrap();
+            alloc.deallocate(ptr.cast(), large);
+        }
+        let stats = alloc.fragmentation();
+        assert_eq!(stats.free_blocks, 1);
+        assert_eq!(stats.total_free_bytes, LEN);
+    }
+
+    #[test]
+    fn try_new() {
+        let mut buf = Buffer([MaybeUninit::uninit(); LEN]);
+        assert_eq!(
+            FreeListAllocator::try_new(&mut buf.0[..1]).err(),
+            Some(TooSmall {
+                len: 1,
+                required: UNIT,
+            }),
+        );
+        // Misaligned buffers need room to skip to an aligned block.
+        let err = FreeListAllocator::try_new(&mut buf.0[1..UNIT + 1]).err();
+        assert_eq!(err.map(|e| e.required), Some(UNIT * 2 - 1));
+        let alloc = FreeListAllocator::try_new(&mut buf.0[..UNIT]).unwrap();
+        assert!(alloc.allocate(layout()).is_ok());
+    }
+}
@@ -0,0 +1,66 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An [`Allocator`] that can use a locality hint when growing a block.
+///
+/// Arena-style allocators can use the hint to keep related allocations close
+/// together in memory, which can improve cache behavior. The hint is purely
+/// advisory: the default implementation ignores it and calls
+/// [`Allocator::grow`].
+pub trait GrowNear: Allocator {
+    /// Like [`Allocator::grow`], but tries to place the new block near
+    /// `hint`, if provided.
+    ///
+    /// # Safety
+    ///
+    /// See [`alloc::alloc::Allocator::grow`]. `hint` doesn’t need to point
+    /// to any particular memory.
+    unsafe fn grow_near(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        hint: Option<NonNull<u8>>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let _ = hint;
+        // SAFETY: Checked by caller.
+        unsafe { self.grow(ptr, old_layout, new_layout) }
+    }
+}
+
+impl<A> GrowNear for &A
+where
+    A: GrowNear + ?Sized,
+{
+    unsafe fn grow_near(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        hint: Option<NonNull<u8>>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { A::grow_near(*self, ptr, old_layout, new_layout, hint) }
+    }
+}
+
+impl GrowNear for Global {}
@@ -0,0 +1,91 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global};
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+type Hook = Box<dyn Fn(Layout) + Send + Sync>;
+
+static HOOK: AtomicPtr<Hook> = AtomicPtr::new(ptr::null_mut());
+
+/// Installs a process-wide hook that [`HookedGlobal`] calls on every
+/// allocation.
+///
+/// The hook may be called from any thread, possibly concurrently. It must
+/// not allocate through [`HookedGlobal`], as that would call the hook again
+/// recursively.
+///
+/// If a hook is already installed, it is replaced. Because another thread
+/// could still be running the previous hook, it is leaked rather than
+/// dropped.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+pub fn set_alloc_hook<F>(f: F)
+where
+    F: Fn(Layout) + Send + Sync + 'static,
+{
+    let hook: Hook = Box::new(f);
+    HOOK.store(Box::into_raw(Box::new(hook)), Ordering::Release);
+}
+
+/// An allocator that forwards to [`Global`], calling the hook installed by
+/// [`set_alloc_hook`] (if any) with the layout of each allocation.
+///
+/// This allows process-wide observation of allocations made through this
+/// type without having to thread a stateful allocator through every API.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub struct HookedGlobal;
+
+// SAFETY: This impl forwards to `Global`.
+unsafe impl Allocator for HookedGlobal {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let hook = HOOK.load(Ordering::Acquire);
+        if !hook.is_null() {
+            // SAFETY: Installed hooks are never freed.
+            unsafe { (*hook)(layout) };
+        }
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { Global.deallocate(ptr, layout) };
+    }
+}
+
+#[test]
+fn test() {
+    use core::sync::atomic::AtomicUsize;
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    set_alloc_hook(|layout| {
+        assert_eq!(layout, Layout::new::<u64>());
+        COUNT.fetch_add(1, Ordering::Relaxed);
+    });
+
+    let layout = Layout::new::<u64>();
+    for i in 1..=3 {
+        let ptr = HookedGlobal.allocate(layout).unwrap();
+        assert_eq!(COUNT.load(Ordering::Relaxed), i);
+        unsafe { HookedGlobal.deallocate(ptr.cast(), layout) };
+    }
+}
@@ -0,0 +1,229 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global, Overhead};
+use crate::move_block::move_block;
+use alloc::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+/// The size of the header storing the base pointer of an over-aligned block.
+const HEADER_SIZE: usize = size_of::<*mut u8>();
+
+/// An allocator that forwards to `A`, but serves requests with very large
+/// alignments by over-allocating and aligning the block itself.
+///
+/// Requests aligned to more than [`Self::THRESHOLD`] bytes, such as 2 MiB
+/// alignments for huge pages, are allocated from `A` with the alignment of a
+/// pointer and `align - 1 + size_of::<*mut u8>()` extra bytes. The block is
+/// placed at the first suitably aligned address after room for a header,
+/// which stores the pointer returned by `A` so it can be deallocated. This
+/// costs up to `align - 1 + size_of::<*mut u8>()` bytes per request, but
+/// doesn’t depend on `A` supporting large alignments. Other requests are
+/// forwarded unchanged.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HugeAligned<A = Global>(pub A);
+
+impl<A> HugeAligned<A> {
+    /// Requests with an alignment greater than this are aligned manually.
+    pub const THRESHOLD: usize = 4096;
+
+    fn is_huge(layout: Layout) -> bool {
+        layout.align() > Self::THRESHOLD
+    }
+
+    /// Returns the layout requested from `A` for a block with the given
+    /// (huge) layout.
+    fn outer(layout: Layout) -> Result<Layout, AllocError> {
+        let size = layout
+            .size()
+            .checked_add(layout.align() - 1 + HEADER_SIZE)
+            .ok_or(AllocError)?;
+        Layout::from_size_align(size, align_of::<*mut u8>())
+            .map_err(|_| AllocError)
+    }
+
+    /// Places a block for `layout` within `base`, an outer block allocated
+    /// for it, and records `base` in the header before it.
+    ///
+    /// # Safety
+    ///
+    /// `base` must be a block allocated with [`Self::outer`] for `layout`.
+    unsafe fn place(base: NonNull<[u8]>, layout: Layout) -> NonNull<[u8]> {
+        let base = base.as_ptr() as *mut u8;
+        let addr = base as usize + HEADER_SIZE;
+        let padding = addr.wrapping_neg() & (layout.align() - 1);
+        // SAFETY: The outer block has room for the header and the padding
+        // before the block. The header is aligned, since the block is
+        // aligned to more than a pointer.
+        unsafe {
+            let ptr = base.add(HEADER_SIZE + padding);
+            (ptr.sub(HEADER_SIZE) as *mut *mut u8).write(base);
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                ptr,
+                layout.size(),
+            ))
+        }
+    }
+
+    /// Returns the base pointer of an over-aligned block.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a block returned by [`Self::place`].
+    unsafe fn base(ptr: NonNull<u8>) -> NonNull<u8> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            let header = ptr.as_ptr().sub(HEADER_SIZE) as *const *mut u8;
+            NonNull::new_unchecked(header.read())
+        }
+    }
+}
+
+// SAFETY: Requests that aren’t huge are forwarded to `A`. Huge requests are
+// placed within a larger block from `A`, which is at least as aligned as the
+// header and large enough for the header, padding, and block. Huge blocks
+// are only ever resized by moving them.
+unsafe impl<A: Allocator> Allocator for HugeAligned<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !Self::is_huge(layout) {
+            return self.0.allocate(layout);
+        }
+        let base = self.0.allocate(Self::outer(layout)?)?;
+        // SAFETY: `base` was allocated with `Self::outer(layout)`.
+        Ok(unsafe { Self::place(base, layout) })
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !Self::is_huge(layout) {
+            return self.0.allocate_zeroed(layout);
+        }
+        let base = self.0.allocate_zeroed(Self::outer(layout)?)?;
+        // SAFETY: `base` was allocated with `Self::outer(layout)`.
+        Ok(unsafe { Self::place(base, layout) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if !Self::is_huge(layout) {
+            // SAFETY: Checked by caller.
+            return unsafe { self.0.deallocate(ptr, layout) };
+        }
+        let outer = match Self::outer(layout) {
+            Ok(outer) => outer,
+            // SAFETY: Checked by caller (the block was allocated with
+            // `layout`, so this succeeded then).
+            Err(_) => unsafe { core::hint::unreachable_unchecked() },
+        };
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(Self::base(ptr), outer) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if Self::is_huge(old_layout) || Self::is_huge(new_layout) {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, false)
+            };
+        }
+        // SAFETY: Checked by caller.
+        unsafe { self.0.grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if Self::is_huge(old_layout) || Self::is_huge(new_layout) {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, true)
+            };
+        }
+        // SAFETY: Checked by caller.
+        unsafe { self.0.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if Self::is_huge(old_layout) || Self::is_huge(new_layout) {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, false)
+            };
+        }
+        // SAFETY: Checked by caller.
+        unsafe { self.0.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+/// Adds the header and worst-case padding of huge requests to `A`’s
+/// overhead for the outer block.
+impl<A: Overhead> Overhead for HugeAligned<A> {
+    fn overhead(&self, layout: Layout) -> usize {
+        if !Self::is_huge(layout) {
+            return self.0.overhead(layout);
+        }
+        Self::outer(layout).map_or(0, |outer| {
+            outer.size() - layout.size() + self.0.overhead(outer)
+        })
+    }
+}
+
+#[test]
+fn test() {
+    use crate::TrackingAllocator;
+
+    let backing = TrackingAllocator::new(Global);
+    let alloc = HugeAligned(&backing);
+    let layout = Layout::from_size_align(100, 1 << 20).unwrap();
+    let ptr = alloc.allocate_zeroed(layout).unwrap();
+    let ptr = ptr.as_ptr() as *mut u8;
+    assert_eq!(ptr as usize % (1 << 20), 0);
+    unsafe {
+        assert!(
+            (*ptr::slice_from_raw_parts(ptr, 100)).iter().all(|&b| b == 0)
+        );
+        ptr.write_bytes(1, 100);
+    }
+    assert_eq!(backing.stats().current, 100 + alloc.overhead(layout));
+
+    // Growing into the forwarded range moves the block.
+    let small = Layout::from_size_align(200, 8).unwrap();
+    let ptr = NonNull::new(ptr).unwrap();
+    let grown = unsafe { alloc.grow(ptr, layout, small) }.unwrap();
+    let grown = grown.as_ptr() as *mut u8;
+    assert_eq!(unsafe { *grown.add(99) }, 1);
+    unsafe { alloc.deallocate(NonNull::new(grown).unwrap(), small) };
+    let stats = backing.stats();
+    assert_eq!(stats.current, 0);
+    assert_eq!(stats.allocations, stats.deallocations);
+}
@@ -0,0 +1,69 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{Allocator, Global};
+
+/// Compile-time information about an [`Allocator`].
+///
+/// Generic code can inspect these constants in a `const` context, so
+/// properties of the allocator can be checked when the code is compiled:
+///
+/// ```
+/// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+/// use allocator_fallback::{AllocatorInfo, NullAllocator};
+///
+/// /// A structure that can be used where allocating isn’t allowed, such as
+/// /// in an interrupt handler.
+/// struct NoAlloc<A>(A);
+///
+/// impl<A: AllocatorInfo> NoAlloc<A> {
+///     // Fails to compile when `A` allocates. (`assert!` in constants needs
+///     // Rust 1.57.)
+///     const CHECK: () = [()][!A::NEVER_ALLOCATES as usize];
+///
+///     fn new(alloc: A) -> Self {
+///         let () = Self::CHECK;
+///         Self(alloc)
+///     }
+/// }
+///
+/// NoAlloc::new(NullAllocator);
+/// ```
+///
+/// `NoAlloc::new(Global)` would fail to compile.
+pub trait AllocatorInfo: Allocator {
+    /// Whether this allocator never allocates any memory, i.e., every call
+    /// to [`Allocator::allocate`] and related methods fails.
+    ///
+    /// This defaults to `false`. Overriding it to `true` is a promise that
+    /// code may rely on; it must not be done for an allocator that could
+    /// ever succeed.
+    const NEVER_ALLOCATES: bool = false;
+}
+
+impl<A> AllocatorInfo for &A
+where
+    A: AllocatorInfo + ?Sized,
+{
+    const NEVER_ALLOCATES: bool = A::NEVER_ALLOCATES;
+}
+
+impl AllocatorInfo for Global {}
+
+#[cfg(feature = "std")]
+impl AllocatorInfo for std::alloc::System {}
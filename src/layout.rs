@@ -0,0 +1,67 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::AllocError;
+use alloc::alloc::Layout;
+
+/// Extends `base` with `next`, returning the combined layout and the offset
+/// of `next` within it.
+///
+/// This is [`Layout::extend`], but fails with [`AllocError`], so it can be
+/// used with `?` in allocator implementations. The padding inserted before
+/// `next` is the offset minus `base.size()`. Like [`Layout::extend`], this
+/// doesn’t add trailing padding; use [`Layout::pad_to_align`] on the final
+/// layout if needed.
+///
+/// # Example
+///
+/// ```
+/// use allocator_fallback::extend_layout;
+/// use std::alloc::Layout;
+///
+/// let header = Layout::new::<u16>();
+/// let array = Layout::new::<[u64; 4]>();
+/// let tail = Layout::new::<u8>();
+/// let (layout, array_offset) = extend_layout(header, array).unwrap();
+/// let (layout, tail_offset) = extend_layout(layout, tail).unwrap();
+/// assert_eq!((array_offset, tail_offset, layout.size()), (8, 40, 41));
+/// ```
+pub fn extend_layout(
+    base: Layout,
+    next: Layout,
+) -> Result<(Layout, usize), AllocError> {
+    base.extend(next).map_err(|_| AllocError)
+}
+
+#[test]
+fn test() {
+    let header = Layout::from_size_align(12, 4).unwrap();
+    let (layout, a) = extend_layout(header, Layout::new::<u64>()).unwrap();
+    assert_eq!(a, 16);
+    let (layout, b) = extend_layout(layout, Layout::new::<[u8; 3]>()).unwrap();
+    assert_eq!(b, 24);
+    let (layout, c) = extend_layout(layout, Layout::new::<u32>()).unwrap();
+    assert_eq!(c, 28);
+    assert_eq!((layout.size(), layout.align()), (32, 8));
+
+    // Older versions of Rust allow layouts larger than `isize::MAX`, so
+    // overflow `usize` too.
+    let big = Layout::from_size_align(usize::MAX / 2, 1).unwrap();
+    let huge = extend_layout(big, big).map(|(layout, _)| layout);
+    assert!(huge.and_then(|huge| extend_layout(huge, big)).is_err());
+}
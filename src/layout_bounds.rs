@@ -0,0 +1,134 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{Allocator, Global};
+use alloc::alloc::Layout;
+
+/// An [`Allocator`] that can report the range of layouts it supports.
+///
+/// This lets generic code check a backend’s constraints before allocating
+/// (for example, to choose a different backend) instead of discovering them
+/// through [`AllocError`](crate::AllocError). A slab allocator might support
+/// exactly one layout, and an allocator that serves a fixed buffer can’t
+/// serve a block larger than the buffer.
+pub trait LayoutBounds: Allocator {
+    /// Returns the smallest layout this allocator serves. Requests for
+    /// smaller layouts use as much memory as this one.
+    ///
+    /// The default implementation returns a layout with a size of 0 and an
+    /// alignment of 1.
+    fn min_layout(&self) -> Layout {
+        Layout::new::<()>()
+    }
+
+    /// Returns the largest layout this allocator can serve, or [`None`] if
+    /// it is bounded only by available memory.
+    ///
+    /// Requests larger than the returned size always fail. The returned
+    /// layout itself can be served when nothing else is allocated, but
+    /// requests within these bounds may still fail, like when memory is in
+    /// use. The default implementation returns [`None`].
+    fn max_layout(&self) -> Option<Layout> {
+        None
+    }
+}
+
+impl<A> LayoutBounds for &A
+where
+    A: LayoutBounds + ?Sized,
+{
+    fn min_layout(&self) -> Layout {
+        A::min_layout(*self)
+    }
+
+    fn max_layout(&self) -> Option<Layout> {
+        A::max_layout(*self)
+    }
+}
+
+impl LayoutBounds for Global {}
+
+#[cfg(feature = "std")]
+impl LayoutBounds for std::alloc::System {}
+
+#[test]
+fn test() {
+    use crate::{AllocError, Bump};
+    use core::mem::MaybeUninit;
+    use core::ptr::NonNull;
+
+    /// Serves blocks of exactly one layout.
+    struct Slab(Layout);
+
+    unsafe impl Allocator for Slab {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            if layout.size() > self.0.size() || layout.align() > self.0.align()
+            {
+                return Err(AllocError);
+            }
+            Global.allocate(self.0)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+            unsafe { Global.deallocate(ptr, self.0) };
+        }
+    }
+
+    impl LayoutBounds for Slab {
+        fn min_layout(&self) -> Layout {
+            self.0
+        }
+
+        fn max_layout(&self) -> Option<Layout> {
+            Some(self.0)
+        }
+    }
+
+    fn fits<A: LayoutBounds>(alloc: A, layout: Layout) -> bool {
+        alloc.max_layout().map_or(true, |max| {
+            layout.size() <= max.size() && layout.align() <= max.align()
+        })
+    }
+
+    let block = Layout::new::<[u64; 4]>();
+    let slab = Slab(block);
+    assert_eq!(slab.min_layout(), block);
+    assert_eq!(slab.max_layout(), Some(block));
+    assert!(fits(&slab, Layout::new::<u32>()));
+    assert!(!fits(&slab, Layout::new::<[u64; 5]>()));
+    assert!(slab.allocate(Layout::new::<[u64; 5]>()).is_err());
+    assert!(fits(Global, Layout::new::<[u64; 5]>()));
+    assert_eq!(Global.max_layout(), None);
+
+    let mut buf = [MaybeUninit::uninit(); 256];
+    let bump = &Bump::new(&mut buf);
+    let max = bump.max_layout().unwrap();
+    assert_eq!(max.size(), 256);
+    assert_eq!(bump.min_layout(), Layout::new::<()>());
+    let big = Layout::from_size_align(257, 1).unwrap();
+    assert!(!fits(bump, big));
+    assert!(bump.allocate(big).is_err());
+    let ptr = bump.allocate(max).unwrap();
+    assert!(bump.allocate(Layout::new::<u8>()).is_err());
+    unsafe { bump.deallocate(ptr.cast(), max) };
+    let aligned = Layout::from_size_align(128, max.align()).unwrap();
+    assert!(bump.allocate(aligned).is_ok());
+}
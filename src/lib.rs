@@ -20,6 +20,7 @@
 #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
 #![cfg_attr(feature = "doc_cfg", feature(doc_cfg))]
 #![deny(unsafe_op_in_unsafe_fn)]
+#![allow(clippy::needless_doctest_main)]
 
 //! This crate provides a minimal fallback for the standard library’s allocator
 //! API, which is currently unstable.
@@ -115,16 +116,189 @@
 //!
 //! If the crate feature `allocator_api` is enabled, this crate will simply
 //! re-export the real allocator API in the standard library. Of course, this
-//! requires Rust nightly.
+//! requires Rust nightly. The allocators this crate provides then implement
+//! the standard library’s trait directly, so they work with its collections
+//! (e.g., `Vec::new_in(&bump)`).
 //!
 //! If the crate feature `std` is enabled (the default), the crate will use
 //! [`std`]; otherwise, it will be `no_std`. Using [`std`] allows
-//! [`AllocError`] to implement [`std::error::Error`].
+//! [`AllocError`] to implement [`std::error::Error`]. On Rust 1.81 and later,
+//! [`AllocError`] implements [`core::error::Error`] (of which
+//! [`std::error::Error`] is a re-export) regardless of this feature.
+//!
+//! The `std` feature also makes the crate re-export [`System`], with an
+//! implementation of [`Allocator`] that uses `calloc` for zeroed allocations
+//! where possible.
+//!
+//! If the crate feature `test-util` is enabled (it implies `std`), the crate
+//! provides [`CheckedAllocator`], which detects layout mismatches between
+//! allocation and deallocation.
+//!
+//! If the crate feature `backtrace` is enabled (it implies `std`, and
+//! requires Rust 1.65 or later), the crate provides `BacktraceAllocator`,
+//! which records where each live block was allocated.
+//!
+//! If the crate feature `tracing` is enabled, the crate provides
+//! `TracedAllocator`, which emits a [`tracing`] event for each allocator
+//! operation.
+//!
+//! If the crate feature `bytes` is enabled, the crate provides `AllocBuf`,
+//! a buffer allocated from any [`Allocator`] that can be converted into a
+//! [`Bytes`] without copying.
+//!
+//! [`tracing`]: https://docs.rs/tracing
+//! [`Bytes`]: https://docs.rs/bytes/1/bytes/struct.Bytes.html
+//!
+//! If the crate feature `mmap` is enabled (it implies `std`), on Unix
+//! platforms, the crate provides `MmapFileAllocator`, which allocates from a
+//! memory-mapped file so that allocations are persisted to it.
+//!
+//! If the crate feature `prefault` is enabled (it implies `std`), on Unix
+//! platforms, the crate provides `PreFaulting`, which commits memory eagerly
+//! at allocation time.
+//!
+//! If the crate feature `secure` is enabled (it implies `std`), on Unix
+//! platforms, the crate provides `SecureAllocator`, which allocates locked
+//! memory mappings suitable for holding secrets.
 
 extern crate alloc;
 
+mod adaptive;
+mod align_check;
+mod align_profile;
+mod allocator_eq;
+mod arena;
+mod arena_error;
+#[cfg(feature = "backtrace")]
+mod backtrace;
+#[cfg(feature = "bytes")]
+mod buf;
+mod bump;
+mod cache;
+mod cache_align;
+mod chain;
+#[cfg(any(feature = "test-util", doc))]
+mod checked;
+#[cfg(feature = "std")]
+mod churn;
+pub mod collections;
+mod deferred;
+mod ext;
 #[cfg(not(feature = "allocator_api"))]
 mod fallback;
+mod ffi;
+mod fixed_align;
+mod fragmentation;
+mod free_list;
+mod grow_near;
+#[cfg(feature = "std")]
+mod hook;
+mod huge_align;
+mod info;
+mod layout;
+mod layout_bounds;
+mod limit;
+mod lock_free_pool;
+mod max_align;
+mod middleware;
+#[cfg(all(unix, feature = "mmap"))]
+mod mmap_file;
+mod move_block;
+mod no_realloc;
+mod null;
+mod overhead;
+mod owning;
+#[cfg(all(
+    unix,
+    any(feature = "mmap", feature = "prefault", feature = "secure")
+))]
+mod page;
+#[cfg(all(unix, feature = "prefault"))]
+mod prefault;
+mod red_zone;
+#[cfg(feature = "std")]
+mod reentrant;
+mod region;
+mod ring;
+mod round_len;
+mod rounded;
+#[cfg(feature = "std")]
+mod scoped;
+mod scratch;
+#[cfg(all(unix, feature = "secure"))]
+mod secure;
+mod size_class;
+mod size_prefixed;
+mod size_tracking;
+mod spin;
+mod stats_global;
+#[cfg(feature = "tracing")]
+mod traced;
+mod tracking;
+
+pub use adaptive::Adaptive;
+pub use align_profile::AlignmentProfiler;
+pub use allocator_eq::AllocatorEq;
+pub use arena::TypedArena;
+pub use arena_error::{ArenaError, TooSmall};
+#[cfg(feature = "backtrace")]
+pub use backtrace::BacktraceAllocator;
+#[cfg(feature = "bytes")]
+pub use buf::AllocBuf;
+pub use bump::{Bump, ResetGuard};
+pub use cache::OneBlockCache;
+pub use cache_align::CacheAligned;
+pub use chain::Chain;
+#[cfg(any(feature = "test-util", doc))]
+pub use checked::CheckedAllocator;
+#[cfg(feature = "std")]
+pub use churn::{ChurnDetector, ChurnReport, SizeChurn};
+pub use deferred::DeferredFree;
+pub use ext::{AllocatorExt, Deallocator};
+pub use ffi::{
+    CAllocCallbacks, CAllocShim, CFree, CMalloc, CRealloc, c_alloc_shim,
+};
+pub use fixed_align::FixedAlign;
+pub use fragmentation::FragmentationStats;
+pub use free_list::FreeListAllocator;
+pub use grow_near::GrowNear;
+#[cfg(feature = "std")]
+pub use hook::{HookedGlobal, set_alloc_hook};
+pub use huge_align::HugeAligned;
+pub use info::AllocatorInfo;
+pub use layout::extend_layout;
+pub use layout_bounds::LayoutBounds;
+pub use limit::LimitAllocator;
+pub use lock_free_pool::LockFreePool;
+pub use max_align::MaxAlign;
+pub use middleware::{Instrumented, Middleware};
+#[cfg(all(unix, feature = "mmap"))]
+pub use mmap_file::MmapFileAllocator;
+pub use no_realloc::NoRealloc;
+pub use null::NullAllocator;
+pub use overhead::Overhead;
+pub use owning::OwningAllocator;
+#[cfg(all(unix, feature = "prefault"))]
+pub use prefault::PreFaulting;
+pub use red_zone::RedZone;
+#[cfg(feature = "std")]
+pub use reentrant::NonReentrant;
+pub use region::RegionAllocator;
+pub use ring::RingAllocator;
+pub use round_len::RoundLen;
+#[cfg(feature = "std")]
+pub use scoped::{CurrentAllocator, current_allocator, with_allocator};
+pub use scratch::ScratchAllocator;
+#[cfg(all(unix, feature = "secure"))]
+pub use secure::SecureAllocator;
+pub use size_class::SizeClassed;
+pub use size_prefixed::SizePrefixed;
+pub use size_tracking::SizeTrackingAllocator;
+pub use spin::SpinLocked;
+pub use stats_global::StatsGlobal;
+#[cfg(feature = "tracing")]
+pub use traced::TracedAllocator;
+pub use tracking::{ByteCount, Stats, TrackingAllocator};
 
 #[cfg(not(feature = "allocator_api"))]
 pub use fallback::{AllocError, Allocator, Global};
@@ -132,9 +306,108 @@ pub use fallback::{AllocError, Allocator, Global};
 #[cfg(feature = "allocator_api")]
 pub use alloc::alloc::{AllocError, Allocator, Global};
 
+#[cfg(feature = "std")]
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+pub use std::alloc::System;
+
+/// A type-erased reference to an allocator.
+///
+/// This is `&'a (dyn Allocator + 'a)`, spelling out the trait object’s
+/// lifetime, which is elided differently depending on where `dyn Allocator`
+/// appears (e.g., it defaults to `'static` in `Box<dyn Allocator>`). Because
+/// [`Allocator`] is implemented for references, this type can be passed to
+/// any API that takes an allocator by value, including allocators that
+/// borrow data, like [`Bump`]:
+///
+/// ```
+/// # #![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+/// use allocator_fallback::{Allocator, Bump, DynAlloc};
+/// use core::alloc::Layout;
+/// use core::mem::MaybeUninit;
+///
+/// fn build<A: Allocator>(alloc: A) {
+///     let layout = Layout::new::<u64>();
+///     let ptr = alloc.allocate(layout).unwrap();
+///     unsafe { alloc.deallocate(ptr.cast(), layout) };
+/// }
+///
+/// struct Holder<'a> {
+///     alloc: DynAlloc<'a>,
+/// }
+///
+/// let mut buf = [MaybeUninit::uninit(); 64];
+/// let bump = Bump::new(&mut buf);
+/// let holder = Holder { alloc: &&bump };
+/// build(holder.alloc);
+/// ```
+pub type DynAlloc<'a> = &'a (dyn Allocator + 'a);
+
 /// For use in build scripts. See [Usage](crate#usage).
 pub const HAS_ALLOCATOR_API: bool = cfg!(has_allocator_api);
 
+// Statically checks that the documented types implement `Allocator`, so a
+// refactor can’t silently drop one of the impls.
+const _: () = {
+    fn assert_impl<A: Allocator + ?Sized>() {}
+
+    #[allow(dead_code)]
+    fn assert_impls() {
+        assert_impl::<Global>();
+        assert_impl::<&Global>();
+        assert_impl::<&&Global>();
+        assert_impl::<dyn Allocator>();
+        assert_impl::<&dyn Allocator>();
+        assert_impl::<DynAlloc<'_>>();
+        assert_impl::<alloc::boxed::Box<dyn Allocator>>();
+        assert_impl::<&Bump<'_>>();
+        assert_impl::<Adaptive<&Bump<'_>, Global>>();
+        assert_impl::<AlignmentProfiler<Global>>();
+        assert_impl::<CacheAligned<Global>>();
+        assert_impl::<Chain<&Bump<'_>, Global>>();
+        assert_impl::<DeferredFree<Global>>();
+        assert_impl::<FixedAlign<Global, 16>>();
+        assert_impl::<FreeListAllocator<'_>>();
+        assert_impl::<HugeAligned>();
+        assert_impl::<Instrumented<Global, ()>>();
+        assert_impl::<LimitAllocator<Global>>();
+        assert_impl::<LockFreePool<Global>>();
+        assert_impl::<MaxAlign<Global, 16>>();
+        assert_impl::<NoRealloc<Global>>();
+        assert_impl::<NullAllocator>();
+        assert_impl::<OneBlockCache<Global>>();
+        assert_impl::<RedZone<Global, 16>>();
+        assert_impl::<RegionAllocator<Global>>();
+        assert_impl::<RingAllocator<'_>>();
+        assert_impl::<RoundLen<Global, 16>>();
+        assert_impl::<&ScratchAllocator<Global>>();
+        assert_impl::<SizeClassed<Global>>();
+        assert_impl::<SizePrefixed<Global>>();
+        assert_impl::<SpinLocked<Global>>();
+        assert_impl::<StatsGlobal>();
+        assert_impl::<TrackingAllocator<Global>>();
+        #[cfg(feature = "std")]
+        {
+            assert_impl::<ChurnDetector<Global>>();
+            assert_impl::<CurrentAllocator>();
+            assert_impl::<HookedGlobal>();
+            assert_impl::<NonReentrant<Global>>();
+            assert_impl::<System>();
+        }
+        #[cfg(feature = "test-util")]
+        assert_impl::<CheckedAllocator<Global>>();
+        #[cfg(feature = "backtrace")]
+        assert_impl::<BacktraceAllocator<Global>>();
+        #[cfg(all(unix, feature = "mmap"))]
+        assert_impl::<&MmapFileAllocator>();
+        #[cfg(all(unix, feature = "prefault"))]
+        assert_impl::<PreFaulting<Global>>();
+        #[cfg(all(unix, feature = "secure"))]
+        assert_impl::<SecureAllocator>();
+        #[cfg(feature = "tracing")]
+        assert_impl::<TracedAllocator<Global>>();
+    }
+};
+
 #[test]
 fn test() {
     use alloc::alloc::Layout;
@@ -145,3 +418,375 @@ fn test() {
         (&&&Global).deallocate(ptr.cast(), Layout::new::<u64>());
     }
 }
+
+#[test]
+// The references are the point of this test.
+#[allow(clippy::needless_borrows_for_generic_args)]
+fn test_references() {
+    use alloc::alloc::Layout;
+
+    fn exercise<A: Allocator>(alloc: A) {
+        let small = Layout::new::<[u8; 8]>();
+        let large = Layout::new::<[u8; 32]>();
+        let ptr = alloc.allocate_zeroed(small).unwrap();
+        unsafe {
+            let bytes = &*(ptr.as_ptr() as *const [u8]);
+            assert!(bytes.iter().all(|&b| b == 0));
+            (ptr.as_ptr() as *mut u8).write_bytes(0x11, 8);
+            let ptr = alloc.grow(ptr.cast(), small, large).unwrap();
+            let ptr = alloc.shrink(ptr.cast(), large, small).unwrap();
+            let bytes = &*(ptr.as_ptr() as *const [u8]);
+            assert!(bytes[..8].iter().all(|&b| b == 0x11));
+            alloc.deallocate(ptr.cast(), small);
+        }
+        let ptr = alloc.allocate(large).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), large) };
+    }
+
+    exercise(Global);
+    exercise(&Global);
+    exercise(&&Global);
+    exercise(&&&Global);
+    let dyn_alloc: &dyn Allocator = &Global;
+    exercise(dyn_alloc);
+    exercise(&dyn_alloc);
+    exercise(&&dyn_alloc);
+}
+
+#[test]
+fn test_grow_from_empty() {
+    use alloc::alloc::Layout;
+    use core::ptr::NonNull;
+
+    fn check<A: Allocator>(alloc: A) {
+        let empty = Layout::from_size_align(0, 8).unwrap();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let dangling = NonNull::<u64>::dangling().cast();
+        unsafe {
+            let ptr = alloc.grow(dangling, empty, layout).unwrap();
+            alloc.deallocate(ptr.cast(), layout);
+            let ptr = alloc.grow_zeroed(dangling, empty, layout).unwrap();
+            let bytes = &*(ptr.as_ptr() as *const [u8]);
+            assert!(bytes[..64].iter().all(|&b| b == 0));
+            alloc.deallocate(ptr.cast(), layout);
+        }
+    }
+
+    check(Global);
+}
+
+#[test]
+fn test_global_allocate_bytes() {
+    use alloc::alloc::Layout;
+    use core::mem::MaybeUninit;
+
+    for size in (1..=64).chain([1000, 4096, 100_000].iter().copied()) {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let ptr = Global.allocate(layout).unwrap();
+        unsafe {
+            let len = (&*(ptr.as_ptr() as *const [MaybeUninit<u8>])).len();
+            assert_eq!(len, size);
+            let bytes = ptr.as_ptr() as *mut u8;
+            bytes.write_bytes(size as u8, size);
+            assert_eq!(*bytes.add(size - 1), size as u8);
+            Global.deallocate(ptr.cast(), layout);
+        }
+    }
+}
+
+#[test]
+fn test_global_shrink_in_place() {
+    use alloc::alloc::Layout;
+
+    let old = Layout::from_size_align(4096, 8).unwrap();
+    let new = Layout::from_size_align(1024, 8).unwrap();
+    let ptr = Global.allocate(old).unwrap().cast::<u8>();
+    unsafe {
+        ptr.as_ptr().write_bytes(5, 4096);
+        let shrunk = Global.shrink(ptr, old, new).unwrap();
+        // glibc’s `realloc` shrinks small blocks in place.
+        #[cfg(all(target_os = "linux", target_env = "gnu"))]
+        assert_eq!(shrunk.cast::<u8>(), ptr);
+        let bytes = &*(shrunk.as_ptr() as *const [u8]);
+        assert_eq!(bytes.len(), 1024);
+        assert!(bytes.iter().all(|&b| b == 5));
+        Global.deallocate(shrunk.cast(), new);
+    }
+}
+
+#[test]
+fn test_global_grow_zeroed() {
+    use alloc::alloc::Layout;
+
+    let old = Layout::from_size_align(16, 8).unwrap();
+    for &new in &[
+        Layout::from_size_align(4096, 8).unwrap(),
+        Layout::from_size_align(4096, 64).unwrap(),
+    ] {
+        let ptr = Global.allocate(old).unwrap().cast::<u8>();
+        unsafe {
+            ptr.as_ptr().write_bytes(0xaa, 16);
+            let ptr = Global.grow_zeroed(ptr, old, new).unwrap();
+            assert_eq!(ptr.as_ptr() as *mut u8 as usize % new.align(), 0);
+            let bytes = &*(ptr.as_ptr() as *const [u8]);
+            assert!(bytes[..16].iter().all(|&b| b == 0xaa));
+            assert!(bytes[16..new.size()].iter().all(|&b| b == 0));
+            Global.deallocate(ptr.cast(), new);
+        }
+    }
+}
+
+#[test]
+fn test_default_methods_alignment() {
+    use alloc::alloc::Layout;
+    use core::cell::Cell;
+    use core::ptr::NonNull;
+
+    /// Asserts that every allocation has the expected alignment.
+    struct ExpectAlign(Cell<usize>);
+
+    unsafe impl Allocator for ExpectAlign {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            assert_eq!(layout.align(), self.0.get());
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    let is_aligned =
+        |ptr: NonNull<[u8]>| ptr.as_ptr() as *mut u8 as usize % 64 == 0;
+    let low = Layout::from_size_align(32, 8).unwrap();
+    let high = Layout::from_size_align(64, 64).unwrap();
+    let small_high = Layout::from_size_align(16, 64).unwrap();
+    let alloc = ExpectAlign(Cell::new(64));
+
+    let ptr = alloc.allocate_zeroed(high).unwrap();
+    assert!(is_aligned(ptr));
+    unsafe { alloc.deallocate(ptr.cast(), high) };
+
+    for &zeroed in &[false, true] {
+        alloc.0.set(8);
+        let ptr = alloc.allocate(low).unwrap();
+        alloc.0.set(64);
+        let ptr = unsafe {
+            if zeroed {
+                alloc.grow_zeroed(ptr.cast(), low, high)
+            } else {
+                alloc.grow(ptr.cast(), low, high)
+            }
+        }
+        .unwrap();
+        assert!(is_aligned(ptr));
+        unsafe { alloc.deallocate(ptr.cast(), high) };
+    }
+
+    alloc.0.set(8);
+    let ptr = alloc.allocate(low).unwrap();
+    alloc.0.set(64);
+    let ptr = unsafe { alloc.shrink(ptr.cast(), low, small_high) }.unwrap();
+    assert!(is_aligned(ptr));
+    unsafe { alloc.deallocate(ptr.cast(), small_high) };
+}
+
+#[test]
+fn test_shrink_over_reported() {
+    use alloc::alloc::Layout;
+    use core::ptr::NonNull;
+
+    const EXTRA: usize = 32;
+
+    /// Returns blocks `EXTRA` bytes larger than requested, filled with 0xaa.
+    struct OverReporting;
+
+    unsafe impl Allocator for OverReporting {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            let size = layout.size() + EXTRA;
+            let layout =
+                Layout::from_size_align(size, layout.align()).unwrap();
+            let ptr = Global.allocate(layout)?;
+            unsafe { (ptr.as_ptr() as *mut u8).write_bytes(0xaa, size) };
+            Ok(ptr)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            let size = layout.size() + EXTRA;
+            let layout =
+                Layout::from_size_align(size, layout.align()).unwrap();
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    let old = Layout::new::<[u8; 16]>();
+    let new = Layout::new::<[u8; 8]>();
+    let ptr = OverReporting.allocate(old).unwrap().as_ptr() as *mut u8;
+    unsafe {
+        // The caller may use the entire block, including the excess.
+        ptr.write_bytes(0x11, 16);
+        ptr.add(16).write_bytes(0x55, EXTRA);
+        let new_ptr = OverReporting
+            .shrink(NonNull::new_unchecked(ptr), old, new)
+            .unwrap();
+        let bytes = &*(new_ptr.as_ptr() as *const [u8]);
+        assert_eq!(bytes.len(), 8 + EXTRA);
+        assert!(bytes[..8].iter().all(|&b| b == 0x11));
+        assert!(bytes[8..].iter().all(|&b| b == 0xaa));
+        OverReporting.deallocate(new_ptr.cast(), new);
+    }
+}
+
+#[cfg(not(feature = "allocator_api"))]
+#[test]
+#[cfg_attr(
+    debug_assertions,
+    should_panic(expected = "smaller than requested")
+)]
+fn test_grow_zeroed_under_reported() {
+    use alloc::alloc::Layout;
+    use core::ptr::{self, NonNull};
+
+    /// Reports blocks as a quarter of their real size.
+    struct UnderReporting;
+
+    unsafe impl Allocator for UnderReporting {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            let ptr = Global.allocate(layout)?.as_ptr() as *mut u8;
+            let ptr = ptr::slice_from_raw_parts_mut(ptr, layout.size() / 4);
+            Ok(NonNull::new(ptr).unwrap())
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe { Global.deallocate(ptr, layout) }
+        }
+    }
+
+    let old = Layout::new::<[u8; 16]>();
+    let new = Layout::new::<[u8; 32]>();
+    let ptr = UnderReporting.allocate(old).unwrap().cast::<u8>();
+    unsafe {
+        ptr.as_ptr().write_bytes(0x11, 16);
+        let result = UnderReporting.grow_zeroed(ptr, old, new);
+        assert_eq!(result, Err(AllocError));
+        assert!((0..16).all(|i| *ptr.as_ptr().add(i) == 0x11));
+        UnderReporting.deallocate(ptr, old);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_system() {
+    use alloc::alloc::Layout;
+    let layout = Layout::from_size_align(1 << 20, 8).unwrap();
+    let ptr = System.allocate_zeroed(layout).unwrap();
+    unsafe {
+        let bytes = &*(ptr.as_ptr() as *const [u8]);
+        assert_eq!(bytes.len(), 1 << 20);
+        assert!(bytes.iter().all(|&b| b == 0));
+        System.deallocate(ptr.cast(), layout);
+    }
+
+    // Alignments larger than `calloc` guarantees.
+    let layout = Layout::from_size_align(4096, 4096).unwrap();
+    let ptr = System.allocate_zeroed(layout).unwrap();
+    assert_eq!(ptr.as_ptr() as *mut u8 as usize % 4096, 0);
+    unsafe {
+        let bytes = &*(ptr.as_ptr() as *const [u8]);
+        assert!(bytes.iter().all(|&b| b == 0));
+        System.deallocate(ptr.cast(), layout);
+    }
+}
+
+#[test]
+fn test_shrink_to_zero() {
+    use alloc::alloc::Layout;
+    let old = Layout::new::<[u64; 4]>();
+    let new = Layout::from_size_align(0, 8).unwrap();
+    let ptr = Global.allocate(old).unwrap();
+    unsafe {
+        let ptr = Global.shrink(ptr.cast(), old, new).unwrap();
+        assert_eq!((&*(ptr.as_ptr() as *const [u8])).len(), 0);
+        assert_eq!(ptr.as_ptr() as *mut u8 as usize % 8, 0);
+        Global.deallocate(ptr.cast(), new);
+    }
+}
+
+#[test]
+fn test_same_allocator() {
+    assert!(Global.same_allocator(&Global));
+    assert!(<&Global>::same_allocator(&&Global, &&Global));
+}
+
+#[test]
+fn test_huge() {
+    use alloc::alloc::Layout;
+    let size = isize::MAX as usize - 63;
+    let layout = Layout::from_size_align(size, 64).unwrap();
+    assert_eq!(Global.allocate(layout), Err(AllocError));
+}
+
+#[cfg(has_core_error)]
+#[test]
+fn test_core_error() {
+    let err: &dyn core::error::Error = &AllocError;
+    assert_eq!(
+        alloc::string::ToString::to_string(err),
+        "memory allocation failed"
+    );
+}
+
+// `Allocator` can't be implemented for `ManuallyDrop<A>` or `Cell<A>` here,
+// because with the `allocator_api` feature, both the trait and the types are
+// foreign. Borrowing the inner allocator works in either configuration.
+#[cfg(all(feature = "std", not(feature = "allocator_api")))]
+#[test]
+fn test_stateless_eq() {
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::{BTreeMap, HashSet};
+    use std::hash::{Hash, Hasher};
+
+    fn hash<T: Hash>(value: &T) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    assert_eq!(Global, Global);
+    assert_eq!(Global.cmp(&Global), core::cmp::Ordering::Equal);
+    assert_eq!(hash(&Global), hash(&Global));
+    assert_eq!(HookedGlobal, HookedGlobal);
+
+    let mut registry = BTreeMap::new();
+    registry.insert(Global, "global");
+    assert_eq!(registry.get(&Global), Some(&"global"));
+    let set: HashSet<_> =
+        [HookedGlobal, HookedGlobal].iter().copied().collect();
+    assert_eq!(set.len(), 1);
+}
+
+#[test]
+fn test_manually_drop() {
+    use alloc::alloc::Layout;
+    use core::mem::ManuallyDrop;
+
+    fn roundtrip<A: Allocator>(alloc: A) {
+        let layout = Layout::new::<u64>();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+    }
+
+    let mut buf = [core::mem::MaybeUninit::uninit(); 64];
+    let mut alloc = ManuallyDrop::new(Bump::new(&mut buf));
+    roundtrip(&*alloc);
+    unsafe { ManuallyDrop::drop(&mut alloc) };
+}
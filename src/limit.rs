@@ -0,0 +1,180 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// An allocator that forwards to `A`, but fails requests that would bring
+/// the total size of live blocks above a fixed limit.
+///
+/// Sizes are counted according to the layouts requested, not the lengths of
+/// the blocks returned by `A`.
+#[derive(Debug)]
+pub struct LimitAllocator<A> {
+    inner: A,
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl<A> LimitAllocator<A> {
+    /// Creates a new [`LimitAllocator`] that allows at most `limit` bytes to
+    /// be allocated from `inner` at once.
+    pub fn new(inner: A, limit: usize) -> Self {
+        Self {
+            inner,
+            limit,
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the maximum total size of live blocks.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of bytes that can currently be allocated before
+    /// the limit is reached.
+    ///
+    /// This is advisory: if the allocator is shared between threads, another
+    /// thread may allocate between this call and a subsequent allocation.
+    pub fn available(&self) -> usize {
+        self.limit - self.used.load(Ordering::Relaxed)
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Reserves `size` bytes of the limit.
+    fn reserve(&self, size: usize) -> Result<(), AllocError> {
+        self.used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                used.checked_add(size).filter(|&used| used <= self.limit)
+            })
+            .map(|_| ())
+            .map_err(|_| AllocError)
+    }
+
+    fn release(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    /// Reserves `size` bytes, then calls `f`, releasing them if it fails.
+    fn reserve_for<F>(
+        &self,
+        size: usize,
+        f: F,
+    ) -> Result<NonNull<[u8]>, AllocError>
+    where
+        F: FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    {
+        self.reserve(size)?;
+        f().map_err(|e| {
+            self.release(size);
+            e
+        })
+    }
+}
+
+// SAFETY: This impl forwards to `A`, only ever failing additional requests.
+unsafe impl<A: Allocator> Allocator for LimitAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.reserve_for(layout.size(), || self.inner.allocate(layout))
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.reserve_for(layout.size(), || self.inner.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+        self.release(layout.size());
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let size = new_layout.size() - old_layout.size();
+        // SAFETY: Checked by caller.
+        self.reserve_for(size, || unsafe {
+            self.inner.grow(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let size = new_layout.size() - old_layout.size();
+        // SAFETY: Checked by caller.
+        self.reserve_for(size, || unsafe {
+            self.inner.grow_zeroed(ptr, old_layout, new_layout)
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let new = unsafe { self.inner.shrink(ptr, old_layout, new_layout) }?;
+        self.release(old_layout.size() - new_layout.size());
+        Ok(new)
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    let alloc = LimitAllocator::new(Global, 100);
+    assert_eq!(alloc.limit(), 100);
+    assert_eq!(alloc.available(), 100);
+
+    let small = Layout::new::<[u8; 30]>();
+    let large = Layout::new::<[u8; 60]>();
+    let a = alloc.allocate(small).unwrap();
+    let b = alloc.allocate(large).unwrap();
+    assert_eq!(alloc.available(), 10);
+    assert_eq!(alloc.allocate(small), Err(AllocError));
+    assert_eq!(alloc.available(), 10);
+    unsafe {
+        assert!(alloc.grow(a.cast(), small, large).is_err());
+        alloc.deallocate(b.cast(), large);
+        assert_eq!(alloc.available(), 70);
+        let a = alloc.grow(a.cast(), small, large).unwrap();
+        assert_eq!(alloc.available(), 40);
+        let a = alloc.shrink(a.cast(), large, small).unwrap();
+        assert_eq!(alloc.available(), 70);
+        alloc.deallocate(a.cast(), small);
+    }
+    assert_eq!(alloc.available(), 100);
+}
@@ -0,0 +1,306 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global};
+use alloc::alloc::Layout;
+use core::fmt::{self, Debug, Formatter};
+use core::hint;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+/// Stored at the start of each free block.
+struct Node {
+    next: *mut Node,
+}
+
+/// A thread-safe pool of fixed-size blocks, kept on a lock-free free list.
+///
+/// Requests that fit the block layout given to [`Self::new`] are served
+/// from the free list, or from a fresh block allocated from `A` if the list
+/// is empty. Deallocated blocks are pushed back onto the list and stay
+/// there until the pool is dropped, so many threads can allocate and free
+/// same-sized objects (like per-request state in a server) without going
+/// through `A`. Requests that don’t fit a block are forwarded to `A`.
+///
+/// The free list is a Treiber stack. Pushing (i.e., deallocating) is
+/// lock-free. Popping from a Treiber stack is prone to the ABA problem: a
+/// thread could read the top block and its successor, and then, after other
+/// threads pop both and push the first back, replace the top with a block
+/// that is in use. To rule this out without double-width atomics, threads
+/// taking a block from the list hold a short spinlock among themselves,
+/// which never blocks deallocation.
+pub struct LockFreePool<A: Allocator = Global> {
+    alloc: A,
+    layout: Layout,
+    head: AtomicPtr<Node>,
+    popping: AtomicBool,
+}
+
+impl LockFreePool {
+    /// Creates a new pool of blocks for `block` that allocates from
+    /// [`Global`].
+    ///
+    /// # Panics
+    ///
+    /// See [`Self::new_in`].
+    pub fn new(block: Layout) -> Self {
+        Self::new_in(block, Global)
+    }
+}
+
+impl<A: Allocator> LockFreePool<A> {
+    /// Creates a new pool of blocks for `block` that allocates from
+    /// `alloc`.
+    ///
+    /// Blocks are at least as large and as aligned as a pointer, since free
+    /// blocks store a pointer to the next one.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if raising the block size and alignment to
+    /// those of a pointer overflows.
+    pub fn new_in(block: Layout, alloc: A) -> Self {
+        let layout = Layout::from_size_align(
+            block.size().max(size_of::<Node>()),
+            block.align().max(align_of::<Node>()),
+        )
+        .expect("block layout overflow");
+        Self {
+            alloc,
+            layout,
+            head: AtomicPtr::new(ptr::null_mut()),
+            popping: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the layout of the blocks in this pool.
+    pub fn block_layout(&self) -> Layout {
+        self.layout
+    }
+
+    fn fits(&self, layout: Layout) -> bool {
+        layout.size() <= self.layout.size()
+            && layout.align() <= self.layout.align()
+    }
+
+    /// Pushes a block onto the free list.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be an unused block with `self.layout`, allocated from
+    /// `self.alloc`.
+    unsafe fn push(&self, ptr: NonNull<u8>) {
+        let node = ptr.as_ptr() as *mut Node;
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            // SAFETY: Checked by caller. The block is large and aligned
+            // enough for a `Node`.
+            unsafe {
+                node.write(Node {
+                    next: head,
+                })
+            };
+            match self.head.compare_exchange_weak(
+                head,
+                node,
+                Ordering::Release,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Pops a block from the free list, if it isn’t empty.
+    fn pop(&self) -> Option<NonNull<u8>> {
+        if self.head.load(Ordering::Relaxed).is_null() {
+            return None;
+        }
+        while self
+            .popping
+            .compare_exchange_weak(
+                false,
+                true,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            hint::spin_loop();
+        }
+        let mut head = self.head.load(Ordering::Acquire);
+        let block = loop {
+            let node = match NonNull::new(head) {
+                Some(node) => node,
+                None => break None,
+            };
+            // SAFETY: Only this thread can pop blocks, so `node` stays on
+            // the list, and its `next` field isn’t written, until it is
+            // popped here. Pushes with `Release` make the field visible.
+            let next = unsafe { (*node.as_ptr()).next };
+            match self.head.compare_exchange_weak(
+                head,
+                next,
+                Ordering::Acquire,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break Some(node.cast()),
+                Err(current) => head = current,
+            }
+        };
+        self.popping.store(false, Ordering::Release);
+        block
+    }
+}
+
+impl<A: Allocator + Debug> Debug for LockFreePool<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LockFreePool")
+            .field("alloc", &self.alloc)
+            .field("layout", &self.layout)
+            .finish()
+    }
+}
+
+impl<A: Allocator> Drop for LockFreePool<A> {
+    fn drop(&mut self) {
+        let mut next = *self.head.get_mut();
+        while let Some(node) = NonNull::new(next) {
+            // SAFETY: Blocks on the free list are unused, and were allocated
+            // from `self.alloc` with `self.layout`.
+            unsafe {
+                next = (*node.as_ptr()).next;
+                self.alloc.deallocate(node.cast(), self.layout);
+            }
+        }
+    }
+}
+
+// SAFETY: Blocks that fit are taken either from the free list, which holds
+// only unused blocks (and which can’t return a block twice, as argued in the
+// type’s documentation), or from `A`. Other requests are forwarded to `A`.
+// Whether a block is pooled depends only on its layout, which must fit the
+// block when it is deallocated.
+unsafe impl<A: Allocator> Allocator for LockFreePool<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.fits(layout) {
+            return self.alloc.allocate(layout);
+        }
+        let block = match self.pop() {
+            Some(block) => block,
+            None => self.alloc.allocate(self.layout)?.cast(),
+        };
+        let ptr =
+            ptr::slice_from_raw_parts_mut(block.as_ptr(), self.layout.size());
+        // SAFETY: `ptr` came from a `NonNull`.
+        Ok(unsafe { NonNull::new_unchecked(ptr) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.fits(layout) {
+            // SAFETY: Checked by caller.
+            unsafe { self.push(ptr) };
+        } else {
+            // SAFETY: Checked by caller.
+            unsafe { self.alloc.deallocate(ptr, layout) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrackingAllocator;
+
+    #[test]
+    fn reuse() {
+        let backing = TrackingAllocator::new(Global);
+        let pool = LockFreePool::new_in(Layout::new::<[u8; 24]>(), &backing);
+        let small = Layout::new::<u32>();
+        let a = pool.allocate(small).unwrap();
+        let b = pool.allocate(small).unwrap();
+        unsafe {
+            pool.deallocate(a.cast(), small);
+            pool.deallocate(b.cast(), small);
+        }
+        assert_eq!(pool.allocate(small).unwrap(), b);
+        assert_eq!(pool.allocate(small).unwrap(), a);
+        assert_eq!(backing.stats().allocations, 2);
+
+        let large = Layout::new::<[u8; 32]>();
+        let c = pool.allocate(large).unwrap();
+        unsafe {
+            pool.deallocate(c.cast(), large);
+            pool.deallocate(a.cast(), small);
+        }
+        drop(pool);
+        let stats = backing.stats();
+        assert_eq!(stats.allocations, 3);
+        // `b` is still in use.
+        assert_eq!(stats.deallocations, 2);
+        unsafe { backing.deallocate(b.cast(), Layout::new::<[u8; 24]>()) };
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn stress() {
+        use std::collections::HashSet;
+        use std::sync::{Arc, Mutex};
+        use std::thread;
+
+        const THREADS: usize = 8;
+        const BLOCKS: usize = 16;
+        let pool = Arc::new(LockFreePool::new(Layout::new::<[usize; 2]>()));
+        let live = Arc::new(Mutex::new(HashSet::new()));
+        let layout = pool.block_layout();
+        let threads: Vec<_> = (0..THREADS)
+            .map(|id| {
+                let (pool, live) = (pool.clone(), live.clone());
+                thread::spawn(move || {
+                    for _ in 0..500 {
+                        let blocks: Vec<_> = (0..BLOCKS)
+                            .map(|i| {
+                                let block = pool
+                                    .allocate(layout)
+                                    .unwrap()
+                                    .cast::<[usize; 2]>();
+                                let addr = block.as_ptr() as usize;
+                                assert!(live.lock().unwrap().insert(addr));
+                                unsafe { block.as_ptr().write([id, i]) };
+                                block
+                            })
+                            .collect();
+                        thread::yield_now();
+                        for (i, block) in blocks.into_iter().enumerate() {
+                            assert_eq!(unsafe { *block.as_ptr() }, [id, i]);
+                            live.lock()
+                                .unwrap()
+                                .remove(&(block.as_ptr() as usize));
+                            unsafe { pool.deallocate(block.cast(), layout) };
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
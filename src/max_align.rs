@@ -0,0 +1,119 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that forwards to `A`, but fails any request with an
+/// alignment greater than `MAX`.
+///
+/// This is useful for backends that can’t honor large alignments, as it
+/// makes such requests fail cleanly with [`AllocError`] rather than reaching
+/// the backend.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaxAlign<A, const MAX: usize>(pub A);
+
+impl<A, const MAX: usize> MaxAlign<A, MAX> {
+    fn check(layout: Layout) -> Result<(), AllocError> {
+        if layout.align() > MAX {
+            Err(AllocError)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+// SAFETY: This impl forwards to `A`, only ever failing additional requests.
+unsafe impl<A: Allocator, const MAX: usize> Allocator for MaxAlign<A, MAX> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Self::check(layout)?;
+        self.0.allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Self::check(layout)?;
+        self.0.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Self::check(new_layout)?;
+        // SAFETY: Checked by caller.
+        unsafe { self.0.grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Self::check(new_layout)?;
+        // SAFETY: Checked by caller.
+        unsafe { self.0.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        Self::check(new_layout)?;
+        // SAFETY: Checked by caller.
+        unsafe { self.0.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+impl<A: Overhead, const MAX: usize> Overhead for MaxAlign<A, MAX> {
+    fn overhead(&self, layout: Layout) -> usize {
+        self.0.overhead(layout)
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    let alloc = MaxAlign::<_, 64>(Global);
+    let high = Layout::from_size_align(128, 128).unwrap();
+    assert_eq!(alloc.allocate(high), Err(AllocError));
+    assert_eq!(alloc.allocate_zeroed(high), Err(AllocError));
+
+    let layout = Layout::from_size_align(64, 64).unwrap();
+    let ptr = alloc.allocate(layout).unwrap();
+    assert_eq!(ptr.as_ptr() as *mut u8 as usize % 64, 0);
+    unsafe {
+        assert!(alloc.grow(ptr.cast(), layout, high).is_err());
+        let small = Layout::from_size_align(32, 128).unwrap();
+        assert!(alloc.shrink(ptr.cast(), layout, small).is_err());
+        alloc.deallocate(ptr.cast(), layout);
+    }
+}
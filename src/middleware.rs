@@ -0,0 +1,321 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Hooks that run around the operations of an allocator, as applied by
+/// [`Instrumented`].
+///
+/// This allows cross-cutting behavior like logging or counting to be written
+/// once and applied to any allocator, without implementing [`Allocator`].
+/// Every hook does nothing by default.
+///
+/// Zeroed allocations run the allocation hooks, and resizing a block runs
+/// the resizing hooks, even if the allocator implements the operation by
+/// allocating a new block.
+pub trait Middleware {
+    /// Called before a block is allocated.
+    fn before_allocate(&self, layout: Layout) {
+        let _ = layout;
+    }
+
+    /// Called after a block is allocated (or the allocation fails).
+    fn after_allocate(
+        &self,
+        layout: Layout,
+        result: &Result<NonNull<[u8]>, AllocError>,
+    ) {
+        let _ = (layout, result);
+    }
+
+    /// Called before a block is deallocated.
+    fn before_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+
+    /// Called after a block is deallocated. `ptr` is dangling at this
+    /// point.
+    fn after_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let _ = (ptr, layout);
+    }
+
+    /// Called before a block is grown or shrunk.
+    fn before_resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) {
+        let _ = (ptr, old_layout, new_layout);
+    }
+
+    /// Called after a block is grown or shrunk (or resizing fails).
+    fn after_resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        result: &Result<NonNull<[u8]>, AllocError>,
+    ) {
+        let _ = (ptr, old_layout, new_layout, result);
+    }
+}
+
+impl<M: Middleware + ?Sized> Middleware for &M {
+    fn before_allocate(&self, layout: Layout) {
+        M::before_allocate(*self, layout);
+    }
+
+    fn after_allocate(
+        &self,
+        layout: Layout,
+        result: &Result<NonNull<[u8]>, AllocError>,
+    ) {
+        M::after_allocate(*self, layout, result);
+    }
+
+    fn before_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        M::before_deallocate(*self, ptr, layout);
+    }
+
+    fn after_deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        M::after_deallocate(*self, ptr, layout);
+    }
+
+    fn before_resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) {
+        M::before_resize(*self, ptr, old_layout, new_layout);
+    }
+
+    fn after_resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        result: &Result<NonNull<[u8]>, AllocError>,
+    ) {
+        M::after_resize(*self, ptr, old_layout, new_layout, result);
+    }
+}
+
+/// Does nothing.
+impl Middleware for () {}
+
+/// An allocator that forwards to `A`, running the hooks of the
+/// [`Middleware`] `M` around each operation.
+///
+/// Several middlewares can be composed by nesting: in
+/// `Instrumented<Instrumented<A, M1>, M2>`, the hooks of `M2` run outside
+/// those of `M1`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Instrumented<A, M> {
+    inner: A,
+    middleware: M,
+}
+
+impl<A, M> Instrumented<A, M> {
+    /// Creates a new [`Instrumented`] that runs `middleware` around
+    /// `inner`.
+    pub const fn new(inner: A, middleware: M) -> Self {
+        Self {
+            inner,
+            middleware,
+        }
+    }
+
+    /// Returns the underlying allocator.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the middleware.
+    pub fn middleware(&self) -> &M {
+        &self.middleware
+    }
+
+    /// Returns the underlying allocator and the middleware.
+    pub fn into_parts(self) -> (A, M) {
+        (self.inner, self.middleware)
+    }
+
+    fn allocate_with<F>(
+        &self,
+        layout: Layout,
+        f: F,
+    ) -> Result<NonNull<[u8]>, AllocError>
+    where
+        M: Middleware,
+        F: FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    {
+        self.middleware.before_allocate(layout);
+        let result = f();
+        self.middleware.after_allocate(layout, &result);
+        result
+    }
+
+    fn resize_with<F>(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        f: F,
+    ) -> Result<NonNull<[u8]>, AllocError>
+    where
+        M: Middleware,
+        F: FnOnce() -> Result<NonNull<[u8]>, AllocError>,
+    {
+        self.middleware.before_resize(ptr, old_layout, new_layout);
+        let result = f();
+        self.middleware.after_resize(ptr, old_layout, new_layout, &result);
+        result
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator, M: Middleware> Allocator for Instrumented<A, M> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_with(layout, || self.inner.allocate(layout))
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_with(layout, || self.inner.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.middleware.before_deallocate(ptr, layout);
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+        self.middleware.after_deallocate(ptr, layout);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(ptr, old_layout, new_layout, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.grow(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(ptr, old_layout, new_layout, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(ptr, old_layout, new_layout, || {
+            // SAFETY: Checked by caller.
+            unsafe { self.inner.shrink(ptr, old_layout, new_layout) }
+        })
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    use alloc::vec::Vec;
+    use core::cell::{Cell, RefCell};
+
+    #[derive(Default)]
+    struct Log(RefCell<Vec<(&'static str, usize)>>);
+
+    impl Middleware for Log {
+        fn before_allocate(&self, layout: Layout) {
+            self.0.borrow_mut().push(("allocate", layout.size()));
+        }
+
+        fn before_deallocate(&self, _ptr: NonNull<u8>, layout: Layout) {
+            self.0.borrow_mut().push(("deallocate", layout.size()));
+        }
+
+        fn after_resize(
+            &self,
+            _ptr: NonNull<u8>,
+            _old_layout: Layout,
+            new_layout: Layout,
+            result: &Result<NonNull<[u8]>, AllocError>,
+        ) {
+            if result.is_ok() {
+                self.0.borrow_mut().push(("resize", new_layout.size()));
+            }
+        }
+    }
+
+    #[derive(Default)]
+    struct Count(Cell<usize>);
+
+    impl Middleware for Count {
+        fn after_allocate(
+            &self,
+            _layout: Layout,
+            result: &Result<NonNull<[u8]>, AllocError>,
+        ) {
+            if result.is_ok() {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+    }
+
+    let log = Log::default();
+    let alloc =
+        Instrumented::new(Instrumented::new(Global, &log), Count::default());
+    let small = Layout::new::<[u8; 8]>();
+    let large = Layout::new::<[u8; 32]>();
+    let a = alloc.allocate(small).unwrap();
+    let b = alloc.allocate_zeroed(large).unwrap();
+    unsafe {
+        let a = alloc.grow(a.cast(), small, large).unwrap();
+        alloc.deallocate(a.cast(), large);
+        alloc.deallocate(b.cast(), large);
+    }
+    assert_eq!(alloc.middleware().0.get(), 2);
+    assert_eq!(
+        *log.0.borrow(),
+        [
+            ("allocate", 8),
+            ("allocate", 32),
+            ("resize", 32),
+            ("deallocate", 32),
+            ("deallocate", 32),
+        ],
+    );
+}
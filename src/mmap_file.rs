@@ -0,0 +1,316 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use crate::align_check::debug_aligned;
+use crate::page::page_size;
+use alloc::alloc::Layout;
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::convert::TryFrom;
+use core::ptr::{self, NonNull};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+/// The smallest amount by which the file is extended.
+const MIN_CHUNK: usize = 64 * 1024;
+
+/// A shared mapping of part of the file.
+struct Mapping {
+    ptr: NonNull<u8>,
+    len: usize,
+    /// The offset of the mapping within the file.
+    offset: u64,
+}
+
+struct State {
+    /// All mappings, in order of file offset. The last one is current.
+    mappings: Vec<Mapping>,
+    /// The offset of the first unallocated byte in the current mapping.
+    used: usize,
+    /// The offset in the file at which the next mapping starts.
+    end: u64,
+}
+
+/// A bump allocator that serves allocations from a memory-mapped file, so
+/// they are backed by (and persisted to) the file rather than anonymous
+/// memory.
+///
+/// This suits large datasets that may not fit in RAM, since the operating
+/// system can write pages back to the file instead of swap, and data that
+/// should outlive the process. Allocations start after the file’s existing
+/// contents, which are left untouched. When the current mapping is full,
+/// the file is extended (with [`File::set_len`], i.e., `ftruncate`) and the
+/// new part is mapped separately, so existing blocks never move.
+///
+/// Like [`Bump`](crate::Bump), deallocation is a no-op; mappings are
+/// unmapped when the allocator is dropped, but their contents stay in the
+/// file. Use [`Self::flush`] to write them to disk, and
+/// [`Self::file_offset`] to find where a block is stored. The file grows
+/// with every allocation, and is never shrunk.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(all(unix, feature = "mmap"))))]
+pub struct MmapFileAllocator {
+    file: File,
+    state: RefCell<State>,
+}
+
+impl MmapFileAllocator {
+    /// Creates a new allocator that allocates from `file`, which must be
+    /// open for reading and writing.
+    ///
+    /// Nothing is mapped until the first allocation.
+    pub fn new(file: File) -> io::Result<Self> {
+        let page = page_size() as u64;
+        let len = file.metadata()?.len();
+        let end = len
+            .checked_add(page - 1)
+            .map(|end| end & !(page - 1))
+            .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+        Ok(Self {
+            file,
+            state: RefCell::new(State {
+                mappings: Vec::new(),
+                used: 0,
+                end,
+            }),
+        })
+    }
+
+    /// Returns the file this allocator allocates from.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns the offset in the file at which the byte at `ptr` is stored,
+    /// or [`None`] if `ptr` isn’t in memory mapped by this allocator.
+    pub fn file_offset(&self, ptr: NonNull<u8>) -> Option<u64> {
+        let addr = ptr.as_ptr() as usize;
+        self.state.borrow().mappings.iter().find_map(|mapping| {
+            let start = mapping.ptr.as_ptr() as usize;
+            let offset =
+                addr.checked_sub(start).filter(|&o| o < mapping.len)?;
+            Some(mapping.offset + offset as u64)
+        })
+    }
+
+    /// Writes all mapped memory back to the file, waiting for the writes to
+    /// complete.
+    pub fn flush(&self) -> io::Result<()> {
+        for mapping in &self.state.borrow().mappings {
+            // SAFETY: `mapping` is a live mapping of `mapping.len` bytes.
+            let result = unsafe {
+                libc::msync(
+                    mapping.ptr.as_ptr() as *mut libc::c_void,
+                    mapping.len,
+                    libc::MS_SYNC,
+                )
+            };
+            if result != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Extends the file and maps a new part of it large enough for
+    /// `layout`, making it the current mapping.
+    fn extend(&self, state: &mut State, layout: Layout) -> Option<()> {
+        let page = page_size();
+        let last = state.mappings.last().map_or(0, |mapping| mapping.len);
+        let len = layout
+            .size()
+            .checked_add(layout.align() - 1)?
+            .max(last.checked_mul(2)?)
+            .max(MIN_CHUNK)
+            .checked_add(page - 1)?
+            & !(page - 1);
+        let offset = state.end;
+        let end = offset.checked_add(len as u64)?;
+        // Both offsets must be representable as an `off_t`.
+        libc::off_t::try_from(end).ok()?;
+        self.file.set_len(end).ok()?;
+        // SAFETY: The new mapping doesn’t overlap any existing memory, and
+        // the file is at least `end` bytes long.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                self.file.as_raw_fd(),
+                offset as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return None;
+        }
+        state.mappings.push(Mapping {
+            // SAFETY: `mmap` doesn’t return null on success.
+            ptr: unsafe { NonNull::new_unchecked(ptr as *mut u8) },
+            len,
+            offset,
+        });
+        state.used = 0;
+        state.end = end;
+        Some(())
+    }
+
+    /// Returns a block for `layout` in the current mapping, if it fits.
+    fn bump(state: &mut State, layout: Layout) -> Option<NonNull<[u8]>> {
+        let mapping = state.mappings.last()?;
+        let addr = mapping.ptr.as_ptr() as usize + state.used;
+        let padding = addr.wrapping_neg() & (layout.align() - 1);
+        let start = state.used.checked_add(padding)?;
+        let end = start.checked_add(layout.size())?;
+        if end > mapping.len {
+            return None;
+        }
+        state.used = end;
+        // SAFETY: `start + layout.size()` is within the mapping.
+        Some(unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                mapping.ptr.as_ptr().add(start),
+                layout.size(),
+            ))
+        })
+    }
+}
+
+impl Drop for MmapFileAllocator {
+    fn drop(&mut self) {
+        for mapping in &self.state.get_mut().mappings {
+            // SAFETY: `mapping` is a live mapping of `mapping.len` bytes, and
+            // no blocks in it can be used after the allocator is dropped.
+            unsafe {
+                libc::munmap(
+                    mapping.ptr.as_ptr() as *mut libc::c_void,
+                    mapping.len,
+                )
+            };
+        }
+    }
+}
+
+impl core::fmt::Debug for MmapFileAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let state = self.state.borrow();
+        f.debug_struct("MmapFileAllocator")
+            .field("file", &self.file)
+            .field("mappings", &state.mappings.len())
+            .field("end", &state.end)
+            .finish()
+    }
+}
+
+// SAFETY: The allocator owns its mappings, so it can be sent to another
+// thread.
+unsafe impl Send for MmapFileAllocator {}
+
+// SAFETY: Blocks are allocated from mappings that stay mapped until the
+// allocator is dropped, and `Self::bump` never returns overlapping blocks.
+// Mappings are never moved or unmapped while the allocator is alive.
+unsafe impl Allocator for &MmapFileAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let mut state = self.state.borrow_mut();
+        if let Some(block) = MmapFileAllocator::bump(&mut state, layout) {
+            return Ok(debug_aligned(block, layout));
+        }
+        self.extend(&mut state, layout).ok_or(AllocError)?;
+        let block = MmapFileAllocator::bump(&mut state, layout);
+        block.map(|block| debug_aligned(block, layout)).ok_or(AllocError)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Memory is never reused, and the file is only ever extended with
+        // zeros, so every block is already zeroed.
+        self.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+    use std::path::PathBuf;
+
+    /// Creates an empty file in the temporary directory.
+    fn temp_file(name: &str) -> (PathBuf, File) {
+        let path = std::env::temp_dir().join(format!(
+            "allocator-fallback-{}-{}",
+            name,
+            std::process::id(),
+        ));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        (path, file)
+    }
+
+    #[test]
+    fn persist() {
+        let (path, file) = temp_file("persist");
+        let alloc = &MmapFileAllocator::new(file).unwrap();
+        let layout = Layout::new::<[u8; 13]>();
+        let ptr = alloc.allocate(layout).unwrap().cast::<[u8; 13]>();
+        unsafe { ptr.as_ptr().write(*b"hello, world!") };
+        alloc.flush().unwrap();
+        let offset = alloc.file_offset(ptr.cast()).unwrap() as usize;
+
+        let contents = fs::read(&path).unwrap();
+        assert_eq!(&contents[offset..offset + 13], b"hello, world!");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn extend() {
+        let (path, file) = temp_file("extend");
+        let alloc = &MmapFileAllocator::new(file).unwrap();
+        let layout = Layout::from_size_align(40 * 1024, 64).unwrap();
+        let blocks: Vec<_> = (0..8_u8)
+            .map(|i| {
+                let block = alloc.allocate_zeroed(layout).unwrap();
+                let bytes = unsafe { &mut *block.as_ptr() };
+                assert!(bytes.iter().all(|&b| b == 0));
+                bytes.iter_mut().for_each(|b| *b = i);
+                block
+            })
+            .collect();
+        assert!(alloc.state.borrow().mappings.len() > 1);
+        alloc.flush().unwrap();
+
+        let contents = fs::read(&path).unwrap();
+        for (i, block) in blocks.iter().enumerate() {
+            let bytes = unsafe { &*(block.as_ptr() as *const [u8]) };
+            assert!(bytes.iter().all(|&b| b == i as u8));
+            let offset = alloc.file_offset(block.cast()).unwrap() as usize;
+            let stored = &contents[offset..offset + layout.size()];
+            assert!(stored.iter().all(|&b| b == i as u8));
+        }
+        fs::remove_file(&path).unwrap();
+    }
+}
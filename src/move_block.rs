@@ -0,0 +1,57 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Moves a block to a new allocation for `new_layout` from `alloc`, copying
+/// as many bytes as fit and then deallocating the old block.
+///
+/// Wrappers use this to grow or shrink blocks that can’t be resized by the
+/// allocator they wrap, like when the alignment of the underlying request
+/// changes. Both the allocation and the deallocation go through `alloc`
+/// itself, so any bookkeeping it does (like checking red zones) still
+/// happens. If `zeroed` is true, the new block is allocated with
+/// [`Allocator::allocate_zeroed`].
+///
+/// # Safety
+///
+/// `ptr` must denote a block currently allocated via `alloc`, and
+/// `old_layout` must fit that block.
+pub(crate) unsafe fn move_block<A: Allocator + ?Sized>(
+    alloc: &A,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let new = if zeroed {
+        alloc.allocate_zeroed(new_layout)?
+    } else {
+        alloc.allocate(new_layout)?
+    };
+    let size = old_layout.size().min(new_layout.size());
+    // SAFETY: Checked by caller. `new` is a distinct allocation at least
+    // `size` bytes long.
+    unsafe {
+        (new.as_ptr() as *mut u8).copy_from_nonoverlapping(ptr.as_ptr(), size);
+        alloc.deallocate(ptr, old_layout);
+    }
+    Ok(new)
+}
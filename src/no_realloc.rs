@@ -0,0 +1,138 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that forwards to `A`, but always resizes blocks by
+/// allocating a new block, copying, and deallocating the old one.
+///
+/// This bypasses any in-place or `realloc`-based resizing `A` implements,
+/// which helps determine whether a bug is in `A`’s resizing logic. Because
+/// the new block is allocated while the old one is still live, resizing
+/// always returns a different pointer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NoRealloc<A>(pub A);
+
+// SAFETY: This impl forwards to `A`, except that resizing is implemented in
+// terms of `A::allocate` and `A::deallocate`.
+unsafe impl<A: Allocator> Allocator for NoRealloc<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = self.0.allocate(new_layout)?;
+        // SAFETY: Checked by caller (new size must not be less than old
+        // size).
+        unsafe {
+            (new.as_ptr() as *mut u8)
+                .copy_from_nonoverlapping(ptr.as_ptr(), old_layout.size());
+            self.0.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = self.0.allocate_zeroed(new_layout)?;
+        // SAFETY: Checked by caller (new size must not be less than old
+        // size).
+        unsafe {
+            (new.as_ptr() as *mut u8)
+                .copy_from_nonoverlapping(ptr.as_ptr(), old_layout.size());
+            self.0.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new = self.0.allocate(new_layout)?;
+        // SAFETY: Checked by caller (new size must not be greater than old
+        // size).
+        unsafe {
+            (new.as_ptr() as *mut u8)
+                .copy_from_nonoverlapping(ptr.as_ptr(), new_layout.size());
+            self.0.deallocate(ptr, old_layout);
+        }
+        Ok(new)
+    }
+}
+
+impl<A: Overhead> Overhead for NoRealloc<A> {
+    fn overhead(&self, layout: Layout) -> usize {
+        self.0.overhead(layout)
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    let alloc = NoRealloc(Global);
+    let mut layout = Layout::new::<u8>();
+    let mut ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+    unsafe { ptr.as_ptr().write(7) };
+    for size in 2..64 {
+        let new_layout = Layout::from_size_align(size, 1).unwrap();
+        let grown = unsafe { alloc.grow_zeroed(ptr, layout, new_layout) };
+        let grown = grown.unwrap().cast::<u8>();
+        assert_ne!(grown, ptr);
+        unsafe {
+            assert_eq!(*grown.as_ptr(), 7);
+            assert_eq!(*grown.as_ptr().add(size - 1), 0);
+        }
+        ptr = grown;
+        layout = new_layout;
+    }
+    let small = Layout::new::<u8>();
+    let shrunk = unsafe { alloc.shrink(ptr, layout, small) }.unwrap();
+    assert_ne!(shrunk.cast(), ptr);
+    let grown = unsafe { alloc.grow(shrunk.cast(), small, layout) }.unwrap();
+    assert_ne!(grown.cast(), shrunk.cast::<u8>());
+    unsafe {
+        assert_eq!(*(grown.as_ptr() as *mut u8), 7);
+        alloc.deallocate(grown.cast(), layout);
+    }
+}
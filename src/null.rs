@@ -0,0 +1,82 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, AllocatorInfo};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that fails every request.
+///
+/// This is useful as the allocator of a data structure that must never
+/// allocate, such as a collection that only ever holds borrowed memory, or
+/// in tests of allocation-failure handling. [`AllocatorInfo::NEVER_ALLOCATES`]
+/// is `true`, so generic code can check this at compile time.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub struct NullAllocator;
+
+// SAFETY: This allocator never returns any blocks.
+unsafe impl Allocator for NullAllocator {
+    fn allocate(&self, _layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Err(AllocError)
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        // No block can have been allocated via this allocator, so this is
+        // never called.
+    }
+}
+
+impl AllocatorInfo for NullAllocator {
+    const NEVER_ALLOCATES: bool = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+
+    /// Can be created only with an allocator that never allocates.
+    struct NoAlloc<A>(A);
+
+    impl<A: AllocatorInfo> NoAlloc<A> {
+        // `assert!` in constants needs Rust 1.57, so index out of bounds
+        // instead when `A` allocates.
+        const CHECK: () = [()][!A::NEVER_ALLOCATES as usize];
+
+        fn new(alloc: A) -> Self {
+            let () = Self::CHECK;
+            Self(alloc)
+        }
+    }
+
+    const _: () =
+        [()][!<&NullAllocator as AllocatorInfo>::NEVER_ALLOCATES as usize];
+    const _: () = [()][Global::NEVER_ALLOCATES as usize];
+
+    #[test]
+    fn null() {
+        let alloc = NoAlloc::new(NullAllocator).0;
+        assert_eq!(alloc.allocate(Layout::new::<u8>()), Err(AllocError));
+        assert_eq!(
+            alloc.allocate_zeroed(Layout::new::<()>()),
+            Err(AllocError)
+        );
+    }
+}
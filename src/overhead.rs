@@ -0,0 +1,79 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{Allocator, Global};
+use alloc::alloc::Layout;
+
+/// An [`Allocator`] that can report how many extra bytes a request costs.
+///
+/// Wrappers that add headers, red zones, or rounding report their own cost
+/// plus that of the allocator they wrap, so calling [`Self::overhead`] on
+/// the outermost wrapper gives the total for the whole stack. This helps
+/// budget memory when composing several wrappers.
+pub trait Overhead: Allocator {
+    /// Returns the number of bytes beyond `layout.size()` that allocating a
+    /// block for `layout` uses, not counting padding inserted by the
+    /// underlying memory source.
+    ///
+    /// The default implementation returns 0. If the request would fail, the
+    /// result is unspecified.
+    fn overhead(&self, layout: Layout) -> usize {
+        let _ = layout;
+        0
+    }
+}
+
+impl<A> Overhead for &A
+where
+    A: Overhead + ?Sized,
+{
+    fn overhead(&self, layout: Layout) -> usize {
+        A::overhead(*self, layout)
+    }
+}
+
+impl Overhead for Global {}
+
+#[cfg(feature = "std")]
+impl Overhead for std::alloc::System {}
+
+#[test]
+fn test() {
+    use crate::{RedZone, SizeClassed, TrackingAllocator};
+
+    let layout = Layout::new::<[u8; 10]>();
+    let inner = RedZone::<_, 8>(Global);
+    let classed = SizeClassed::new(RedZone::<_, 8>(Global));
+    let alloc = RedZone::<_, 16>(SizeClassed::new(RedZone::<_, 8>(Global)));
+
+    // 16 bytes on each side, rounded from 42 to 64 bytes, then 8 bytes on
+    // each side of that.
+    let red_zone = 32;
+    let rounding = 64 - (10 + red_zone);
+    let inner_red_zone = inner.overhead(Layout::new::<[u8; 64]>());
+    assert_eq!(inner_red_zone, 16);
+    assert_eq!(classed.overhead(Layout::new::<[u8; 42]>()), rounding + 16);
+    assert_eq!(alloc.overhead(layout), red_zone + rounding + inner_red_zone);
+
+    // The reported overhead is what the backend actually sees.
+    let backing = TrackingAllocator::new(Global);
+    let alloc = RedZone::<_, 16>(SizeClassed::new(RedZone::<_, 8>(&backing)));
+    let ptr = alloc.allocate(layout).unwrap();
+    assert_eq!(backing.stats().current, 10 + alloc.overhead(layout));
+    unsafe { alloc.deallocate(ptr.cast(), layout) };
+}
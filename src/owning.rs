@@ -0,0 +1,57 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::Allocator;
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An [`Allocator`] that can determine whether it allocated a given block of
+/// memory.
+///
+/// # Safety
+///
+/// [`Self::owns`] must return true for every block of memory currently
+/// allocated via this allocator, and false for every block of memory
+/// allocated by a different allocator.
+pub unsafe trait OwningAllocator: Allocator {
+    /// Returns whether the block of memory denoted by `ptr` and `layout` was
+    /// allocated via this allocator.
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool;
+}
+
+// SAFETY: This impl simply forwards to `A`.
+unsafe impl<A> OwningAllocator for &A
+where
+    A: OwningAllocator + ?Sized,
+{
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        A::owns(*self, ptr, layout)
+    }
+}
+
+/// Returns whether a block lies within the buffer starting at `start` with
+/// length `len`.
+pub(crate) fn in_buffer(
+    start: NonNull<u8>,
+    len: usize,
+    ptr: NonNull<u8>,
+    layout: Layout,
+) -> bool {
+    let offset = (ptr.as_ptr() as usize).wrapping_sub(start.as_ptr() as usize);
+    offset < len || (offset == len && layout.size() == 0)
+}
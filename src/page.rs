@@ -0,0 +1,36 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Returns the system page size, querying it only once.
+pub(crate) fn page_size() -> usize {
+    static PAGE_SIZE: AtomicUsize = AtomicUsize::new(0);
+    let mut size = PAGE_SIZE.load(Ordering::Relaxed);
+    if size == 0 {
+        // SAFETY: `sysconf` has no preconditions.
+        let result = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        size = if result <= 0 {
+            4096
+        } else {
+            result as usize
+        };
+        PAGE_SIZE.store(size, Ordering::Relaxed);
+    }
+    size
+}
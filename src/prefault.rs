@@ -0,0 +1,125 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use crate::page::page_size;
+use alloc::alloc::Layout;
+use core::mem::MaybeUninit;
+use core::ptr::NonNull;
+
+/// Writes to one byte in every page of `block` without changing its
+/// contents.
+fn prefault(block: NonNull<[u8]>) -> NonNull<[u8]> {
+    let page = page_size();
+    let start = block.as_ptr() as *mut MaybeUninit<u8>;
+    // SAFETY: Allocators always return pointers to valid memory.
+    let len = unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len();
+    let mut offset = 0;
+    while offset < len {
+        // SAFETY: `offset` is within the block, which is uniquely owned by
+        // the caller. Copying `MaybeUninit<u8>` is valid even if the byte is
+        // uninitialized.
+        unsafe {
+            let ptr = start.add(offset);
+            ptr.write_volatile(ptr.read_volatile());
+        }
+        // Advance to the start of the next page.
+        let addr = start as usize + offset;
+        offset += page - addr % page;
+    }
+    block
+}
+
+/// An allocator that forwards to `A`, but writes to every page of each new
+/// block before returning it.
+///
+/// Operating systems usually commit physical memory lazily, the first time
+/// each page is written to, which can cause unpredictable latency long after
+/// allocation. This wrapper forces the memory to be committed up front, at
+/// the cost of slower allocation. Existing contents are preserved.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(all(unix, feature = "prefault"))))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PreFaulting<A>(pub A);
+
+// SAFETY: This impl forwards to `A`, and doesn't change the contents of any
+// returned blocks.
+unsafe impl<A: Allocator> Allocator for PreFaulting<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate(layout).map(prefault)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.0.allocate_zeroed(layout).map(prefault)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.grow(ptr, old_layout, new_layout) }.map(prefault)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.grow_zeroed(ptr, old_layout, new_layout) }
+            .map(prefault)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.0.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[test]
+fn test() {
+    use crate::Global;
+    let page = page_size();
+    let layout = Layout::from_size_align(page * 16 + 3, 1).unwrap();
+    let ptr = PreFaulting(Global).allocate_zeroed(layout).unwrap();
+    unsafe {
+        let bytes = &mut *ptr.as_ptr();
+        assert!(bytes.iter().all(|&b| b == 0));
+        for (i, b) in bytes.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert!(bytes.iter().enumerate().all(|(i, &b)| b == i as u8));
+        Global.deallocate(ptr.cast(), layout);
+    }
+}
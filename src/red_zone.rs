@@ -0,0 +1,278 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use crate::move_block::move_block;
+use alloc::alloc::Layout;
+use core::ptr::{self, NonNull};
+use core::slice;
+
+/// The byte red zones are filled with.
+const FILL: u8 = 0xcc;
+
+/// An allocator that surrounds every block allocated via `A` with `N` bytes
+/// of `0xCC` on each side, and panics when a block is deallocated if any of
+/// them have changed.
+///
+/// This catches buffer overruns and underruns that stay within `N` bytes of
+/// the block. The leading red zone is rounded up to a multiple of the
+/// block’s alignment, so that the block itself stays aligned. `RedZone<A,
+/// 0>` forwards directly to `A`, so red zones can be disabled at compile
+/// time without any overhead.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RedZone<A, const N: usize>(pub A);
+
+impl<A: Allocator, const N: usize> RedZone<A, N> {
+    /// Returns the layout of the block allocated from `A` for `layout`, and
+    /// the offset of the user block within it.
+    fn outer(layout: Layout) -> Result<(Layout, usize), AllocError> {
+        let align = layout.align();
+        let lead = N.checked_add(align - 1).ok_or(AllocError)? & !(align - 1);
+        let size = lead
+            .checked_add(layout.size())
+            .and_then(|size| size.checked_add(N))
+            .ok_or(AllocError)?;
+        let outer =
+            Layout::from_size_align(size, align).map_err(|_| AllocError)?;
+        Ok((outer, lead))
+    }
+
+    fn allocate_impl(
+        &self,
+        layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (outer, lead) = Self::outer(layout)?;
+        let base = self.0.allocate(outer)?.as_ptr() as *mut u8;
+        // SAFETY: `base` is valid for `outer.size()` bytes, which is
+        // `lead + layout.size() + N`.
+        unsafe {
+            base.write_bytes(FILL, lead);
+            let user = base.add(lead);
+            if zeroed {
+                user.write_bytes(0, layout.size());
+            }
+            user.add(layout.size()).write_bytes(FILL, N);
+            Ok(NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                user,
+                layout.size(),
+            )))
+        }
+    }
+
+    /// Checks the red zones around `ptr`, returning the block allocated
+    /// from `A`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block currently allocated via this allocator,
+    /// with `layout`.
+    unsafe fn check(
+        ptr: NonNull<u8>,
+        layout: Layout,
+    ) -> (NonNull<u8>, Layout) {
+        let (outer, lead) = match Self::outer(layout) {
+            Ok(outer) => outer,
+            // SAFETY: Checked by caller (the block was allocated with
+            // `layout`, so this succeeded then).
+            Err(_) => unsafe { core::hint::unreachable_unchecked() },
+        };
+        // SAFETY: Checked by caller. The block is preceded by `lead` bytes
+        // and followed by `N` bytes of red zone.
+        let (base, before, after) = unsafe {
+            let base = ptr.as_ptr().sub(lead);
+            let after = ptr.as_ptr().add(layout.size());
+            (
+                base,
+                slice::from_raw_parts(base, lead),
+                slice::from_raw_parts(after, N),
+            )
+        };
+        if let Some(i) = before.iter().rposition(|&b| b != FILL) {
+            panic!(
+                "red zone before block at {:p} corrupted {} bytes before it",
+                ptr,
+                lead - i,
+            );
+        }
+        if let Some(i) = after.iter().position(|&b| b != FILL) {
+            panic!(
+                "red zone after block at {:p} corrupted {} bytes past its end",
+                ptr, i,
+            );
+        }
+        // SAFETY: `base` is derived from a `NonNull`.
+        (unsafe { NonNull::new_unchecked(base) }, outer)
+    }
+}
+
+// SAFETY: Each block is carved out of a distinct block allocated via `A`,
+// which stays allocated until the block is deallocated. If `N` is 0, this
+// simply forwards to `A`.
+unsafe impl<A: Allocator, const N: usize> Allocator for RedZone<A, N> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if N == 0 {
+            return self.0.allocate(layout);
+        }
+        self.allocate_impl(layout, false)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if N == 0 {
+            return self.0.allocate_zeroed(layout);
+        }
+        self.allocate_impl(layout, true)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if N == 0 {
+            // SAFETY: Checked by caller.
+            return unsafe { self.0.deallocate(ptr, layout) };
+        }
+        // SAFETY: Checked by caller.
+        unsafe {
+            let (base, outer) = Self::check(ptr, layout);
+            self.0.deallocate(base, outer);
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            if N == 0 {
+                return self.0.grow(ptr, old_layout, new_layout);
+            }
+            move_block(self, ptr, old_layout, new_layout, false)
+        }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            if N == 0 {
+                return self.0.grow_zeroed(ptr, old_layout, new_layout);
+            }
+            move_block(self, ptr, old_layout, new_layout, true)
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe {
+            if N == 0 {
+                return self.0.shrink(ptr, old_layout, new_layout);
+            }
+            move_block(self, ptr, old_layout, new_layout, false)
+        }
+    }
+}
+
+/// Adds the red zones, including any padding of the leading one, to `A`’s
+/// overhead for the enlarged block.
+impl<A: Overhead, const N: usize> Overhead for RedZone<A, N> {
+    fn overhead(&self, layout: Layout) -> usize {
+        if N == 0 {
+            return self.0.overhead(layout);
+        }
+        Self::outer(layout).map_or(0, |(outer, _)| {
+            outer.size() - layout.size() + self.0.overhead(outer)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Global, TrackingAllocator};
+
+    #[test]
+    fn pass_through() {
+        let backing = TrackingAllocator::new(Global);
+        let alloc = RedZone::<_, 0>(&backing);
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = alloc.allocate(layout).unwrap();
+        assert_eq!(backing.stats().current, 24);
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+        assert_eq!(core::mem::size_of::<RedZone<Global, 0>>(), 0);
+    }
+
+    #[test]
+    fn padded() {
+        let backing = TrackingAllocator::new(Global);
+        let alloc = RedZone::<_, 16>(&backing);
+        let layout = Layout::from_size_align(10, 32).unwrap();
+        let ptr = alloc.allocate_zeroed(layout).unwrap();
+        let user = ptr.as_ptr() as *mut u8;
+        assert_eq!(user as usize % 32, 0);
+        assert_eq!(unsafe { &*(ptr.as_ptr() as *const [u8]) }.len(), 10);
+        // The leading zone is rounded up to the alignment.
+        assert_eq!(backing.stats().current, 32 + 10 + 16);
+        unsafe {
+            assert!((*ptr.as_ptr()).iter().all(|&b| b == 0));
+            assert_eq!(*user.sub(1), FILL);
+            assert_eq!(*user.add(10 + 15), FILL);
+            user.write_bytes(1, 10);
+            let big = Layout::from_size_align(40, 32).unwrap();
+            let grown = alloc.grow(ptr.cast(), layout, big).unwrap();
+            assert!((&(*grown.as_ptr()))[..10].iter().all(|&b| b == 1));
+            alloc.deallocate(grown.cast(), big);
+        }
+        assert_eq!(backing.stats().current, 0);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn corruption() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let alloc = RedZone::<_, 16>(Global);
+        let layout = Layout::new::<[u8; 8]>();
+        for &(offset, message) in &[
+            (8, "0 bytes past its end"),
+            (20, "12 bytes past its end"),
+            (-3, "3 bytes before it"),
+        ] {
+            let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+            let err = panic::catch_unwind(AssertUnwindSafe(|| unsafe {
+                *ptr.as_ptr().offset(offset) = 0;
+                alloc.deallocate(ptr, layout);
+            }))
+            .unwrap_err();
+            let err = err.downcast::<std::string::String>().unwrap();
+            assert!(err.ends_with(message), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,189 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::ptr::{self, NonNull};
+
+/// A node in a thread’s stack of active [`NonReentrant`] calls.
+struct Active {
+    addr: usize,
+    prev: *const Active,
+}
+
+std::thread_local! {
+    static ACTIVE: Cell<*const Active> = Cell::new(ptr::null());
+}
+
+/// Marks a [`NonReentrant`] as active on the current thread until dropped.
+struct Guard<'a> {
+    node: &'a Active,
+}
+
+impl<'a> Guard<'a> {
+    fn new(node: &'a mut Active) -> Self {
+        ACTIVE.with(|active| {
+            let mut next = active.get();
+            while !next.is_null() {
+                // SAFETY: Nodes are removed from the stack before they're
+                // dropped.
+                let current = unsafe { &*next };
+                if current.addr == node.addr {
+                    panic!("allocator was reentered on the same thread");
+                }
+                next = current.prev;
+            }
+            node.prev = active.replace(node);
+        });
+        Self {
+            node,
+        }
+    }
+}
+
+impl Drop for Guard<'_> {
+    fn drop(&mut self) {
+        ACTIVE.with(|active| active.set(self.node.prev));
+    }
+}
+
+/// An allocator that forwards to `A`, but panics if it is called again on
+/// the same thread while a call is already in progress.
+///
+/// This can happen if an allocator’s bookkeeping allocates through the
+/// allocator itself, which usually causes a deadlock or infinite recursion.
+/// This wrapper turns that into a panic at the point of reentry.
+///
+/// Wrappers are distinguished by address, so nested calls through
+/// *different* [`NonReentrant`] wrappers are allowed. Note that distinct
+/// zero-sized wrappers (e.g., `NonReentrant<Global>`) may share an address.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NonReentrant<A>(pub A);
+
+impl<A> NonReentrant<A> {
+    fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        let mut node = Active {
+            addr: self as *const Self as usize,
+            prev: ptr::null(),
+        };
+        let _guard = Guard::new(&mut node);
+        f()
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for NonReentrant<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.enter(|| self.0.allocate(layout))
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.enter(|| self.0.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        self.enter(|| unsafe { self.0.deallocate(ptr, layout) });
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        self.enter(|| unsafe { self.0.grow(ptr, old_layout, new_layout) })
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.enter(|| {
+            // SAFETY: Checked by caller.
+            unsafe { self.0.grow_zeroed(ptr, old_layout, new_layout) }
+        })
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        self.enter(|| unsafe { self.0.shrink(ptr, old_layout, new_layout) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+
+    /// Allocates through `outer` from within `allocate`.
+    struct Reentrant<'a> {
+        outer: Cell<Option<&'a NonReentrant<Reentrant<'a>>>>,
+    }
+
+    unsafe impl Allocator for Reentrant<'_> {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            match self.outer.get() {
+                Some(outer) => outer.allocate(layout),
+                None => Global.allocate(layout),
+            }
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn nested() {
+        let inner = NonReentrant(Global);
+        let outer = NonReentrant(&inner);
+        let layout = Layout::new::<u64>();
+        let ptr = outer.allocate(layout).unwrap();
+        unsafe { outer.deallocate(ptr.cast(), layout) };
+    }
+
+    #[test]
+    #[should_panic(expected = "reentered")]
+    fn reentrant() {
+        let alloc = NonReentrant(Reentrant {
+            outer: Cell::new(None),
+        });
+        let layout = Layout::new::<u64>();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+        alloc.0.outer.set(Some(&alloc));
+        let _ = alloc.allocate(layout);
+    }
+}
@@ -0,0 +1,361 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global};
+use crate::align_check::debug_aligned;
+use alloc::alloc::{Layout, handle_alloc_error};
+use core::cell::Cell;
+use core::fmt::{self, Debug, Formatter};
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+/// Stored at the start of each region.
+struct RegionHeader {
+    prev: Option<NonNull<RegionHeader>>,
+    /// The size of the region, including this header.
+    size: usize,
+}
+
+const HEADER_SIZE: usize = size_of::<RegionHeader>();
+
+/// A bump allocator that allocates regions from a backing allocator `A`,
+/// growing as needed.
+///
+/// Allocations are served sequentially from the current region. When a
+/// request doesn’t fit, a new region at least twice as large as the last is
+/// allocated from `A`. Deallocation is a no-op; all regions are deallocated
+/// together when the allocator is dropped.
+///
+/// Regions are aligned to [`Self::REGION_ALIGN`]. Requests with a larger
+/// alignment are satisfied by padding within the region, which can waste up
+/// to `align - 1` bytes per request (and makes new regions correspondingly
+/// larger). To surface this cost, [`Self::set_strict_align`] makes such
+/// requests fail with [`AllocError`] instead.
+pub struct RegionAllocator<A: Allocator = Global> {
+    alloc: A,
+    strict_align: bool,
+    frozen: bool,
+    /// The most recently allocated region.
+    head: Cell<NonNull<RegionHeader>>,
+    /// The offset of the first unallocated byte in the current region.
+    offset: Cell<usize>,
+}
+
+impl<A: Allocator> RegionAllocator<A> {
+    /// The alignment of each region, which is the largest alignment this
+    /// allocator can provide without padding.
+    pub const REGION_ALIGN: usize = align_of::<RegionHeader>();
+
+    /// Creates a new region allocator whose first region, allocated from
+    /// `alloc`, is `size` bytes long.
+    ///
+    /// The size includes a small header.
+    ///
+    /// If the first region can’t be allocated, this calls
+    /// [`handle_alloc_error`]. Use [`Self::try_new`] to handle the failure
+    /// instead.
+    pub fn new(alloc: A, size: usize) -> Self {
+        Self::try_new(alloc, size).unwrap_or_else(|_| {
+            handle_alloc_error(Self::region_layout(size.max(HEADER_SIZE)))
+        })
+    }
+
+    /// Like [`Self::new`], but returns an error if the first region can’t be
+    /// allocated.
+    pub fn try_new(alloc: A, size: usize) -> Result<Self, AllocError> {
+        let size = size.max(HEADER_SIZE);
+        let head = Self::allocate_region(&alloc, size, None)?;
+        Ok(Self {
+            alloc,
+            strict_align: false,
+            frozen: false,
+            head: Cell::new(head),
+            offset: Cell::new(HEADER_SIZE),
+        })
+    }
+
+    /// Sets whether requests with an alignment greater than
+    /// [`Self::REGION_ALIGN`] fail rather than being padded.
+    pub fn set_strict_align(&mut self, strict: bool) {
+        self.strict_align = strict;
+    }
+
+    /// Prevents any further allocations, including of new regions, until
+    /// [`Self::thaw`] is called.
+    ///
+    /// Regions come from `A` and needn’t be page-aligned, so their pages
+    /// aren’t write-protected.
+    pub fn freeze(&mut self) {
+        self.frozen = true;
+    }
+
+    /// Allows allocation again after [`Self::freeze`].
+    pub fn thaw(&mut self) {
+        self.frozen = false;
+    }
+
+    /// Returns whether the allocator is frozen.
+    pub fn is_frozen(&self) -> bool {
+        self.frozen
+    }
+
+    /// Ensures at least `additional` contiguous bytes are available in the
+    /// current region, allocating a new region now if they aren’t.
+    ///
+    /// The bytes are counted from the next offset aligned to
+    /// [`Self::REGION_ALIGN`], so a burst of requests that needs no more
+    /// than `additional` bytes, including padding between them, won’t cause
+    /// a new region to be allocated partway through.
+    pub fn reserve(&self, additional: usize) -> Result<(), AllocError> {
+        if self.frozen {
+            return Err(AllocError);
+        }
+        let layout = Layout::from_size_align(additional, Self::REGION_ALIGN)
+            .map_err(|_| AllocError)?;
+        if Self::fit(self.head.get(), self.offset.get(), layout).is_none() {
+            self.grow(layout)?;
+        }
+        Ok(())
+    }
+
+    fn region_layout(size: usize) -> Layout {
+        Layout::from_size_align(size, align_of::<RegionHeader>())
+            .expect("region size overflow")
+    }
+
+    fn allocate_region(
+        alloc: &A,
+        size: usize,
+        prev: Option<NonNull<RegionHeader>>,
+    ) -> Result<NonNull<RegionHeader>, AllocError> {
+        let layout = Layout::from_size_align(size, align_of::<RegionHeader>())
+            .map_err(|_| AllocError)?;
+        let region = alloc.allocate(layout)?.cast::<RegionHeader>();
+        // SAFETY: `region` is valid for `layout`, which is at least as large
+        // and as aligned as a `RegionHeader`.
+        unsafe {
+            region.as_ptr().write(RegionHeader {
+                prev,
+                size,
+            });
+        }
+        Ok(region)
+    }
+
+    /// Returns the offset of a block for `layout` placed at or after
+    /// `offset` in `region`, if it fits.
+    fn fit(
+        region: NonNull<RegionHeader>,
+        offset: usize,
+        layout: Layout,
+    ) -> Option<usize> {
+        // SAFETY: `region` is always a region allocated by this allocator.
+        let size = unsafe { (*region.as_ptr()).size };
+        let addr = region.as_ptr() as usize;
+        let align = layout.align();
+        let start =
+            addr.checked_add(offset)?.checked_add(align - 1)? & !(align - 1);
+        let start = start - addr;
+        let end = start.checked_add(layout.size())?;
+        if end > size {
+            return None;
+        }
+        Some(start)
+    }
+
+    /// Allocates a new region large enough for `layout`.
+    fn grow(&self, layout: Layout) -> Result<(), AllocError> {
+        let head = self.head.get();
+        // SAFETY: `self.head` is always a region allocated by this allocator.
+        let last = unsafe { (*head.as_ptr()).size };
+        let needed = HEADER_SIZE
+            .checked_add(layout.size())
+            .and_then(|n| n.checked_add(layout.align() - 1))
+            .ok_or(AllocError)?;
+        let size = needed.max(last.saturating_mul(2));
+        let region = Self::allocate_region(&self.alloc, size, Some(head))
+            .or_else(|_| {
+                // Doubling might fail where a smaller region wouldn’t.
+                Self::allocate_region(&self.alloc, needed, Some(head))
+            })?;
+        self.head.set(region);
+        self.offset.set(HEADER_SIZE);
+        Ok(())
+    }
+}
+
+impl<A: Allocator + Debug> Debug for RegionAllocator<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RegionAllocator").field("alloc", &self.alloc).finish()
+    }
+}
+
+// SAFETY: Blocks are handed out only from the unallocated part of the
+// current region, which is never handed out again. Regions stay allocated
+// until the allocator is dropped.
+unsafe impl<A: Allocator> Allocator for RegionAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if self.frozen {
+            return Err(AllocError);
+        }
+        if self.strict_align && layout.align() > Self::REGION_ALIGN {
+            return Err(AllocError);
+        }
+        let start = match Self::fit(self.head.get(), self.offset.get(), layout)
+        {
+            Some(start) => start,
+            None => {
+                self.grow(layout)?;
+                Self::fit(self.head.get(), self.offset.get(), layout)
+                    .ok_or(AllocError)?
+            }
+        };
+        self.offset.set(start + layout.size());
+        // SAFETY: `Self::fit` ensures the block is within the region.
+        let ptr = unsafe { (self.head.get().as_ptr() as *mut u8).add(start) };
+        // SAFETY: `ptr` is derived from a `NonNull`.
+        let block = unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                ptr,
+                layout.size(),
+            ))
+        };
+        Ok(debug_aligned(block, layout))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+}
+
+impl<A: Allocator> Drop for RegionAllocator<A> {
+    fn drop(&mut self) {
+        let mut next = Some(self.head.get());
+        while let Some(region) = next {
+            // SAFETY: `region` was allocated by this allocator with the
+            // layout for its size.
+            unsafe {
+                let header = region.as_ptr().read();
+                let layout = Self::region_layout(header.size);
+                self.alloc.deallocate(region.cast(), layout);
+                next = header.prev;
+            }
+        }
+    }
+}
+
+// SAFETY: The allocator owns its regions, so it can be sent to another
+// thread if its backing allocator can.
+unsafe impl<A: Allocator + Send> Send for RegionAllocator<A> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrackingAllocator;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn grow() {
+        let backing = TrackingAllocator::new(Global);
+        let region = RegionAllocator::new(&backing, 64);
+        let layout = Layout::new::<[u64; 4]>();
+        let blocks: Vec<_> = (0..50_u64)
+            .map(|i| {
+                let block =
+                    region.allocate(layout).unwrap().cast::<[u64; 4]>();
+                unsafe { block.as_ptr().write([i; 4]) };
+                block
+            })
+            .collect();
+        let regions = backing.stats().allocations;
+        assert!(regions > 3);
+        assert!(regions < 10);
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(unsafe { *block.as_ptr() }, [i as u64; 4]);
+        }
+
+        // Large and overaligned requests get a region of their own.
+        let big = Layout::from_size_align(10_000, 256).unwrap();
+        let ptr = region.allocate(big).unwrap();
+        assert_eq!(ptr.as_ptr() as *mut u8 as usize % 256, 0);
+        unsafe { region.deallocate(ptr.cast(), big) };
+        assert_eq!(backing.stats().allocations, regions + 1);
+
+        drop(region);
+        let stats = backing.stats();
+        assert_eq!(stats.deallocations, stats.allocations);
+        assert_eq!(stats.current, 0);
+    }
+
+    #[test]
+    fn strict_align() {
+        let mut region = RegionAllocator::new(Global, 256);
+        let align = RegionAllocator::<Global>::REGION_ALIGN;
+        let native = Layout::from_size_align(8, align).unwrap();
+        let over = Layout::from_size_align(8, 64).unwrap();
+        let ptr = region.allocate(over).unwrap();
+        assert_eq!(ptr.as_ptr() as *mut u8 as usize % 64, 0);
+        region.set_strict_align(true);
+        assert_eq!(region.allocate(over), Err(AllocError));
+        assert!(region.allocate(native).is_ok());
+    }
+
+    #[test]
+    fn freeze() {
+        let backing = TrackingAllocator::new(Global);
+        let mut region = RegionAllocator::new(&backing, 64);
+        let layout = Layout::new::<u64>();
+        region.allocate(layout).unwrap();
+        region.freeze();
+        assert!(region.is_frozen());
+        assert_eq!(region.allocate(layout), Err(AllocError));
+        assert_eq!(backing.stats().allocations, 1);
+        region.thaw();
+        assert!(region.allocate(layout).is_ok());
+    }
+
+    #[test]
+    fn try_new() {
+        assert!(RegionAllocator::try_new(crate::NullAllocator, 64).is_err());
+        assert!(RegionAllocator::try_new(Global, 64).is_ok());
+    }
+
+    #[test]
+    fn reserve() {
+        let backing = TrackingAllocator::new(Global);
+        let region = RegionAllocator::new(&backing, 64);
+        region.reserve(4096).unwrap();
+        assert_eq!(backing.stats().allocations, 2);
+        let layouts = [Layout::new::<u8>(), Layout::new::<[u32; 3]>()];
+        for &layout in layouts.iter().cycle().take(200) {
+            region.allocate(layout).unwrap();
+        }
+        assert_eq!(backing.stats().allocations, 2);
+
+        // Already-available space doesn’t need a new region.
+        region.reserve(16).unwrap();
+        assert_eq!(backing.stats().allocations, 2);
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn std_vec() {
+        let region = RegionAllocator::new(Global, 64);
+        let mut vec = Vec::new_in(&region);
+        vec.extend(0..100_u64);
+        assert_eq!(vec.iter().sum::<u64>(), 4950);
+    }
+}
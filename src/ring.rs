@@ -0,0 +1,323 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{
+    AllocError, Allocator, AllocatorEq, FragmentationStats, OwningAllocator,
+};
+use crate::align_check::debug_aligned;
+use crate::owning::in_buffer;
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem::{MaybeUninit, align_of, size_of};
+use core::ptr::{self, NonNull};
+
+/// Sentinel offset indicating the absence of a block.
+const NONE: usize = usize::MAX;
+
+/// Stored immediately before each allocation.
+struct Header {
+    /// The offset of the next block’s header, or [`NONE`] if this is the
+    /// most recent block.
+    next: usize,
+    live: bool,
+}
+
+const HEADER_SIZE: usize = size_of::<Header>();
+
+/// An allocator that serves allocations sequentially from a fixed buffer,
+/// wrapping around to the start once the end is reached.
+///
+/// Space is reclaimed when the oldest outstanding allocation is deallocated,
+/// which makes this allocator suitable for FIFO workloads. Blocks that are
+/// deallocated out of order are reclaimed once every older block has also
+/// been deallocated.
+///
+/// Each allocation is preceded by a small header, so the buffer must be
+/// larger than the total size of the allocations it needs to hold at once.
+#[derive(Debug)]
+pub struct RingAllocator<'a> {
+    start: NonNull<u8>,
+    len: usize,
+    /// The offset just past the most recent block.
+    head: Cell<usize>,
+    /// The header offset of the oldest block.
+    tail: Cell<usize>,
+    /// The header offset of the most recent block, or [`NONE`] if there are
+    /// no outstanding blocks.
+    last: Cell<usize>,
+    phantom: PhantomData<&'a mut [MaybeUninit<u8>]>,
+}
+
+impl<'a> RingAllocator<'a> {
+    /// Creates a new ring allocator that allocates from `buf`.
+    pub fn new(buf: &'a mut [MaybeUninit<u8>]) -> Self {
+        Self {
+            start: NonNull::new(buf.as_mut_ptr() as *mut u8).unwrap(),
+            len: buf.len(),
+            head: Cell::new(0),
+            tail: Cell::new(0),
+            last: Cell::new(NONE),
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns statistics about the space available for new allocations.
+    ///
+    /// Blocks that were deallocated out of order aren’t counted as free
+    /// until they are reclaimed (see the [type-level docs](Self)).
+    pub fn fragmentation(&self) -> FragmentationStats {
+        let mut stats = FragmentationStats::default();
+        let (head, tail) = (self.head.get(), self.tail.get());
+        if self.last.get() == NONE {
+            stats.add(self.len);
+        } else if head > tail {
+            stats.add(self.len - head);
+            stats.add(tail);
+        } else {
+            stats.add(tail - head);
+        }
+        stats
+    }
+
+    /// Returns the header and data offsets of a block for `layout` placed at
+    /// or after `start`, if it fits before `limit`.
+    fn fit(
+        &self,
+        layout: Layout,
+        start: usize,
+        limit: usize,
+    ) -> Option<(usize, usize)> {
+        debug_assert!(
+            layout.size() <= isize::MAX as usize - (layout.align() - 1),
+            "layout size overflows when padded to its alignment",
+        );
+        let align = layout.align().max(align_of::<Header>());
+        let addr = self.start.as_ptr() as usize;
+        let data = start
+            .checked_add(HEADER_SIZE)?
+            .checked_add(addr)?
+            .checked_add(align - 1)?
+            & !(align - 1);
+        let data = data - addr;
+        let end = data.checked_add(layout.size())?;
+        if end > limit {
+            return None;
+        }
+        Some((data - HEADER_SIZE, data))
+    }
+
+    fn header(&self, offset: usize) -> *mut Header {
+        // SAFETY: Callers only pass offsets of headers within the buffer.
+        unsafe { self.start.as_ptr().add(offset) as *mut Header }
+    }
+
+    /// Reclaims deallocated blocks starting from the oldest one.
+    fn advance_tail(&self) {
+        loop {
+            // SAFETY: `self.tail` is the offset of a valid header whenever
+            // there are outstanding blocks.
+            let header = unsafe { &*self.header(self.tail.get()) };
+            if header.live {
+                return;
+            }
+            if header.next == NONE {
+                self.last.set(NONE);
+                return;
+            }
+            self.tail.set(header.next);
+        }
+    }
+}
+
+// SAFETY: Blocks are handed out only from the region that isn’t occupied by
+// outstanding blocks, and that region only grows when the oldest blocks are
+// deallocated.
+unsafe impl Allocator for RingAllocator<'_> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let last = self.last.get();
+        let (header, data) = if last == NONE {
+            self.fit(layout, 0, self.len)
+        } else if self.head.get() > self.tail.get() {
+            self.fit(layout, self.head.get(), self.len)
+                .or_else(|| self.fit(layout, 0, self.tail.get()))
+        } else {
+            self.fit(layout, self.head.get(), self.tail.get())
+        }
+        .ok_or(AllocError)?;
+
+        // SAFETY: `Self::fit` ensures the header is within the buffer and
+        // properly aligned.
+        unsafe {
+            self.header(header).write(Header {
+                next: NONE,
+                live: true,
+            });
+        }
+        if last == NONE {
+            self.tail.set(header);
+        } else {
+            // SAFETY: `last` is the offset of a valid header.
+            unsafe {
+                (*self.header(last)).next = header;
+            }
+        }
+        self.last.set(header);
+        self.head.set(data + layout.size());
+
+        // SAFETY: `Self::fit` ensures the block is within the buffer.
+        let ptr = unsafe { self.start.as_ptr().add(data) };
+        // SAFETY: `ptr` is derived from a `NonNull`.
+        let block = unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                ptr,
+                layout.size(),
+            ))
+        };
+        Ok(debug_aligned(block, layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
+        let offset = ptr.as_ptr() as usize - self.start.as_ptr() as usize;
+        let header = offset - HEADER_SIZE;
+        // SAFETY: Checked by caller (`ptr` must have been returned by this
+        // allocator, so it is preceded by a header).
+        unsafe {
+            (*self.header(header)).live = false;
+        }
+        if header == self.tail.get() {
+            self.advance_tail();
+        }
+    }
+}
+
+// SAFETY: The allocator uniquely borrows its buffer, so it can be sent to
+// another thread.
+unsafe impl Send for RingAllocator<'_> {}
+
+// SAFETY: All blocks are allocated from the buffer.
+unsafe impl OwningAllocator for RingAllocator<'_> {
+    fn owns(&self, ptr: NonNull<u8>, layout: Layout) -> bool {
+        in_buffer(self.start, self.len, ptr, layout)
+    }
+}
+
+// SAFETY: Two ring allocators with the same buffer must be the same
+// allocator, as each one uniquely borrows its buffer.
+unsafe impl AllocatorEq for RingAllocator<'_> {
+    fn same_allocator(&self, other: &Self) -> bool {
+        self.start == other.start
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIZE: usize = 32;
+    const BLOCK: usize = HEADER_SIZE + SIZE;
+
+    #[repr(align(16))]
+    struct Buffer([MaybeUninit<u8>; BLOCK * 3 + 16]);
+
+    fn layout() -> Layout {
+        Layout::from_size_align(SIZE, align_of::<Header>()).unwrap()
+    }
+
+    #[test]
+    fn full() {
+        let mut buf = Buffer([MaybeUninit::uninit(); BLOCK * 3 + 16]);
+        let ring = RingAllocator::new(&mut buf.0);
+        for _ in 0..3 {
+            ring.allocate(layout()).unwrap();
+        }
+        assert_eq!(ring.allocate(layout()), Err(AllocError));
+    }
+
+    #[test]
+    fn fragmentation() {
+        let mut buf = Buffer([MaybeUninit::uninit(); BLOCK * 3 + 16]);
+        let ring = RingAllocator::new(&mut buf.0);
+        let stats = ring.fragmentation();
+        assert_eq!(stats.free_blocks, 1);
+        assert_eq!(stats.largest_free_block, BLOCK * 3 + 16);
+
+        let a = ring.allocate(layout()).unwrap();
+        let b = ring.allocate(layout()).unwrap();
+        let c = ring.allocate(layout()).unwrap();
+        let stats = ring.fragmentation();
+        assert_eq!(stats.free_blocks, 1);
+        assert_eq!(stats.total_free_bytes, 16);
+
+        unsafe {
+            ring.deallocate(a.cast(), layout());
+            ring.deallocate(c.cast(), layout());
+        }
+        let stats = ring.fragmentation();
+        assert_eq!(stats.free_blocks, 2);
+        assert_eq!(stats.largest_free_block, BLOCK);
+        assert_eq!(stats.total_free_bytes, BLOCK + 16);
+
+        unsafe { ring.deallocate(b.cast(), layout()) };
+        assert_eq!(ring.fragmentation().total_free_bytes, BLOCK * 3 + 16);
+    }
+
+    #[test]
+    fn huge() {
+        let mut buf = Buffer([MaybeUninit::uninit(); BLOCK * 3 + 16]);
+        let ring = RingAllocator::new(&mut buf.0);
+        ring.allocate(layout()).unwrap();
+        let size = isize::MAX as usize - 63;
+        let layout = Layout::from_size_align(size, 64).unwrap();
+        assert_eq!(ring.allocate(layout), Err(AllocError));
+    }
+
+    #[test]
+    fn wraparound() {
+        let mut buf = Buffer([MaybeUninit::uninit(); BLOCK * 3 + 16]);
+        let ring = RingAllocator::new(&mut buf.0);
+        let a = ring.allocate(layout()).unwrap();
+        let b = ring.allocate(layout()).unwrap();
+        let c = ring.allocate(layout()).unwrap();
+        assert!(ring.allocate(layout()).is_err());
+
+        unsafe { ring.deallocate(a.cast(), layout()) };
+        let d = ring.allocate(layout()).unwrap();
+        assert_eq!(d.cast::<u8>(), a.cast::<u8>());
+        assert!(ring.allocate(layout()).is_err());
+
+        // Out-of-order deallocation doesn’t free any space.
+        unsafe { ring.deallocate(c.cast(), layout()) };
+        assert!(ring.allocate(layout()).is_err());
+
+        // Deallocating `b` reclaims both `b` and `c`.
+        unsafe { ring.deallocate(b.cast(), layout()) };
+        let big = Layout::from_size_align(SIZE + BLOCK, 1).unwrap();
+        let e = ring.allocate(big).unwrap();
+        assert_eq!(e.cast::<u8>(), b.cast::<u8>());
+        assert!(ring.allocate(layout()).is_err());
+
+        unsafe {
+            ring.deallocate(d.cast(), layout());
+            ring.deallocate(e.cast(), big);
+        }
+        for _ in 0..3 {
+            ring.allocate(layout()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,147 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use crate::rounded::Rounded;
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that forwards to `A`, rounding the size of every request up
+/// to a multiple of `MULT`, and returning blocks of exactly that length.
+///
+/// Containers that use the length of returned blocks as their capacity then
+/// get capacities that are multiples of `MULT`, which can help code that
+/// processes them in SIMD-sized chunks. Blocks are always exactly the
+/// rounded size, even if `A` returns a longer block. A `MULT` of 0 or 1
+/// returns blocks of exactly the requested size.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RoundLen<A, const MULT: usize>(pub A);
+
+impl<A, const MULT: usize> RoundLen<A, MULT> {
+    fn round(layout: Layout) -> Result<Layout, AllocError> {
+        let mult = MULT.max(1);
+        let size = layout.size().checked_add(mult - 1).ok_or(AllocError)?
+            / mult
+            * mult;
+        Layout::from_size_align(size, layout.align()).map_err(|_| AllocError)
+    }
+
+    fn rounded(
+        &self,
+    ) -> Rounded<'_, A, impl Fn(Layout) -> Result<Layout, AllocError>> {
+        Rounded {
+            inner: &self.0,
+            round: Self::round,
+        }
+    }
+}
+
+// SAFETY: This impl forwards to `A`. Every block is allocated and
+// deallocated with its rounded layout. Returned blocks are exactly the
+// rounded size, so any layout that fits a block rounds to the same size.
+unsafe impl<A: Allocator, const MULT: usize> Allocator for RoundLen<A, MULT> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.rounded().allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.rounded().allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+/// Adds the rounding to `A`’s overhead for the rounded block.
+impl<A: Overhead, const MULT: usize> Overhead for RoundLen<A, MULT> {
+    fn overhead(&self, layout: Layout) -> usize {
+        Self::round(layout).map_or(0, |rounded| {
+            rounded.size() - layout.size() + self.0.overhead(rounded)
+        })
+    }
+}
+
+#[test]
+fn test() {
+    use crate::{Global, TrackingAllocator};
+    use core::mem::MaybeUninit;
+
+    fn len(block: NonNull<[u8]>) -> usize {
+        unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len()
+    }
+
+    let backing = TrackingAllocator::new(Global);
+    let alloc = RoundLen::<_, 32>(&backing);
+    let layout = Layout::new::<[u8; 40]>();
+    let ptr = alloc.allocate(layout).unwrap();
+    assert_eq!(len(ptr), 64);
+    assert_eq!(backing.stats().current, 64);
+
+    // The whole block can be used, and growing within it doesn’t move it.
+    let full = Layout::new::<[u8; 64]>();
+    let grown = unsafe { alloc.grow(ptr.cast(), layout, full) }.unwrap();
+    assert_eq!(grown, ptr);
+    let bigger = Layout::new::<[u8; 65]>();
+    let grown = unsafe { alloc.grow_zeroed(grown.cast(), full, bigger) };
+    let grown = grown.unwrap();
+    assert_eq!(len(grown), 96);
+    let bytes = unsafe { &*(grown.as_ptr() as *const [u8]) };
+    assert!(bytes[64..].iter().all(|&b| b == 0));
+    unsafe { alloc.deallocate(grown.cast(), Layout::new::<[u8; 90]>()) };
+    assert_eq!(backing.stats().current, 0);
+
+    let exact = RoundLen::<_, 0>(Global);
+    let ptr = exact.allocate(layout).unwrap();
+    assert_eq!(len(ptr), 40);
+    unsafe { exact.deallocate(ptr.cast(), layout) };
+}
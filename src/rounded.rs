@@ -0,0 +1,156 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+/// The shared implementation of wrappers that round the layout of every
+/// request with `round` before forwarding it to `inner`.
+///
+/// Blocks are returned with a length of exactly the rounded size, so any
+/// layout that fits a block rounds to the same layout (provided `round` is
+/// monotonic), and growing or shrinking within a rounded size doesn’t call
+/// `inner` at all.
+pub(crate) struct Rounded<'a, A: ?Sized, F> {
+    pub inner: &'a A,
+    pub round: F,
+}
+
+/// Returns a block starting at `ptr` with length `len`.
+fn block(ptr: *mut u8, len: usize) -> NonNull<[u8]> {
+    let ptr = ptr::slice_from_raw_parts_mut(ptr, len);
+    // SAFETY: Callers pass pointers that came from a `NonNull`.
+    unsafe { NonNull::new_unchecked(ptr) }
+}
+
+/// Sets the length of `result` to `layout.size()`.
+fn clamp(
+    result: Result<NonNull<[u8]>, AllocError>,
+    layout: Layout,
+) -> Result<NonNull<[u8]>, AllocError> {
+    Ok(block(result?.as_ptr() as *mut u8, layout.size()))
+}
+
+impl<A, F> Rounded<'_, A, F>
+where
+    A: Allocator + ?Sized,
+    F: Fn(Layout) -> Result<Layout, AllocError>,
+{
+    /// # Safety
+    ///
+    /// `layout` must describe a block currently allocated via this
+    /// allocator.
+    unsafe fn round_unchecked(&self, layout: Layout) -> Layout {
+        match (self.round)(layout) {
+            Ok(layout) => layout,
+            // SAFETY: `self.round` must have succeeded for an equivalent
+            // layout when the block was allocated.
+            Err(_) => unsafe { core::hint::unreachable_unchecked() },
+        }
+    }
+
+    pub fn allocate(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let layout = (self.round)(layout)?;
+        clamp(self.inner.allocate(layout), layout)
+    }
+
+    pub fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let layout = (self.round)(layout)?;
+        clamp(self.inner.allocate_zeroed(layout), layout)
+    }
+
+    /// # Safety
+    ///
+    /// See [`Allocator::deallocate`].
+    pub unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe {
+            let layout = self.round_unchecked(layout);
+            self.inner.deallocate(ptr, layout);
+        }
+    }
+
+    /// # Safety
+    ///
+    /// See [`Allocator::grow`].
+    pub unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let old = unsafe { self.round_unchecked(old_layout) };
+        let new = (self.round)(new_layout)?;
+        if old == new {
+            return Ok(block(ptr.as_ptr(), new.size()));
+        }
+        // SAFETY: Checked by caller.
+        clamp(unsafe { self.inner.grow(ptr, old, new) }, new)
+    }
+
+    /// # Safety
+    ///
+    /// See [`Allocator::grow_zeroed`].
+    pub unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let old = unsafe { self.round_unchecked(old_layout) };
+        let new = (self.round)(new_layout)?;
+        if old == new {
+            let len = new.size() - old_layout.size();
+            // SAFETY: The block is valid for `new.size()` bytes.
+            unsafe {
+                ptr.as_ptr().add(old_layout.size()).write_bytes(0, len);
+            }
+            return Ok(block(ptr.as_ptr(), new.size()));
+        }
+        // SAFETY: Checked by caller.
+        clamp(unsafe { self.inner.grow_zeroed(ptr, old, new) }, new)
+    }
+
+    /// # Safety
+    ///
+    /// See [`Allocator::shrink`].
+    pub unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let old = unsafe { self.round_unchecked(old_layout) };
+        let new = (self.round)(new_layout)?;
+        if old == new {
+            return Ok(block(ptr.as_ptr(), new.size()));
+        }
+        // SAFETY: Checked by caller.
+        clamp(unsafe { self.inner.shrink(ptr, old, new) }, new)
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global};
+use alloc::alloc::Layout;
+use alloc::rc::Rc;
+use core::cell::RefCell;
+use core::ptr::NonNull;
+
+std::thread_local! {
+    static CURRENT: RefCell<Option<Rc<dyn Allocator>>> = RefCell::new(None);
+}
+
+/// Restores the previous allocator when dropped.
+struct Guard(Option<Rc<dyn Allocator>>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let prev = self.0.take();
+        // The replaced allocator is dropped after the borrow ends, in case
+        // its destructor calls `current_allocator`.
+        let _scoped = CURRENT.with(|current| current.replace(prev));
+    }
+}
+
+/// Runs `f` with `alloc` as the current thread’s [`current_allocator`].
+///
+/// This lets code that can’t take an allocator parameter (like a callback
+/// called by a library) still be steered toward, e.g., an arena for a
+/// scope. It affects only code that explicitly allocates through
+/// [`current_allocator`]; other allocations, including those made by the
+/// standard library’s collections, are unaffected. Scopes can be nested;
+/// the previous allocator is restored when `f` returns or panics.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+pub fn with_allocator<A, R, F>(alloc: A, f: F) -> R
+where
+    A: Allocator + 'static,
+    F: FnOnce() -> R,
+{
+    let alloc: Rc<dyn Allocator> = Rc::new(alloc);
+    let prev = CURRENT.with(|current| current.replace(Some(alloc)));
+    let _guard = Guard(prev);
+    f()
+}
+
+/// Returns the allocator installed on the current thread by the innermost
+/// active call to [`with_allocator`], or [`Global`] if there is none.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+pub fn current_allocator() -> CurrentAllocator {
+    CurrentAllocator(CURRENT.with(|current| current.borrow().clone()))
+}
+
+/// The allocator returned by [`current_allocator`].
+///
+/// This keeps the allocator it refers to alive, so blocks allocated within
+/// a [`with_allocator`] scope can still be deallocated through it after the
+/// scope ends.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "std")))]
+#[derive(Clone)]
+pub struct CurrentAllocator(Option<Rc<dyn Allocator>>);
+
+impl CurrentAllocator {
+    fn get(&self) -> &dyn Allocator {
+        match &self.0 {
+            Some(alloc) => &**alloc,
+            None => &Global,
+        }
+    }
+}
+
+impl core::fmt::Debug for CurrentAllocator {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_tuple("CurrentAllocator")
+            .field(&if self.0.is_some() {
+                "scoped"
+            } else {
+                "Global"
+            })
+            .finish()
+    }
+}
+
+// SAFETY: This impl forwards to the allocator it holds, and clones hold
+// the same one.
+unsafe impl Allocator for CurrentAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.get().allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.get().allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.get().deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.get().grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.get().grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.get().shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[test]
+fn test() {
+    use crate::TrackingAllocator;
+
+    static OUTER: TrackingAllocator<Global> = TrackingAllocator::new(Global);
+    static INNER: TrackingAllocator<Global> = TrackingAllocator::new(Global);
+
+    fn alloc_once() {
+        let layout = Layout::new::<u64>();
+        let alloc = current_allocator();
+        let ptr = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(ptr.cast(), layout) };
+    }
+
+    let count = || (OUTER.stats().allocations, INNER.stats().allocations);
+    alloc_once();
+    assert_eq!(count(), (0, 0));
+    with_allocator(&OUTER, || {
+        alloc_once();
+        assert_eq!(count(), (1, 0));
+        with_allocator(&INNER, || {
+            alloc_once();
+            assert_eq!(count(), (1, 1));
+        });
+        alloc_once();
+        assert_eq!(count(), (2, 1));
+    });
+    alloc_once();
+    assert_eq!(count(), (2, 1));
+}
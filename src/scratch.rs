@@ -0,0 +1,285 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global};
+use alloc::alloc::Layout;
+use core::cell::Cell;
+use core::fmt::{self, Debug, Formatter};
+use core::ptr::{self, NonNull};
+
+/// The smallest capacity of the backing buffer.
+const MIN_CAPACITY: usize = 64;
+
+/// An allocator for loops that allocate, use, and free a single block at a
+/// time, reusing one backing buffer allocated from `A`.
+///
+/// Every allocation returns the start of the backing buffer. If a request
+/// doesn’t fit, the buffer is replaced with one at least twice as large, so
+/// after a few iterations, the loop stops allocating from `A` altogether.
+/// This is common in serializers and formatters that build output in a
+/// temporary buffer.
+///
+/// Only one block can be live at a time. It becomes free again when it is
+/// deallocated, or when [`Self::reset`] is called. Allocating while a block
+/// is live panics in debug builds and fails with [`AllocError`] in release
+/// builds.
+///
+/// Like [`Bump`](crate::Bump), [`Allocator`] is implemented for
+/// `&ScratchAllocator`, so [`Self::reset`] can’t be called while the block
+/// is in use.
+pub struct ScratchAllocator<A: Allocator = Global> {
+    alloc: A,
+    buf: Cell<Option<(NonNull<u8>, Layout)>>,
+    live: Cell<bool>,
+}
+
+impl ScratchAllocator {
+    /// Creates a new scratch allocator whose buffer is allocated from
+    /// [`Global`].
+    pub fn new() -> Self {
+        Self::new_in(Global)
+    }
+}
+
+impl Default for ScratchAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<A: Allocator> ScratchAllocator<A> {
+    /// Creates a new scratch allocator whose buffer is allocated from
+    /// `alloc`.
+    ///
+    /// This doesn’t allocate until the first request.
+    pub fn new_in(alloc: A) -> Self {
+        Self {
+            alloc,
+            buf: Cell::new(None),
+            live: Cell::new(false),
+        }
+    }
+
+    /// Returns the size of the backing buffer.
+    pub fn capacity(&self) -> usize {
+        self.buf.get().map_or(0, |(_, layout)| layout.size())
+    }
+
+    /// Frees the live block, if any, without deallocating it.
+    pub fn reset(&mut self) {
+        self.live.set(false);
+    }
+
+    /// Returns the layout of a buffer big enough for `layout`, replacing a
+    /// buffer with layout `old`.
+    fn buffer_layout(old: Option<Layout>, layout: Layout) -> Option<Layout> {
+        let (size, align) = old.map_or((0, 1), |l| (l.size(), l.align()));
+        let size = size.saturating_mul(2).max(layout.size()).max(MIN_CAPACITY);
+        Layout::from_size_align(size, align.max(layout.align())).ok()
+    }
+
+    /// Returns whether the buffer can hold a block for `layout`.
+    fn fits(buf: NonNull<u8>, buf_layout: Layout, layout: Layout) -> bool {
+        layout.size() <= buf_layout.size()
+            && buf.as_ptr() as usize & (layout.align() - 1) == 0
+    }
+}
+
+impl<A: Allocator + Debug> Debug for ScratchAllocator<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScratchAllocator")
+            .field("alloc", &self.alloc)
+            .field("capacity", &self.capacity())
+            .field("live", &self.live.get())
+            .finish()
+    }
+}
+
+fn block(ptr: NonNull<u8>, len: usize) -> NonNull<[u8]> {
+    // SAFETY: `ptr` is a `NonNull`.
+    unsafe {
+        NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+            ptr.as_ptr(),
+            len,
+        ))
+    }
+}
+
+// SAFETY: At most one block is live at a time, and the buffer is only
+// replaced while no block is live, or by resizing the live block.
+// `ScratchAllocator::reset` requires a unique reference, so it can’t be
+// called while any `&ScratchAllocator` is in use.
+unsafe impl<A: Allocator> Allocator for &ScratchAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        debug_assert!(
+            !self.live.get(),
+            "a block from this scratch allocator is already live",
+        );
+        if self.live.get() {
+            return Err(AllocError);
+        }
+        let current = self.buf.get();
+        let (buf, buf_layout) = match current {
+            Some((buf, buf_layout))
+                if ScratchAllocator::<A>::fits(buf, buf_layout, layout) =>
+            {
+                (buf, buf_layout)
+            }
+            _ => {
+                let old = current.map(|(_, layout)| layout);
+                let new_layout =
+                    ScratchAllocator::<A>::buffer_layout(old, layout)
+                        .ok_or(AllocError)?;
+                let new = self.alloc.allocate(new_layout)?.cast();
+                if let Some((buf, buf_layout)) = current {
+                    // SAFETY: No block is live, and `buf` was allocated
+                    // with `buf_layout`.
+                    unsafe { self.alloc.deallocate(buf, buf_layout) };
+                }
+                self.buf.set(Some((new, new_layout)));
+                (new, new_layout)
+            }
+        };
+        debug_assert!(buf_layout.size() >= layout.size());
+        self.live.set(true);
+        Ok(block(buf, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
+        self.live.set(false);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        _old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // `ptr` is the live block, which is at the start of the buffer.
+        let (buf, buf_layout) = match self.buf.get() {
+            Some(buf) => buf,
+            // SAFETY: Checked by caller (`ptr` must have been allocated by
+            // this allocator, so the buffer exists).
+            None => unsafe { core::hint::unreachable_unchecked() },
+        };
+        debug_assert_eq!(buf, ptr);
+        if ScratchAllocator::<A>::fits(buf, buf_layout, new_layout) {
+            return Ok(block(buf, new_layout.size()));
+        }
+        let layout =
+            ScratchAllocator::<A>::buffer_layout(Some(buf_layout), new_layout)
+                .ok_or(AllocError)?;
+        // SAFETY: `buf` was allocated with `buf_layout`, which is no larger
+        // than `layout`. Moving the buffer preserves the block’s contents,
+        // which are at its start.
+        let new = unsafe { self.alloc.grow(buf, buf_layout, layout) }?.cast();
+        self.buf.set(Some((new, layout)));
+        Ok(block(new, new_layout.size()))
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        _old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if ptr.as_ptr() as usize & (new_layout.align() - 1) != 0 {
+            return Err(AllocError);
+        }
+        Ok(block(ptr, new_layout.size()))
+    }
+}
+
+impl<A: Allocator> Drop for ScratchAllocator<A> {
+    fn drop(&mut self) {
+        if let Some((buf, layout)) = self.buf.get() {
+            // SAFETY: `buf` was allocated with `layout`.
+            unsafe { self.alloc.deallocate(buf, layout) };
+        }
+    }
+}
+
+// SAFETY: The allocator owns its buffer, so it can be sent to another
+// thread if its backing allocator can.
+unsafe impl<A: Allocator + Send> Send for ScratchAllocator<A> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TrackingAllocator;
+
+    #[test]
+    fn reuse() {
+        let backing = TrackingAllocator::new(Global);
+        let mut scratch = ScratchAllocator::new_in(&backing);
+        let layout = Layout::new::<[u8; 32]>();
+        let first = (&scratch).allocate(layout).unwrap();
+        scratch.reset();
+        for i in 0..10 {
+            let ptr = (&scratch).allocate(layout);
+            assert_eq!(ptr, Ok(first));
+            if i % 2 == 0 {
+                unsafe { (&scratch).deallocate(first.cast(), layout) };
+            } else {
+                scratch.reset();
+            }
+        }
+        unsafe { (&scratch).deallocate(first.cast(), layout) };
+        assert_eq!(backing.stats().allocations, 1);
+    }
+
+    #[test]
+    fn grow() {
+        let backing = TrackingAllocator::new(Global);
+        let scratch = ScratchAllocator::new_in(&backing);
+        let small = Layout::new::<[u8; 16]>();
+        let ptr = (&scratch).allocate(small).unwrap();
+        assert_eq!(scratch.capacity(), MIN_CAPACITY);
+        unsafe { (&scratch).deallocate(ptr.cast(), small) };
+
+        // A larger request replaces the buffer.
+        let big = Layout::from_size_align(1000, 64).unwrap();
+        let ptr = (&scratch).allocate(big).unwrap();
+        assert_eq!(ptr.as_ptr() as *mut u8 as usize % 64, 0);
+        assert_eq!(scratch.capacity(), 1000);
+        assert_eq!(backing.stats().current, 1000);
+
+        // Growing the live block moves the buffer, keeping its contents.
+        unsafe {
+            (ptr.as_ptr() as *mut u8).write(7);
+            let huge = Layout::from_size_align(5000, 64).unwrap();
+            let ptr = (&scratch).grow(ptr.cast(), big, huge).unwrap();
+            assert_eq!(*(ptr.as_ptr() as *mut u8), 7);
+            assert_eq!(scratch.capacity(), 5000);
+            (&scratch).deallocate(ptr.cast(), huge);
+        }
+        assert!((&scratch).allocate(big).is_ok());
+        drop(scratch);
+        assert_eq!(backing.stats().current, 0);
+    }
+
+    #[cfg(all(feature = "std", debug_assertions))]
+    #[test]
+    #[should_panic(expected = "already live")]
+    fn second_live_block() {
+        let scratch = ScratchAllocator::new();
+        let layout = Layout::new::<u64>();
+        let _a = (&scratch).allocate(layout);
+        let _b = (&scratch).allocate(layout);
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use crate::align_check::debug_aligned;
+use crate::page::page_size;
+use alloc::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+/// Rounds `size` up to a non-zero multiple of `page`.
+fn mapping_size(size: usize, page: usize) -> Option<usize> {
+    Some(size.max(1).checked_add(page - 1)? & !(page - 1))
+}
+
+/// An allocator for secrets, which maps fresh pages for each allocation and
+/// locks them into memory so they are never swapped to disk.
+///
+/// Each block is a separate anonymous memory mapping, so it never shares
+/// pages with the global heap (or with other blocks), and sizes are rounded
+/// up to a multiple of the page size. Blocks are zeroed before they are
+/// unmapped.
+///
+/// If the pages can’t be locked (for example, because of the process’s
+/// `RLIMIT_MEMLOCK`), allocation fails with [`AllocError`] rather than
+/// returning memory that could be swapped. Alignments greater than the page
+/// size aren’t supported.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(all(unix, feature = "secure"))))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SecureAllocator;
+
+// SAFETY: Each block is a distinct mapping, which stays mapped until it is
+// deallocated. Mappings are page-aligned, and the alignment is checked
+// against the page size.
+unsafe impl Allocator for SecureAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let page = page_size();
+        if layout.align() > page {
+            return Err(AllocError);
+        }
+        let size = mapping_size(layout.size(), page).ok_or(AllocError)?;
+        // SAFETY: Creating a new anonymous mapping doesn’t affect any
+        // existing memory.
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANON,
+                -1,
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(AllocError);
+        }
+        // SAFETY: `ptr` is a mapping of `size` bytes.
+        if unsafe { libc::mlock(ptr, size) } != 0 {
+            // SAFETY: `ptr` is a mapping of `size` bytes.
+            unsafe { libc::munmap(ptr, size) };
+            return Err(AllocError);
+        }
+        // SAFETY: `mmap` doesn’t return null on success.
+        let block = unsafe {
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                ptr as *mut u8,
+                size,
+            ))
+        };
+        Ok(debug_aligned(block, layout))
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // Anonymous mappings are always zeroed.
+        self.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // Any layout that fits the block rounds to the size of its mapping,
+        // as the block’s length is already a multiple of the page size.
+        let size = match mapping_size(layout.size(), page_size()) {
+            Some(size) => size,
+            // SAFETY: Checked by caller (the block was allocated, so its size
+            // didn’t overflow).
+            None => unsafe { core::hint::unreachable_unchecked() },
+        };
+        // SAFETY: Checked by caller. `ptr` is a mapping of `size` bytes. The
+        // zeroing can’t be optimized out, as `munmap` is an opaque call that
+        // receives the pointer.
+        unsafe {
+            ptr.as_ptr().write_bytes(0, size);
+            let ptr = ptr.as_ptr() as *mut libc::c_void;
+            libc::munlock(ptr, size);
+            libc::munmap(ptr, size);
+        }
+    }
+}
+
+#[test]
+fn test() {
+    let layout = Layout::new::<[u8; 32]>();
+    let block = match SecureAllocator.allocate(layout) {
+        Ok(block) => block,
+        // Locking memory may not be permitted in this environment.
+        Err(_) => return,
+    };
+    let page = page_size();
+    assert_eq!(unsafe { &*(block.as_ptr() as *const [u8]) }.len(), page);
+    assert_eq!(block.as_ptr() as *mut u8 as usize % page, 0);
+    unsafe {
+        let ptr = block.as_ptr() as *mut [u8; 32];
+        assert_eq!(*ptr, [0; 32]);
+        ptr.write(*b"correct horse battery staple....");
+        assert_eq!(&*ptr, b"correct horse battery staple....");
+        SecureAllocator.deallocate(block.cast(), layout);
+    }
+    let huge = Layout::from_size_align(1, page * 2).unwrap();
+    assert_eq!(SecureAllocator.allocate(huge), Err(AllocError));
+}
@@ -0,0 +1,250 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use crate::rounded::Rounded;
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// An allocator that forwards to `A`, but rounds the size of every request
+/// up to one of a fixed set of size classes.
+///
+/// This way, `A` sees only a small number of distinct sizes, which reduces
+/// fragmentation in backends like slab or pool allocators. By default,
+/// sizes are rounded up to the next power of two; [`Self::with_classes`]
+/// allows a custom table of sizes.
+///
+/// Blocks are returned with a length of at most the rounded size, so
+/// deallocating with any layout that fits the returned block rounds to the
+/// same size class. Growing or shrinking within a size class doesn’t call
+/// `A` at all.
+#[derive(Clone, Copy, Debug)]
+pub struct SizeClassed<A> {
+    inner: A,
+    classes: Option<&'static [usize]>,
+}
+
+impl<A> SizeClassed<A> {
+    /// Creates a new [`SizeClassed`] that rounds sizes up to the next power
+    /// of two.
+    pub fn new(inner: A) -> Self {
+        Self {
+            inner,
+            classes: None,
+        }
+    }
+
+    /// Creates a new [`SizeClassed`] that rounds sizes up to the next size
+    /// in `classes`.
+    ///
+    /// Requests larger than every size in `classes` are forwarded with their
+    /// size unchanged.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `classes` isn’t sorted in ascending order.
+    pub fn with_classes(inner: A, classes: &'static [usize]) -> Self {
+        assert!(
+            classes.windows(2).all(|w| w[0] < w[1]),
+            "size classes must be sorted",
+        );
+        Self {
+            inner,
+            classes: Some(classes),
+        }
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Returns the size class for `size`, or [`None`] on overflow.
+    fn class(&self, size: usize) -> Option<usize> {
+        match self.classes {
+            None if size == 0 => Some(0),
+            None => size.checked_next_power_of_two(),
+            Some(classes) => Some(
+                classes.iter().copied().find(|&c| c >= size).unwrap_or(size),
+            ),
+        }
+    }
+
+    fn round(&self, layout: Layout) -> Result<Layout, AllocError> {
+        let size = self.class(layout.size()).ok_or(AllocError)?;
+        // Older versions of Rust allow layouts larger than `isize::MAX`,
+        // which no allocator can serve.
+        if size > isize::MAX as usize - (layout.align() - 1) {
+            return Err(AllocError);
+        }
+        Layout::from_size_align(size, layout.align()).map_err(|_| AllocError)
+    }
+
+    fn rounded(
+        &self,
+    ) -> Rounded<'_, A, impl Fn(Layout) -> Result<Layout, AllocError> + '_>
+    {
+        Rounded {
+            inner: &self.inner,
+            round: move |layout| self.round(layout),
+        }
+    }
+}
+
+// SAFETY: This impl forwards to `A`. Every block is allocated and
+// deallocated with its rounded layout. The lengths of returned blocks are
+// limited to the rounded size, so any layout that fits a block rounds to
+// the same size.
+unsafe impl<A: Allocator> Allocator for SizeClassed<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.rounded().allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.rounded().allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.rounded().shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+/// Adds the rounding to `A`’s overhead for the rounded block.
+impl<A: Overhead> Overhead for SizeClassed<A> {
+    fn overhead(&self, layout: Layout) -> usize {
+        self.round(layout).map_or(0, |rounded| {
+            rounded.size() - layout.size() + self.inner.overhead(rounded)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+    use core::cell::Cell;
+    use core::mem::MaybeUninit;
+
+    /// `NonNull<[u8]>::len` needs Rust 1.63.
+    fn len(block: NonNull<[u8]>) -> usize {
+        // SAFETY: Test blocks are valid for their length.
+        unsafe { &*(block.as_ptr() as *const [MaybeUninit<u8>]) }.len()
+    }
+
+    /// Records the layouts it is called with.
+    #[derive(Default)]
+    struct Record {
+        allocated: Cell<Option<Layout>>,
+        deallocated: Cell<Option<Layout>>,
+    }
+
+    unsafe impl Allocator for Record {
+        fn allocate(
+            &self,
+            layout: Layout,
+        ) -> Result<NonNull<[u8]>, AllocError> {
+            self.allocated.set(Some(layout));
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.deallocated.set(Some(layout));
+            unsafe { Global.deallocate(ptr, layout) };
+        }
+    }
+
+    #[test]
+    fn power_of_two() {
+        let alloc = SizeClassed::new(Record::default());
+        let layout = Layout::from_size_align(100, 4).unwrap();
+        let ptr = alloc.allocate(layout).unwrap();
+        assert_eq!(len(ptr), 128);
+        assert_eq!(alloc.inner.allocated.take().unwrap().size(), 128);
+        unsafe {
+            // Growing within the size class happens in place.
+            let large = Layout::from_size_align(120, 4).unwrap();
+            let grown = alloc.grow(ptr.cast(), layout, large).unwrap();
+            assert_eq!(grown, ptr);
+            assert_eq!(alloc.inner.allocated.get(), None);
+            alloc.deallocate(grown.cast(), large);
+        }
+        let dealloc = alloc.inner.deallocated.get().unwrap();
+        assert_eq!(dealloc, Layout::from_size_align(128, 4).unwrap());
+        let huge = isize::MAX as usize / 2 + 2;
+        let huge = Layout::from_size_align(huge, 1).unwrap();
+        assert_eq!(alloc.allocate(huge), Err(AllocError));
+    }
+
+    #[test]
+    fn table() {
+        static CLASSES: [usize; 3] = [16, 48, 80];
+        let alloc = SizeClassed::with_classes(Record::default(), &CLASSES);
+        for &(size, class) in
+            &[(1, 16), (16, 16), (17, 48), (80, 80), (81, 81)]
+        {
+            let layout = Layout::from_size_align(size, 1).unwrap();
+            let ptr = alloc.allocate(layout).unwrap();
+            assert_eq!(len(ptr), class);
+            unsafe { alloc.deallocate(ptr.cast(), layout) };
+            let dealloc = alloc.inner.deallocated.get().unwrap();
+            assert_eq!(dealloc.size(), class);
+        }
+        let small = Layout::new::<[u8; 10]>();
+        let large = Layout::new::<[u8; 40]>();
+        let ptr = alloc.allocate(small).unwrap();
+        unsafe {
+            let ptr = alloc.grow(ptr.cast(), small, large).unwrap();
+            assert_eq!(len(ptr), 48);
+            alloc.deallocate(ptr.cast(), large);
+        }
+    }
+}
@@ -0,0 +1,254 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead, SizeTrackingAllocator};
+use crate::move_block::move_block;
+use alloc::alloc::Layout;
+use core::mem::{align_of, size_of};
+use core::ptr::{self, NonNull};
+
+/// An allocator that forwards to `A`, storing the size of each block in a
+/// header before it, so blocks can be deallocated with
+/// [`SizeTrackingAllocator::deallocate_unsized`].
+///
+/// The header is `max(align, size_of::<usize>())` bytes, and blocks are
+/// aligned to at least a `usize`. Growing or shrinking a block moves it if
+/// its alignment changes.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SizePrefixed<A>(pub A);
+
+impl<A> SizePrefixed<A> {
+    /// Returns the offset of the block within the outer block allocated for
+    /// a layout with the given alignment.
+    fn lead(align: usize) -> usize {
+        align.max(size_of::<usize>())
+    }
+
+    /// Returns the layout of the outer block for `layout`.
+    fn outer(layout: Layout) -> Result<Layout, AllocError> {
+        let size = layout
+            .size()
+            .checked_add(Self::lead(layout.align()))
+            .ok_or(AllocError)?;
+        let align = layout.align().max(align_of::<usize>());
+        Layout::from_size_align(size, align).map_err(|_| AllocError)
+    }
+
+    /// # Safety
+    ///
+    /// `layout` must describe a block currently allocated via this
+    /// allocator.
+    unsafe fn outer_unchecked(layout: Layout) -> Layout {
+        match Self::outer(layout) {
+            Ok(layout) => layout,
+            // SAFETY: `Self::outer` must have succeeded for this layout when
+            // the block was allocated.
+            Err(_) => unsafe { core::hint::unreachable_unchecked() },
+        }
+    }
+
+    /// Writes the header to `outer`, a block from `A` for `layout`,
+    /// returning the block within it.
+    ///
+    /// # Safety
+    ///
+    /// `outer` must be valid for [`Self::outer`] of `layout`.
+    unsafe fn init(outer: NonNull<[u8]>, layout: Layout) -> NonNull<[u8]> {
+        // SAFETY: Checked by caller. The block is aligned to at least a
+        // `usize`, so the header just before it is too.
+        unsafe {
+            let ptr =
+                (outer.as_ptr() as *mut u8).add(Self::lead(layout.align()));
+            (ptr as *mut usize).sub(1).write(layout.size());
+            NonNull::new_unchecked(ptr::slice_from_raw_parts_mut(
+                ptr,
+                layout.size(),
+            ))
+        }
+    }
+
+    /// Returns the start of the outer block for the block at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be a block allocated via this allocator with an alignment
+    /// of `align`.
+    unsafe fn base(ptr: NonNull<u8>, align: usize) -> NonNull<u8> {
+        // SAFETY: Checked by caller.
+        unsafe { NonNull::new_unchecked(ptr.as_ptr().sub(Self::lead(align))) }
+    }
+}
+
+impl<A: Allocator> SizePrefixed<A> {
+    /// # Safety
+    ///
+    /// See [`Allocator::grow`] and [`Allocator::shrink`].
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        grow: bool,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.align() != new_layout.align() {
+            // SAFETY: Checked by caller.
+            return unsafe {
+                move_block(self, ptr, old_layout, new_layout, zeroed)
+            };
+        }
+        // SAFETY: Checked by caller.
+        let (base, old) = unsafe {
+            (
+                Self::base(ptr, old_layout.align()),
+                Self::outer_unchecked(old_layout),
+            )
+        };
+        let new = Self::outer(new_layout)?;
+        // SAFETY: Checked by caller. Both outer layouts have the same
+        // alignment and header size, so `new` is larger (or smaller) than
+        // `old` if `new_layout` is larger (or smaller) than `old_layout`.
+        let outer = unsafe {
+            match (grow, zeroed) {
+                (true, false) => self.0.grow(base, old, new),
+                (true, true) => self.0.grow_zeroed(base, old, new),
+                (false, _) => self.0.shrink(base, old, new),
+            }
+        }?;
+        // SAFETY: `outer` was allocated with `new`.
+        Ok(unsafe { Self::init(outer, new_layout) })
+    }
+}
+
+// SAFETY: This impl forwards to `A`. Each block is placed after a header
+// within an outer block from `A`, which is deallocated with the same outer
+// layout, since that depends only on the layout of the block.
+unsafe impl<A: Allocator> Allocator for SizePrefixed<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let outer = self.0.allocate(Self::outer(layout)?)?;
+        // SAFETY: `outer` was allocated with `Self::outer(layout)`.
+        Ok(unsafe { Self::init(outer, layout) })
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let outer = self.0.allocate_zeroed(Self::outer(layout)?)?;
+        // SAFETY: `outer` was allocated with `Self::outer(layout)`.
+        Ok(unsafe { Self::init(outer, layout) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe {
+            self.0.deallocate(
+                Self::base(ptr, layout.align()),
+                Self::outer_unchecked(layout),
+            );
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.resize(ptr, old_layout, new_layout, true, false) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.resize(ptr, old_layout, new_layout, true, true) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.resize(ptr, old_layout, new_layout, false, false) }
+    }
+}
+
+impl<A: Allocator> SizeTrackingAllocator for SizePrefixed<A> {
+    unsafe fn deallocate_unsized(&self, ptr: NonNull<u8>, align: usize) {
+        // SAFETY: Checked by caller. Every block is preceded by its size,
+        // and the size and alignment made a valid layout when the block was
+        // allocated.
+        unsafe {
+            let size = (ptr.as_ptr() as *const usize).sub(1).read();
+            let layout = Layout::from_size_align_unchecked(size, align);
+            self.deallocate(ptr, layout);
+        }
+    }
+}
+
+/// Adds the header to `A`’s overhead for the outer block.
+impl<A: Overhead> Overhead for SizePrefixed<A> {
+    fn overhead(&self, layout: Layout) -> usize {
+        Self::outer(layout).map_or(0, |outer| {
+            outer.size() - layout.size() + self.0.overhead(outer)
+        })
+    }
+}
+
+#[test]
+fn test() {
+    use crate::{Global, TrackingAllocator};
+
+    let backing = TrackingAllocator::new(Global);
+    let alloc = SizePrefixed(&backing);
+    let layouts = [
+        Layout::new::<u8>(),
+        Layout::new::<[u64; 5]>(),
+        Layout::from_size_align(100, 64).unwrap(),
+    ];
+    for &layout in &layouts {
+        let ptr = alloc.allocate(layout).unwrap().cast::<u8>();
+        assert_eq!(ptr.as_ptr() as usize % layout.align(), 0);
+        unsafe {
+            ptr.as_ptr().write_bytes(0xaa, layout.size());
+            alloc.deallocate_unsized(ptr, layout.align());
+        }
+    }
+
+    let small = Layout::new::<[u8; 10]>();
+    let large = Layout::new::<[u8; 1000]>();
+    let ptr = alloc.allocate(small).unwrap().cast::<u8>();
+    unsafe {
+        ptr.as_ptr().write_bytes(1, 10);
+        let ptr = alloc.grow_zeroed(ptr, small, large).unwrap().cast::<u8>();
+        let bytes = &*ptr::slice_from_raw_parts(ptr.as_ptr(), 1000);
+        assert!(bytes[..10].iter().all(|&b| b == 1));
+        assert!(bytes[10..].iter().all(|&b| b == 0));
+        alloc.deallocate_unsized(ptr, 1);
+    }
+    let stats = backing.stats();
+    assert_eq!(stats.current, 0);
+    assert_eq!(stats.allocations, stats.deallocations);
+}
@@ -0,0 +1,49 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::Allocator;
+use core::ptr::NonNull;
+
+/// An [`Allocator`] that can determine the size of its blocks, so they can
+/// be deallocated given only a pointer and an alignment.
+///
+/// This bridges to APIs, like many C interfaces, that pass only a pointer
+/// when freeing memory. Not every allocator can support this, so it is a
+/// separate trait rather than a method with a default implementation.
+/// [`SizePrefixed`](crate::SizePrefixed) adds support to any allocator by
+/// storing the size of each block in a header.
+pub trait SizeTrackingAllocator: Allocator {
+    /// Deallocates the block at `ptr`, which was allocated with an alignment
+    /// of `align`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must denote a block of memory currently allocated via this
+    /// allocator, with a layout whose alignment is `align`.
+    unsafe fn deallocate_unsized(&self, ptr: NonNull<u8>, align: usize);
+}
+
+impl<A> SizeTrackingAllocator for &A
+where
+    A: SizeTrackingAllocator + ?Sized,
+{
+    unsafe fn deallocate_unsized(&self, ptr: NonNull<u8>, align: usize) {
+        // SAFETY: Checked by caller.
+        unsafe { A::deallocate_unsized(*self, ptr, align) };
+    }
+}
@@ -0,0 +1,194 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::fmt::{self, Debug, Formatter};
+use core::hint;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// An allocator that forwards to `A` while holding a spinlock, which makes
+/// it [`Sync`] even if `A` isn’t.
+///
+/// This works without [`std`], but because waiting threads spin rather than
+/// sleep, it is suitable only when the critical sections (i.e., the
+/// operations of `A`) are short. Spinlocks are also prone to priority
+/// inversion: if a thread holding the lock is preempted by a higher-priority
+/// thread that then tries to allocate, the latter may spin indefinitely.
+///
+/// [`std`]: https://doc.rust-lang.org/std/
+pub struct SpinLocked<A> {
+    locked: AtomicBool,
+    inner: UnsafeCell<A>,
+}
+
+impl<A> SpinLocked<A> {
+    /// Creates a new spinlock-guarded allocator.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            inner: UnsafeCell::new(inner),
+        }
+    }
+
+    /// Returns the wrapped allocator.
+    pub fn into_inner(self) -> A {
+        self.inner.into_inner()
+    }
+
+    fn lock(&self) -> Guard<'_, A> {
+        while self
+            .locked
+            .compare_exchange_weak(
+                false,
+                true,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            )
+            .is_err()
+        {
+            while self.locked.load(Ordering::Relaxed) {
+                hint::spin_loop();
+            }
+        }
+        Guard(self)
+    }
+}
+
+impl<A: Default> Default for SpinLocked<A> {
+    fn default() -> Self {
+        Self::new(A::default())
+    }
+}
+
+impl<A> Debug for SpinLocked<A> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SpinLocked").finish()
+    }
+}
+
+// SAFETY: Access to `A` is serialized by the lock, so only `A: Send` is
+// required.
+unsafe impl<A: Send> Sync for SpinLocked<A> {}
+
+/// Releases the lock when dropped.
+struct Guard<'a, A>(&'a SpinLocked<A>);
+
+impl<A> Guard<'_, A> {
+    fn get(&self) -> &A {
+        // SAFETY: The lock is held, so no other thread can access the inner
+        // allocator.
+        unsafe { &*self.0.inner.get() }
+    }
+}
+
+impl<A> Drop for Guard<'_, A> {
+    fn drop(&mut self) {
+        self.0.locked.store(false, Ordering::Release);
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for SpinLocked<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.lock().get().allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.lock().get().allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.lock().get().deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.lock().get().grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.lock().get().grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { self.lock().get().shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test() {
+    use crate::RingAllocator;
+    use core::mem::MaybeUninit;
+    use std::sync::Arc;
+    use std::thread;
+
+    // `thread::scope` needs Rust 1.63, so the buffer must be `'static`.
+    let buf = Box::leak(Box::new([MaybeUninit::uninit(); 1024]));
+    let alloc = Arc::new(SpinLocked::new(RingAllocator::new(buf)));
+    let layout = Layout::new::<[usize; 4]>();
+    let threads: Vec<_> = (0..4)
+        .map(|t| {
+            let alloc = alloc.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    let value = t * 1000 + i;
+                    let ptr = loop {
+                        if let Ok(ptr) = alloc.allocate(layout) {
+                            break ptr.cast::<[usize; 4]>();
+                        }
+                        thread::yield_now();
+                    };
+                    unsafe {
+                        ptr.as_ptr().write([value; 4]);
+                        thread::yield_now();
+                        assert_eq!(*ptr.as_ptr(), [value; 4]);
+                        alloc.deallocate(ptr.cast(), layout);
+                    }
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+}
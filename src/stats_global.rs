@@ -0,0 +1,121 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Global, Stats, TrackingAllocator};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+static TRACKER: TrackingAllocator<Global> = TrackingAllocator::new(Global);
+
+/// An allocator that forwards to [`Global`], recording process-wide
+/// statistics shared by all instances of this type.
+///
+/// This gives coarse visibility into memory usage where installing a
+/// `#[global_allocator]` isn’t possible, such as in a `cdylib` loaded by a
+/// host runtime. Only allocations made through this type are counted.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord,
+)]
+pub struct StatsGlobal;
+
+impl StatsGlobal {
+    /// Returns the statistics for all allocations made through this type.
+    pub fn global_stats() -> Stats {
+        TRACKER.stats()
+    }
+
+    /// Returns the total size in bytes of live blocks allocated through this
+    /// type.
+    pub fn global_current() -> usize {
+        TRACKER.stats().current
+    }
+
+    /// Returns the largest value of [`Self::global_current`] so far.
+    pub fn global_peak() -> usize {
+        TRACKER.stats().peak
+    }
+}
+
+// SAFETY: This impl forwards to `Global`.
+unsafe impl Allocator for StatsGlobal {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        TRACKER.allocate(layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        TRACKER.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { TRACKER.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { TRACKER.grow(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { TRACKER.grow_zeroed(ptr, old_layout, new_layout) }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        unsafe { TRACKER.shrink(ptr, old_layout, new_layout) }
+    }
+}
+
+#[test]
+fn test() {
+    let layout = Layout::new::<[u8; 32]>();
+    let (a, b) = (StatsGlobal, StatsGlobal);
+    let x = a.allocate(layout).unwrap();
+    let y = b.allocate(layout).unwrap();
+    assert_eq!(StatsGlobal::global_current(), 64);
+    unsafe { a.deallocate(y.cast(), layout) };
+    assert_eq!(StatsGlobal::global_current(), 32);
+    let z = StatsGlobal.allocate(layout).unwrap();
+    unsafe {
+        b.deallocate(x.cast(), layout);
+        StatsGlobal.deallocate(z.cast(), layout);
+    }
+    assert_eq!(StatsGlobal::global_current(), 0);
+    assert_eq!(StatsGlobal::global_peak(), 64);
+    assert_eq!(StatsGlobal::global_stats().allocations, 3);
+    assert_eq!(StatsGlobal::global_stats().deallocations, 3);
+}
@@ -0,0 +1,223 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator};
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+
+/// Emits a trace-level event for an allocator operation.
+macro_rules! event {
+    ($op:literal, $layout:expr, $ptr:expr) => {
+        tracing::trace!(
+            op = $op,
+            size = $layout.size(),
+            align = $layout.align(),
+            ptr = ?$ptr,
+        )
+    };
+}
+
+/// Returns the start of a successfully allocated block.
+fn start(result: &Result<NonNull<[u8]>, AllocError>) -> Option<*mut u8> {
+    result.as_ref().ok().map(|ptr| ptr.as_ptr() as *mut u8)
+}
+
+/// An allocator that forwards to `A`, emitting a [`tracing`] event for each
+/// operation.
+///
+/// Events are emitted at the trace level, with the fields `op`, `size`,
+/// `align`, and `ptr` (which is [`None`] if the operation failed). For
+/// [`grow`](Allocator::grow) and [`shrink`](Allocator::shrink), `size` and
+/// `align` describe the new layout, and `old_ptr` and `old_size` describe
+/// the old block.
+///
+/// When trace-level events are disabled, the overhead is a check of
+/// [`tracing`]’s cached per-callsite interest.
+#[cfg_attr(feature = "doc_cfg", doc(cfg(feature = "tracing")))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TracedAllocator<A>(pub A);
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for TracedAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let result = self.0.allocate(layout);
+        event!("allocate", layout, start(&result));
+        result
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let result = self.0.allocate_zeroed(layout);
+        event!("allocate_zeroed", layout, start(&result));
+        result
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        event!("deallocate", layout, Some(ptr.as_ptr()));
+        // SAFETY: Checked by caller.
+        unsafe { self.0.deallocate(ptr, layout) };
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let result = unsafe { self.0.grow(ptr, old_layout, new_layout) };
+        tracing::trace!(
+            op = "grow",
+            size = new_layout.size(),
+            align = new_layout.align(),
+            ptr = ?start(&result),
+            old_ptr = ?ptr.as_ptr(),
+            old_size = old_layout.size(),
+        );
+        result
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let result =
+            unsafe { self.0.grow_zeroed(ptr, old_layout, new_layout) };
+        tracing::trace!(
+            op = "grow_zeroed",
+            size = new_layout.size(),
+            align = new_layout.align(),
+            ptr = ?start(&result),
+            old_ptr = ?ptr.as_ptr(),
+            old_size = old_layout.size(),
+        );
+        result
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let result = unsafe { self.0.shrink(ptr, old_layout, new_layout) };
+        tracing::trace!(
+            op = "shrink",
+            size = new_layout.size(),
+            align = new_layout.align(),
+            ptr = ?start(&result),
+            old_ptr = ?ptr.as_ptr(),
+            old_size = old_layout.size(),
+        );
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+    use alloc::string::{String, ToString};
+    use alloc::vec::Vec;
+    use core::fmt::Debug;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records the `op`, `size`, and `align` fields of every event.
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<(String, u64, u64)>>>);
+
+    #[derive(Default)]
+    struct Fields(String, u64, u64);
+
+    impl Visit for Fields {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            if field.name() == "op" {
+                self.0 = value.to_string();
+            }
+        }
+
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            match field.name() {
+                "size" => self.1 = value,
+                "align" => self.2 = value,
+                _ => {}
+            }
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn Debug) {}
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut fields = Fields::default();
+            event.record(&mut fields);
+            let Fields(op, size, align) = fields;
+            self.0.lock().unwrap().push((op, size, align));
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn events() {
+        let recorder = Recorder::default();
+        let events = recorder.0.clone();
+        tracing::subscriber::with_default(recorder, || {
+            let alloc = TracedAllocator(Global);
+            let small = Layout::from_size_align(8, 4).unwrap();
+            let large = Layout::from_size_align(32, 4).unwrap();
+            let ptr = alloc.allocate(small).unwrap();
+            unsafe {
+                let ptr = alloc.grow(ptr.cast(), small, large).unwrap();
+                alloc.deallocate(ptr.cast(), large);
+            }
+        });
+        let events = events.lock().unwrap();
+        assert_eq!(
+            *events,
+            [
+                ("allocate".to_string(), 8, 4),
+                ("grow".to_string(), 32, 4),
+                ("deallocate".to_string(), 32, 4),
+            ]
+        );
+    }
+}
@@ -0,0 +1,322 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+use super::{AllocError, Allocator, Overhead};
+use alloc::alloc::Layout;
+use core::fmt::{self, Display, Formatter};
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Statistics about the allocations made through a [`TrackingAllocator`].
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// The total size in bytes of live blocks.
+    pub current: usize,
+    /// The largest value of [`Self::current`] in the reporting period.
+    pub peak: usize,
+    /// The number of allocations.
+    pub allocations: usize,
+    /// The number of deallocations.
+    pub deallocations: usize,
+}
+
+/// Formats as a one-line summary, like
+/// `current: 1.2 MiB, peak: 3.4 MiB, allocs: 1532, deallocs: 1498`.
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "current: {}, peak: {}, allocs: {}, deallocs: {}",
+            ByteCount(self.current),
+            ByteCount(self.peak),
+            self.allocations,
+            self.deallocations,
+        )
+    }
+}
+
+/// A number of bytes, which is displayed in binary units.
+///
+/// Counts below 1 KiB are displayed exactly, like `512 B`; larger ones are
+/// rounded to a tenth of the largest unit that fits, like `1.5 KiB` or
+/// `3.4 MiB`.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+pub struct ByteCount(pub usize);
+
+impl Display for ByteCount {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        const UNITS: [&str; 6] = ["KiB", "MiB", "GiB", "TiB", "PiB", "EiB"];
+        let bytes = self.0 as u128;
+        if bytes < 1024 {
+            return write!(f, "{} B", bytes);
+        }
+        // Rounds `bytes` to the nearest tenth of the unit `1 << shift`.
+        let tenths = |shift: u32| (bytes * 10 + (1 << shift) / 2) >> shift;
+        let mut unit = 0;
+        // Rounding can carry into the next unit, as with 1023.99 KiB.
+        while unit + 1 < UNITS.len() && tenths(10 * (unit as u32 + 1)) >= 10240
+        {
+            unit += 1;
+        }
+        let tenths = tenths(10 * (unit as u32 + 1));
+        write!(f, "{}.{} {}", tenths / 10, tenths % 10, UNITS[unit])
+    }
+}
+
+/// An allocator that forwards to `A`, counting allocations and live bytes.
+///
+/// Sizes are counted according to the layouts requested, not the lengths of
+/// the blocks returned by `A`. Growing or shrinking a block adjusts the byte
+/// counts but isn’t counted as an allocation.
+#[derive(Debug, Default)]
+pub struct TrackingAllocator<A> {
+    inner: A,
+    current: AtomicUsize,
+    peak: AtomicUsize,
+    allocations: AtomicUsize,
+    deallocations: AtomicUsize,
+}
+
+impl<A> TrackingAllocator<A> {
+    /// Creates a new [`TrackingAllocator`] that forwards to `inner`.
+    ///
+    /// This is a `const fn`, so it can initialize a `static`.
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            current: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            deallocations: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the statistics since this allocator was created, or since
+    /// the last call to [`Self::take_stats`].
+    pub fn stats(&self) -> Stats {
+        Stats {
+            current: self.current.load(Ordering::Relaxed),
+            peak: self.peak.load(Ordering::Relaxed),
+            allocations: self.allocations.load(Ordering::Relaxed),
+            deallocations: self.deallocations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Like [`Self::stats`], but also resets the counters, so the next call
+    /// reports only what happened in between.
+    ///
+    /// This is useful for periodic sampling. [`Stats::current`] isn’t reset,
+    /// as it describes the live blocks rather than a period, and
+    /// [`Stats::peak`] is reset to the current number of live bytes rather
+    /// than zero. Each counter is reset atomically, so no allocation is
+    /// missed or counted twice, even if other threads are allocating.
+    pub fn take_stats(&self) -> Stats {
+        let current = self.current.load(Ordering::Relaxed);
+        Stats {
+            current,
+            peak: self.peak.swap(current, Ordering::Relaxed).max(current),
+            allocations: self.allocations.swap(0, Ordering::Relaxed),
+            deallocations: self.deallocations.swap(0, Ordering::Relaxed),
+        }
+    }
+
+    /// Returns the underlying allocator.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn add(&self, size: usize) {
+        let current = self.current.fetch_add(size, Ordering::Relaxed) + size;
+        self.peak.fetch_max(current, Ordering::Relaxed);
+    }
+
+    fn sub(&self, size: usize) {
+        self.current.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    fn record_allocation(
+        &self,
+        result: Result<NonNull<[u8]>, AllocError>,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if result.is_ok() {
+            self.allocations.fetch_add(1, Ordering::Relaxed);
+            self.add(layout.size());
+        }
+        result
+    }
+}
+
+// SAFETY: This impl forwards to `A`.
+unsafe impl<A: Allocator> Allocator for TrackingAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.record_allocation(self.inner.allocate(layout), layout)
+    }
+
+    fn allocate_zeroed(
+        &self,
+        layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.record_allocation(self.inner.allocate_zeroed(layout), layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // SAFETY: Checked by caller.
+        unsafe { self.inner.deallocate(ptr, layout) };
+        self.deallocations.fetch_add(1, Ordering::Relaxed);
+        self.sub(layout.size());
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let new = unsafe { self.inner.grow(ptr, old_layout, new_layout) }?;
+        self.add(new_layout.size() - old_layout.size());
+        Ok(new)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let new =
+            unsafe { self.inner.grow_zeroed(ptr, old_layout, new_layout) }?;
+        self.add(new_layout.size() - old_layout.size());
+        Ok(new)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // SAFETY: Checked by caller.
+        let new = unsafe { self.inner.shrink(ptr, old_layout, new_layout) }?;
+        self.sub(old_layout.size() - new_layout.size());
+        Ok(new)
+    }
+}
+
+impl<A: Overhead> Overhead for TrackingAllocator<A> {
+    fn overhead(&self, layout: Layout) -> usize {
+        self.inner.overhead(layout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Global;
+
+    #[test]
+    fn stats() {
+        let alloc = TrackingAllocator::new(Global);
+        let small = Layout::new::<[u8; 16]>();
+        let large = Layout::new::<[u8; 64]>();
+        let a = alloc.allocate(small).unwrap();
+        let b = alloc.allocate(small).unwrap();
+        unsafe {
+            let b = alloc.grow(b.cast(), small, large).unwrap();
+            alloc.deallocate(a.cast(), small);
+            assert_eq!(
+                alloc.stats(),
+                Stats {
+                    current: 64,
+                    peak: 80,
+                    allocations: 2,
+                    deallocations: 1,
+                }
+            );
+            alloc.deallocate(b.cast(), large);
+        }
+        assert_eq!(alloc.stats().current, 0);
+    }
+
+    #[test]
+    fn display() {
+        use alloc::string::ToString;
+        let cases = [
+            (0, "0 B"),
+            (1023, "1023 B"),
+            (1024, "1.0 KiB"),
+            (1536, "1.5 KiB"),
+            ((1 << 20) - 1, "1.0 MiB"),
+            (1 << 20, "1.0 MiB"),
+            (3_565_158, "3.4 MiB"),
+            ((1 << 30) - (1 << 20), "1023.0 MiB"),
+            ((1 << 30) - 1, "1.0 GiB"),
+            (1 << 30, "1.0 GiB"),
+        ];
+        for &(bytes, s) in &cases {
+            assert_eq!(ByteCount(bytes).to_string(), s);
+        }
+        let stats = Stats {
+            current: 1_258_291,
+            peak: 3_565_158,
+            allocations: 1532,
+            deallocations: 1498,
+        };
+        assert_eq!(
+            stats.to_string(),
+            "current: 1.2 MiB, peak: 3.4 MiB, allocs: 1532, deallocs: 1498",
+        );
+    }
+
+    #[test]
+    fn take_stats() {
+        let alloc = TrackingAllocator::new(Global);
+        let layout = Layout::new::<[u8; 32]>();
+        let a = alloc.allocate(layout).unwrap();
+        let b = alloc.allocate(layout).unwrap();
+        unsafe { alloc.deallocate(b.cast(), layout) };
+        assert_eq!(
+            alloc.take_stats(),
+            Stats {
+                current: 32,
+                peak: 64,
+                allocations: 2,
+                deallocations: 1,
+            }
+        );
+
+        // The second read reports only what happened since the first, and
+        // the peak starts from the bytes that were live at that point.
+        unsafe { alloc.deallocate(a.cast(), layout) };
+        assert_eq!(
+            alloc.take_stats(),
+            Stats {
+                current: 0,
+                peak: 32,
+                allocations: 0,
+                deallocations: 1,
+            }
+        );
+        assert_eq!(alloc.take_stats(), Stats::default());
+    }
+}
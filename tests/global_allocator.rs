@@ -0,0 +1,78 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Checks that [`Global`] uses the `#[global_allocator]` installed by the
+//! binary.
+
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+use allocator_fallback::{Allocator, Global};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// An unusual size, so that allocations made by the test harness aren't
+/// counted.
+const SIZE: usize = 12345;
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+static ZEROED: AtomicBool = AtomicBool::new(false);
+
+struct Counting;
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == SIZE {
+            COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == SIZE {
+            ZEROED.store(true, Ordering::Relaxed);
+        }
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[test]
+fn global_uses_global_allocator() {
+    let layout = Layout::from_size_align(SIZE, 8).unwrap();
+    for i in 1..=3 {
+        let ptr = Global.allocate(layout).unwrap();
+        assert_eq!(COUNT.load(Ordering::Relaxed), i);
+        unsafe { Global.deallocate(ptr.cast(), layout) };
+    }
+}
+
+#[test]
+fn global_uses_alloc_zeroed() {
+    let layout = Layout::from_size_align(SIZE, 8).unwrap();
+    let ptr = Global.allocate_zeroed(layout).unwrap();
+    assert!(ZEROED.load(Ordering::Relaxed));
+    let bytes = unsafe { &*(ptr.as_ptr() as *const [u8]) };
+    assert!(bytes.iter().all(|&b| b == 0));
+    unsafe { Global.deallocate(ptr.cast(), layout) };
+}
@@ -0,0 +1,87 @@
+/*
+ * Copyright 2022 taylor.fish <contact@taylor.fish>
+ *
+ * This file is part of allocator-fallback.
+ *
+ * allocator-fallback is licensed under the Apache License, Version 2.0
+ * (the "License"); you may not use allocator-fallback except in compliance
+ * with the License. You may obtain a copy of the License at
+ *
+ *     http://www.apache.org/licenses/LICENSE-2.0
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Exercises the core allocator paths using only `core` and `alloc`.
+//!
+//! This test crate is `no_std`, so it can’t accidentally depend on anything
+//! from `std`. The test harness itself still links `std`, but running these
+//! tests with `--no-default-features` checks that the crate’s fundamental
+//! behavior works when it’s built without `std`.
+
+#![no_std]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use allocator_fallback::{AllocError, Allocator, Global};
+use core::ptr::NonNull;
+
+fn bytes<'a>(ptr: NonNull<[u8]>) -> &'a mut [u8] {
+    unsafe { &mut *ptr.as_ptr() }
+}
+
+/// Allocates from `Global`, but overrides only the required methods, so
+/// the trait’s default methods are used for everything else.
+struct Minimal;
+
+unsafe impl Allocator for Minimal {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        Global.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { Global.deallocate(ptr, layout) }
+    }
+}
+
+fn check<A: Allocator>(alloc: A) {
+    let small = Layout::from_size_align(8, 8).unwrap();
+    let large = Layout::from_size_align(32, 8).unwrap();
+
+    let ptr = alloc.allocate_zeroed(small).unwrap();
+    assert_eq!(ptr.as_ptr() as *mut u8 as usize % 8, 0);
+    assert!(bytes(ptr).iter().all(|&b| b == 0));
+    bytes(ptr).copy_from_slice(b"abcdefgh");
+
+    let ptr = unsafe { alloc.grow(ptr.cast(), small, large) }.unwrap();
+    assert_eq!(&bytes(ptr)[..8], b"abcdefgh");
+    let ptr = unsafe { alloc.shrink(ptr.cast(), large, small) }.unwrap();
+    assert_eq!(&bytes(ptr)[..8], b"abcdefgh");
+    let ptr = unsafe { alloc.grow_zeroed(ptr.cast(), small, large) }.unwrap();
+    assert_eq!(&bytes(ptr)[..8], b"abcdefgh");
+    assert!(bytes(ptr)[8..32].iter().all(|&b| b == 0));
+    unsafe { alloc.deallocate(ptr.cast(), large) };
+}
+
+#[test]
+fn global() {
+    check(Global);
+}
+
+#[test]
+fn by_ref() {
+    check::<&Global>(&Global);
+    check(Global.by_ref());
+}
+
+#[test]
+fn default_methods() {
+    check(Minimal);
+    check::<&Minimal>(&Minimal);
+}